@@ -0,0 +1,9 @@
+-- =============================================
+-- Banter — Message deletion tombstones (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 001_initial.sql
+-- =============================================
+
+-- Deleted messages keep their row (so pagination cursors and references stay
+-- valid) but have their content wiped and `deleted_at` set.
+ALTER TABLE messages    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+ALTER TABLE dm_messages ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
@@ -0,0 +1,87 @@
+-- =============================================
+-- Banter — Custom roles & permission bitfields (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 002_message_tombstones.sql
+--
+-- Replaces the fixed owner/admin/member `member_role` enum. Ownership now
+-- comes from servers.owner_id; everything else from role permissions.
+-- Permission bit values are defined in src/permissions.rs.
+-- =============================================
+
+-- =============================================
+-- ROLES
+-- =============================================
+CREATE TABLE IF NOT EXISTS roles (
+    id          UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    server_id   UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    name        VARCHAR(100) NOT NULL,
+    color       VARCHAR(64),
+    position    INT NOT NULL DEFAULT 0,
+    permissions BIGINT NOT NULL DEFAULT 0,
+    -- The implicit @everyone role every member has (exactly one per server)
+    is_default  BOOLEAN NOT NULL DEFAULT false,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_roles_server ON roles(server_id, position);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_roles_server_default ON roles(server_id) WHERE is_default;
+
+-- =============================================
+-- MEMBER ROLES (multi-role membership)
+-- =============================================
+CREATE TABLE IF NOT EXISTS member_roles (
+    server_id   UUID NOT NULL,
+    user_id     UUID NOT NULL,
+    role_id     UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (server_id, user_id, role_id),
+    FOREIGN KEY (server_id, user_id) REFERENCES server_members(server_id, user_id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_member_roles_role ON member_roles(role_id);
+
+-- =============================================
+-- BACKFILL
+-- =============================================
+
+-- Every server gets an @everyone role:
+-- VIEW_CHANNEL | SEND_MESSAGES | READ_MESSAGE_HISTORY | CONNECT | SPEAK
+INSERT INTO roles (server_id, name, position, permissions, is_default)
+SELECT s.id, '@everyone', 0, 2 | 4 | 8 | 128 | 256, true
+FROM servers s
+WHERE NOT EXISTS (SELECT 1 FROM roles r WHERE r.server_id = s.id AND r.is_default);
+
+-- Former 'admin' members move to an "Admin" role with ADMINISTRATOR
+DO $$ BEGIN
+  IF EXISTS (
+    SELECT 1 FROM information_schema.columns
+    WHERE table_name = 'server_members' AND column_name = 'role'
+  ) THEN
+    INSERT INTO roles (server_id, name, position, permissions)
+    SELECT DISTINCT sm.server_id, 'Admin', 1, 1
+    FROM server_members sm
+    WHERE sm.role = 'admin';
+
+    INSERT INTO member_roles (server_id, user_id, role_id)
+    SELECT sm.server_id, sm.user_id, r.id
+    FROM server_members sm
+    INNER JOIN roles r ON r.server_id = sm.server_id AND r.name = 'Admin' AND NOT r.is_default
+    WHERE sm.role = 'admin'
+    ON CONFLICT DO NOTHING;
+
+    ALTER TABLE server_members DROP COLUMN role;
+  END IF;
+END $$;
+
+DROP TYPE IF EXISTS member_role;
+
+-- =============================================
+-- ROW LEVEL SECURITY
+-- =============================================
+ALTER TABLE roles ENABLE ROW LEVEL SECURITY;
+ALTER TABLE member_roles ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_roles') THEN
+    CREATE POLICY "service_all_roles" ON roles FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_member_roles') THEN
+    CREATE POLICY "service_all_member_roles" ON member_roles FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,30 @@
+-- =============================================
+-- Banter — Per-channel permission overwrites (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 003_roles.sql
+-- =============================================
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'overwrite_kind') THEN
+    CREATE TYPE overwrite_kind AS ENUM ('role', 'member');
+  END IF;
+END $$;
+
+-- Allow/deny bits layered on top of server-level role permissions.
+-- `target_id` is a roles.id (kind = 'role') or a profiles.id (kind = 'member').
+CREATE TABLE IF NOT EXISTS channel_overwrites (
+    channel_id  UUID NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    target_id   UUID NOT NULL,
+    kind        overwrite_kind NOT NULL,
+    allow       BIGINT NOT NULL DEFAULT 0,
+    deny        BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (channel_id, target_id)
+);
+CREATE INDEX IF NOT EXISTS idx_channel_overwrites_target ON channel_overwrites(target_id);
+
+ALTER TABLE channel_overwrites ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_channel_overwrites') THEN
+    CREATE POLICY "service_all_channel_overwrites" ON channel_overwrites FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,29 @@
+-- =============================================
+-- Banter — Server invite links (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 004_channel_overwrites.sql
+-- =============================================
+
+CREATE TABLE IF NOT EXISTS invites (
+    code        VARCHAR(16) PRIMARY KEY,
+    server_id   UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    channel_id  UUID REFERENCES channels(id) ON DELETE CASCADE,
+    inviter_id  UUID REFERENCES profiles(id) ON DELETE SET NULL,
+    max_uses    INT,            -- NULL = unlimited
+    uses        INT NOT NULL DEFAULT 0,
+    expires_at  TIMESTAMPTZ,    -- NULL = never
+    -- Members who join through a temporary invite are removed when they
+    -- disconnect, unless they've been given a role in the meantime
+    temporary   BOOLEAN NOT NULL DEFAULT false,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_invites_server ON invites(server_id);
+
+ALTER TABLE server_members ADD COLUMN IF NOT EXISTS temporary BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE invites ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_invites') THEN
+    CREATE POLICY "service_all_invites" ON invites FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,27 @@
+-- =============================================
+-- Banter — Kick, ban & timeout moderation (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 005_invites.sql
+-- =============================================
+
+CREATE TABLE IF NOT EXISTS server_bans (
+    server_id   UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    user_id     UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    reason      VARCHAR(512),
+    banned_by   UUID REFERENCES profiles(id) ON DELETE SET NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (server_id, user_id)
+);
+
+-- Timed-out members keep only VIEW_CHANNEL and READ_MESSAGE_HISTORY until then
+ALTER TABLE server_members ADD COLUMN IF NOT EXISTS timed_out_until TIMESTAMPTZ;
+
+-- Ban with message cleanup looks up a user's recent messages
+CREATE INDEX IF NOT EXISTS idx_messages_author_created ON messages(author_id, created_at DESC);
+
+ALTER TABLE server_bans ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_server_bans') THEN
+    CREATE POLICY "service_all_server_bans" ON server_bans FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
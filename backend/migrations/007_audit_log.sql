@@ -0,0 +1,52 @@
+-- =============================================
+-- Banter — Server audit log (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 006_moderation.sql
+-- =============================================
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'audit_action') THEN
+    CREATE TYPE audit_action AS ENUM (
+      'member_join',
+      'member_leave',
+      'member_kick',
+      'member_ban_add',
+      'member_ban_remove',
+      'member_update',
+      'member_role_add',
+      'member_role_remove',
+      'channel_create',
+      'channel_overwrite_update',
+      'channel_overwrite_delete',
+      'role_create',
+      'role_update',
+      'role_delete',
+      'invite_create',
+      'invite_delete',
+      'message_delete',
+      'message_bulk_delete'
+    );
+  END IF;
+END $$;
+
+-- `target_id` points at whatever `action` acted on (member, channel, role,
+-- message…); invites have no UUID, so their code goes into `changes`.
+-- `changes` is a JSON array of { key, old_value?, new_value? }.
+CREATE TABLE IF NOT EXISTS audit_log_entries (
+    id          UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    server_id   UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    actor_id    UUID REFERENCES profiles(id) ON DELETE SET NULL,
+    action      audit_action NOT NULL,
+    target_id   UUID,
+    changes     JSONB NOT NULL DEFAULT '[]',
+    reason      VARCHAR(512),
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_server_created ON audit_log_entries(server_id, created_at DESC);
+
+ALTER TABLE audit_log_entries ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_audit_log_entries') THEN
+    CREATE POLICY "service_all_audit_log_entries" ON audit_log_entries FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,43 @@
+-- =============================================
+-- Banter — Reply references & threads (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 007_audit_log.sql
+-- =============================================
+
+-- Replies keep working (without a preview) if the referenced row is purged
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS reply_to_id UUID REFERENCES messages(id) ON DELETE SET NULL;
+
+-- A thread is a text channel whose `parent_id` is the channel it was started
+-- in; it shares the parent's permission overwrites.
+ALTER TABLE channels ADD COLUMN IF NOT EXISTS parent_id UUID REFERENCES channels(id) ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS idx_channels_parent ON channels(parent_id) WHERE parent_id IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS threads (
+    channel_id           UUID PRIMARY KEY REFERENCES channels(id) ON DELETE CASCADE,
+    source_message_id    UUID UNIQUE REFERENCES messages(id) ON DELETE SET NULL,
+    owner_id             UUID REFERENCES profiles(id) ON DELETE SET NULL,
+    archived             BOOLEAN NOT NULL DEFAULT false,
+    archived_at          TIMESTAMPTZ,
+    -- Archived automatically after this many minutes without a new message
+    auto_archive_minutes INT NOT NULL DEFAULT 1440,
+    last_message_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_threads_active ON threads(last_message_at) WHERE NOT archived;
+
+CREATE TABLE IF NOT EXISTS thread_members (
+    thread_id   UUID NOT NULL REFERENCES threads(channel_id) ON DELETE CASCADE,
+    user_id     UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    joined_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (thread_id, user_id)
+);
+
+ALTER TABLE threads ENABLE ROW LEVEL SECURITY;
+ALTER TABLE thread_members ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_threads') THEN
+    CREATE POLICY "service_all_threads" ON threads FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_thread_members') THEN
+    CREATE POLICY "service_all_thread_members" ON thread_members FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,34 @@
+-- =============================================
+-- Banter — Message reactions (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 008_threads.sql
+--
+-- `emoji` is either a unicode emoji or a custom emoji as `name:id`.
+-- =============================================
+
+CREATE TABLE IF NOT EXISTS message_reactions (
+    message_id  UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id     UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    emoji       VARCHAR(100) NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, emoji, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS dm_message_reactions (
+    message_id  UUID NOT NULL REFERENCES dm_messages(id) ON DELETE CASCADE,
+    user_id     UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    emoji       VARCHAR(100) NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, emoji, user_id)
+);
+
+ALTER TABLE message_reactions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE dm_message_reactions ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_message_reactions') THEN
+    CREATE POLICY "service_all_message_reactions" ON message_reactions FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_dm_message_reactions') THEN
+    CREATE POLICY "service_all_dm_message_reactions" ON dm_message_reactions FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,37 @@
+-- =============================================
+-- Banter — Message attachments (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 009_reactions.sql
+--
+-- Files are uploaded to a channel or DM first and claimed by the message
+-- they're sent with; unclaimed uploads are swept after a day.
+-- =============================================
+
+CREATE TABLE IF NOT EXISTS attachments (
+    id              UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    uploader_id     UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    channel_id      UUID REFERENCES channels(id) ON DELETE CASCADE,
+    dm_channel_id   UUID REFERENCES dm_channels(id) ON DELETE CASCADE,
+    message_id      UUID REFERENCES messages(id) ON DELETE CASCADE,
+    dm_message_id   UUID REFERENCES dm_messages(id) ON DELETE CASCADE,
+    storage_key     TEXT NOT NULL UNIQUE,
+    filename        VARCHAR(255) NOT NULL,
+    content_type    VARCHAR(127) NOT NULL,
+    size            BIGINT NOT NULL,
+    width           INTEGER,
+    height          INTEGER,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT attachments_one_channel CHECK ((channel_id IS NULL) <> (dm_channel_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachments_message ON attachments(message_id) WHERE message_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_attachments_dm_message ON attachments(dm_message_id) WHERE dm_message_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_attachments_unclaimed ON attachments(created_at)
+    WHERE message_id IS NULL AND dm_message_id IS NULL;
+
+ALTER TABLE attachments ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_attachments') THEN
+    CREATE POLICY "service_all_attachments" ON attachments FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,14 @@
+-- =============================================
+-- Banter — Uploaded avatars, banners and server icons (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 010_attachments.sql
+--
+-- Uploaded images live in storage under content-addressed paths; these
+-- columns hold the URL of each image's default variant. `servers.icon`
+-- stays as the emoji shown when no icon is uploaded.
+-- =============================================
+
+ALTER TABLE profiles ADD COLUMN IF NOT EXISTS banner_url TEXT;
+
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS icon_url TEXT;
+
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'server_update';
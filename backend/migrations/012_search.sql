@@ -0,0 +1,15 @@
+-- =============================================
+-- Banter — Full-text message search (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 011_images.sql
+--
+-- Tombstoned messages have empty content, so they drop out of the index.
+-- =============================================
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_tsv tsvector
+    GENERATED ALWAYS AS (to_tsvector('english'::regconfig, content)) STORED;
+
+ALTER TABLE dm_messages ADD COLUMN IF NOT EXISTS content_tsv tsvector
+    GENERATED ALWAYS AS (to_tsvector('english'::regconfig, content)) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_messages_content_tsv ON messages USING GIN (content_tsv);
+CREATE INDEX IF NOT EXISTS idx_dm_messages_content_tsv ON dm_messages USING GIN (content_tsv);
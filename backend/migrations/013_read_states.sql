@@ -0,0 +1,39 @@
+-- =============================================
+-- Banter — Read states (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 012_search.sql
+--
+-- One row per user per channel / DM they have read. `last_read_at` is the
+-- acknowledged message's `created_at`; everything newer from someone else
+-- counts as unread. Channels without a row are read up to the member's
+-- `joined_at`, DMs without a row are entirely unread.
+-- =============================================
+
+CREATE TABLE IF NOT EXISTS read_states (
+    user_id              UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    channel_id           UUID NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    last_read_message_id UUID REFERENCES messages(id) ON DELETE SET NULL,
+    last_read_at         TIMESTAMPTZ NOT NULL,
+    updated_at           TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, channel_id)
+);
+
+CREATE TABLE IF NOT EXISTS dm_read_states (
+    user_id              UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    dm_channel_id        UUID NOT NULL REFERENCES dm_channels(id) ON DELETE CASCADE,
+    last_read_message_id UUID REFERENCES dm_messages(id) ON DELETE SET NULL,
+    last_read_at         TIMESTAMPTZ NOT NULL,
+    updated_at           TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, dm_channel_id)
+);
+
+ALTER TABLE read_states ENABLE ROW LEVEL SECURITY;
+ALTER TABLE dm_read_states ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_read_states') THEN
+    CREATE POLICY "service_all_read_states" ON read_states FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_dm_read_states') THEN
+    CREATE POLICY "service_all_dm_read_states" ON dm_read_states FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,28 @@
+-- =============================================
+-- Banter — Mentions (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 013_read_states.sql
+--
+-- `message_mentions` holds everyone a channel message notified: users named
+-- with <@id> (`direct`) and members reached through a mentioned role,
+-- @everyone or @here. Only members who could see the channel are stored.
+-- =============================================
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS mention_everyone BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS mention_role_ids UUID[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE IF NOT EXISTS message_mentions (
+    message_id  UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id     UUID NOT NULL REFERENCES profiles(id) ON DELETE CASCADE,
+    direct      BOOLEAN NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, user_id)
+);
+CREATE INDEX IF NOT EXISTS idx_message_mentions_user ON message_mentions(user_id, created_at DESC);
+
+ALTER TABLE message_mentions ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_message_mentions') THEN
+    CREATE POLICY "service_all_message_mentions" ON message_mentions FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,24 @@
+-- =============================================
+-- Banter — Gateway event bus (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 014_mentions.sql
+--
+-- With EVENT_BUS=postgres, backend nodes pass gateway events to each other
+-- with NOTIFY on the `banter_gateway` channel. Payloads too big for NOTIFY
+-- (8000 bytes) are stored here and only their id is sent; rows are only
+-- needed for a moment and are swept after a minute.
+-- =============================================
+
+CREATE UNLOGGED TABLE IF NOT EXISTS gateway_events (
+    id          BIGSERIAL PRIMARY KEY,
+    payload     TEXT NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_gateway_events_created ON gateway_events(created_at);
+
+ALTER TABLE gateway_events ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_gateway_events') THEN
+    CREATE POLICY "service_all_gateway_events" ON gateway_events FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
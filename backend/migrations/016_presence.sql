@@ -0,0 +1,17 @@
+-- =============================================
+-- Banter — Presence (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 015_event_bus.sql
+--
+-- `profiles.status` is the status the user last picked; `invisible` shows as
+-- offline to everyone else. The custom status (text and/or emoji) is cleared
+-- once `custom_status_expires_at` passes.
+-- =============================================
+
+ALTER TYPE user_status ADD VALUE IF NOT EXISTS 'invisible';
+
+ALTER TABLE profiles ADD COLUMN IF NOT EXISTS custom_status_text TEXT;
+ALTER TABLE profiles ADD COLUMN IF NOT EXISTS custom_status_emoji TEXT;
+ALTER TABLE profiles ADD COLUMN IF NOT EXISTS custom_status_expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_profiles_custom_status_expires
+    ON profiles(custom_status_expires_at) WHERE custom_status_expires_at IS NOT NULL;
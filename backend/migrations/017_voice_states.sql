@@ -0,0 +1,23 @@
+-- =============================================
+-- Banter — Voice states (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 016_presence.sql
+--
+-- Voice states are set through the gateway. A user is in at most one voice
+-- channel; `session_id` is the gateway session that joined, and the row is
+-- removed when that session ends. `node_id` is the backend node holding the
+-- session, so rows left behind by a node that died can be swept.
+-- =============================================
+
+ALTER TABLE voice_states ADD COLUMN IF NOT EXISTS deafened BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE voice_states ADD COLUMN IF NOT EXISTS screen_sharing BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE voice_states ADD COLUMN IF NOT EXISTS session_id UUID;
+ALTER TABLE voice_states ADD COLUMN IF NOT EXISTS node_id UUID;
+
+-- Keep only each user's latest voice state before enforcing one per user
+DELETE FROM voice_states v
+USING voice_states newer
+WHERE newer.user_id = v.user_id
+  AND (newer.joined_at, newer.channel_id) > (v.joined_at, v.channel_id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_voice_states_user ON voice_states(user_id);
+CREATE INDEX IF NOT EXISTS idx_voice_states_session ON voice_states(session_id);
@@ -0,0 +1,48 @@
+-- =============================================
+-- Banter — LiveKit webhook ordering (idempotent — safe to re-run)
+-- Run this in Supabase SQL Editor after 017_voice_states.sql
+--
+-- LiveKit may deliver a webhook more than once, and out of order. The id of
+-- each event handled is kept for a day so a redelivery is skipped, and
+-- `voice_state_changes` records when each user's voice state last changed
+-- (including being removed) so an event created before that is ignored.
+-- =============================================
+
+CREATE TABLE IF NOT EXISTS livekit_webhook_events (
+    id          TEXT PRIMARY KEY,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_livekit_webhook_events_received ON livekit_webhook_events(received_at);
+
+CREATE TABLE IF NOT EXISTS voice_state_changes (
+    user_id    UUID PRIMARY KEY REFERENCES profiles(id) ON DELETE CASCADE,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE OR REPLACE FUNCTION public.record_voice_state_change()
+RETURNS TRIGGER AS $$
+BEGIN
+  INSERT INTO public.voice_state_changes (user_id, changed_at)
+  VALUES (CASE WHEN TG_OP = 'DELETE' THEN OLD.user_id ELSE NEW.user_id END, clock_timestamp())
+  ON CONFLICT (user_id) DO UPDATE SET changed_at = EXCLUDED.changed_at;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS on_voice_state_change ON voice_states;
+CREATE TRIGGER on_voice_state_change
+  AFTER INSERT OR UPDATE OR DELETE ON voice_states
+  FOR EACH ROW EXECUTE FUNCTION public.record_voice_state_change();
+
+ALTER TABLE livekit_webhook_events ENABLE ROW LEVEL SECURITY;
+ALTER TABLE voice_state_changes ENABLE ROW LEVEL SECURITY;
+
+DO $$ BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_livekit_webhook_events') THEN
+    CREATE POLICY "service_all_livekit_webhook_events" ON livekit_webhook_events FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_policies WHERE policyname = 'service_all_voice_state_changes') THEN
+    CREATE POLICY "service_all_voice_state_changes" ON voice_state_changes FOR ALL USING (auth.role() = 'service_role');
+  END IF;
+END $$;
@@ -0,0 +1,130 @@
+//! Server audit log: recording moderation / admin actions.
+//!
+//! Handlers build an `AuditEntry` after validating a change and record it on
+//! the same connection (or transaction) that applied it. The free-text reason
+//! comes from the optional `X-Audit-Log-Reason` request header.
+
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use serde::Serialize;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::error::{AppError, AppResult};
+use crate::models::{AuditAction, AuditChange};
+
+/// Header carrying the reason for an audited action.
+pub const REASON_HEADER: &str = "x-audit-log-reason";
+
+/// Longest reason stored with an entry.
+pub const MAX_REASON_LEN: usize = 512;
+
+/// Optional reason supplied via `X-Audit-Log-Reason`.
+#[derive(Debug, Clone, Default)]
+pub struct AuditReason(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuditReason {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &AppState) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(REASON_HEADER) else {
+            return Ok(AuditReason(None));
+        };
+
+        let reason = value
+            .to_str()
+            .map_err(|_| AppError::BadRequest("Invalid audit log reason".into()))?;
+        normalize_reason(Some(reason)).map(AuditReason)
+    }
+}
+
+/// Trim a reason, treating blank as none, and enforce `MAX_REASON_LEN`.
+pub fn normalize_reason(reason: Option<&str>) -> AppResult<Option<String>> {
+    let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+    if reason.is_some_and(|r| r.chars().count() > MAX_REASON_LEN) {
+        return Err(AppError::BadRequest(format!("Reason cannot exceed {MAX_REASON_LEN} characters")));
+    }
+    Ok(reason.map(str::to_owned))
+}
+
+/// An audit log entry being built up before it's recorded.
+#[derive(Debug)]
+pub struct AuditEntry {
+    server_id: Uuid,
+    actor_id: Uuid,
+    action: AuditAction,
+    target_id: Option<Uuid>,
+    changes: Vec<AuditChange>,
+    reason: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(server_id: Uuid, actor_id: Uuid, action: AuditAction) -> Self {
+        Self {
+            server_id,
+            actor_id,
+            action,
+            target_id: None,
+            changes: Vec::new(),
+            reason: None,
+        }
+    }
+
+    pub fn target(mut self, target_id: Uuid) -> Self {
+        self.target_id = Some(target_id);
+        self
+    }
+
+    pub fn reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason;
+        self
+    }
+
+    /// Record `key` going from `old` to `new` (skipped if nothing changed).
+    pub fn change(mut self, key: &str, old: impl Serialize, new: impl Serialize) -> Self {
+        let (old, new) = (to_value(old), to_value(new));
+        if old != new {
+            self.changes.push(AuditChange { key: key.into(), old_value: old, new_value: new });
+        }
+        self
+    }
+
+    /// Record the value of `key` on something that was created.
+    pub fn created(mut self, key: &str, new: impl Serialize) -> Self {
+        self.changes.push(AuditChange { key: key.into(), old_value: None, new_value: to_value(new) });
+        self
+    }
+
+    /// Record the value of `key` on something that was removed.
+    pub fn removed(mut self, key: &str, old: impl Serialize) -> Self {
+        self.changes.push(AuditChange { key: key.into(), old_value: to_value(old), new_value: None });
+        self
+    }
+
+    pub async fn record<'e>(self, executor: impl PgExecutor<'e>) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log_entries (server_id, actor_id, action, target_id, changes, reason)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(self.server_id)
+        .bind(self.actor_id)
+        .bind(self.action)
+        .bind(self.target_id)
+        .bind(sqlx::types::Json(&self.changes))
+        .bind(&self.reason)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Serialize a value for the `changes` column, with `null` meaning absent.
+fn to_value(value: impl Serialize) -> Option<serde_json::Value> {
+    serde_json::to_value(value).ok().filter(|v| !v.is_null())
+}
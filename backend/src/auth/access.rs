@@ -0,0 +1,254 @@
+//! Membership and permission checks shared by the REST handlers and the
+//! WebSocket gateway.
+//!
+//! Every endpoint or gateway event that touches a server, channel or DM goes
+//! through one of these helpers so both transports enforce identical rules.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::models::Channel;
+use crate::permissions::{self, MemberPermissions, Permissions};
+
+/// Ensure `user_id` is a member of `server_id`, returning their permissions.
+pub async fn require_server_member(pool: &PgPool, server_id: Uuid, user_id: Uuid) -> AppResult<MemberPermissions> {
+    permissions::server_permissions(pool, server_id, user_id).await
+}
+
+/// Ensure `user_id` is not banned from `server_id` (checked before joining).
+pub async fn require_not_banned(pool: &PgPool, server_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    let is_banned = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM server_bans WHERE server_id = $1 AND user_id = $2)"
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    if is_banned {
+        return Err(AppError::Forbidden("You are banned from this server".into()));
+    }
+
+    Ok(())
+}
+
+/// Ensure `user_id` holds `perm` in `server_id`.
+pub async fn require_server_permission(
+    pool: &PgPool,
+    server_id: Uuid,
+    user_id: Uuid,
+    perm: Permissions,
+) -> AppResult<MemberPermissions> {
+    let perms = permissions::server_permissions(pool, server_id, user_id).await?;
+    perms.require(perm)?;
+    Ok(perms)
+}
+
+/// Load a channel and ensure `user_id` holds `perm` in it, taking the
+/// channel's permission overwrites into account.
+pub async fn require_channel_permission(
+    pool: &PgPool,
+    channel_id: Uuid,
+    user_id: Uuid,
+    perm: Permissions,
+) -> AppResult<Channel> {
+    let channel = load_channel(pool, channel_id).await?;
+    permissions::channel_permissions(pool, &channel, user_id).await?.require(perm)?;
+    Ok(channel)
+}
+
+/// Load a channel by id.
+pub async fn load_channel(pool: &PgPool, channel_id: Uuid) -> AppResult<Channel> {
+    sqlx::query_as::<_, Channel>("SELECT * FROM channels WHERE id = $1")
+        .bind(channel_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Channel not found".into()))
+}
+
+/// Ensure `user_id` is a participant of the DM channel.
+pub async fn require_dm_member(pool: &PgPool, dm_channel_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    let is_member = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM dm_members WHERE dm_channel_id = $1 AND user_id = $2)"
+    )
+    .bind(dm_channel_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    if !is_member {
+        return Err(AppError::Forbidden("You are not a participant of this DM".into()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, TestServer};
+
+    fn is_forbidden<T>(result: AppResult<T>) -> bool {
+        matches!(result, Err(AppError::Forbidden(_)))
+    }
+
+    /// Give `user_id` a new role in the server with `perms`.
+    async fn grant(pool: &PgPool, server_id: Uuid, user_id: Uuid, perms: Permissions) -> Uuid {
+        let role_id = sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO roles (server_id, name, position, permissions) VALUES ($1, 'Role', 1, $2) RETURNING id"
+        )
+        .bind(server_id)
+        .bind(perms)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO member_roles (server_id, user_id, role_id) VALUES ($1, $2, $3)")
+            .bind(server_id)
+            .bind(user_id)
+            .bind(role_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        role_id
+    }
+
+    /// Overwrite `perms` for `target_id` in the channel: allowed, or denied.
+    async fn overwrite(pool: &PgPool, channel_id: Uuid, target_id: Uuid, kind: &str, perms: Permissions, allow: bool) {
+        let (allow, deny) = if allow { (perms, Permissions::default()) } else { (Permissions::default(), perms) };
+        sqlx::query(
+            "INSERT INTO channel_overwrites (channel_id, target_id, kind, allow, deny) VALUES ($1, $2, $3::overwrite_kind, $4, $5)"
+        )
+        .bind(channel_id)
+        .bind(target_id)
+        .bind(kind)
+        .bind(allow)
+        .bind(deny)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn everyone_role(pool: &PgPool, server_id: Uuid) -> Uuid {
+        sqlx::query_scalar("SELECT id FROM roles WHERE server_id = $1 AND is_default")
+            .bind(server_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    /// Two servers: `a` owned by Alice with Bob as a member, `b` owned by Carol.
+    async fn setup() -> (PgPool, TestServer, TestServer, [Uuid; 3]) {
+        let pool = test_util::database().await;
+        let alice = test_util::user(&pool, "Alice").await;
+        let bob = test_util::user(&pool, "Bob").await;
+        let carol = test_util::user(&pool, "Carol").await;
+        let a = test_util::server(&pool, alice).await;
+        let b = test_util::server(&pool, carol).await;
+        test_util::join(&pool, a.id, bob).await;
+        (pool, a, b, [alice, bob, carol])
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn members_get_their_server_permissions() {
+        let (pool, a, _, [alice, bob, carol]) = setup().await;
+
+        assert!(require_server_member(&pool, a.id, alice).await.unwrap().is_owner);
+        let perms = require_server_member(&pool, a.id, bob).await.unwrap();
+        assert_eq!(perms.permissions, Permissions::DEFAULT);
+        assert!(is_forbidden(require_server_member(&pool, a.id, carol).await));
+
+        require_server_permission(&pool, a.id, alice, Permissions::MANAGE_SERVER).await.unwrap();
+        assert!(is_forbidden(require_server_permission(&pool, a.id, bob, Permissions::MANAGE_SERVER).await));
+        grant(&pool, a.id, bob, Permissions::MANAGE_SERVER).await;
+        require_server_permission(&pool, a.id, bob, Permissions::MANAGE_SERVER).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn channel_overwrites_apply() {
+        let (pool, a, _, [alice, bob, _]) = setup().await;
+        let channel = a.text_channel;
+
+        require_channel_permission(&pool, channel, bob, Permissions::SEND_MESSAGES).await.unwrap();
+
+        // Private to a role Bob doesn't have yet
+        let everyone = everyone_role(&pool, a.id).await;
+        overwrite(&pool, channel, everyone, "role", Permissions::VIEW_CHANNEL, false).await;
+        assert!(is_forbidden(require_channel_permission(&pool, channel, bob, Permissions::VIEW_CHANNEL).await));
+        require_channel_permission(&pool, channel, alice, Permissions::VIEW_CHANNEL).await.unwrap();
+
+        let role = grant(&pool, a.id, bob, Permissions::default()).await;
+        overwrite(&pool, channel, role, "role", Permissions::VIEW_CHANNEL, true).await;
+        require_channel_permission(&pool, channel, bob, Permissions::SEND_MESSAGES).await.unwrap();
+
+        overwrite(&pool, channel, bob, "member", Permissions::SEND_MESSAGES, false).await;
+        assert!(is_forbidden(require_channel_permission(&pool, channel, bob, Permissions::SEND_MESSAGES).await));
+        require_channel_permission(&pool, channel, bob, Permissions::VIEW_CHANNEL).await.unwrap();
+
+        let missing = require_channel_permission(&pool, Uuid::new_v4(), bob, Permissions::VIEW_CHANNEL).await;
+        assert!(matches!(missing, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn nothing_carries_over_between_servers() {
+        let (pool, a, b, [alice, bob, carol]) = setup().await;
+
+        // Alice owns A but is no member of B, so none of B's channels are hers
+        assert!(is_forbidden(require_server_member(&pool, b.id, alice).await));
+        assert!(is_forbidden(require_channel_permission(&pool, b.text_channel, alice, Permissions::VIEW_CHANNEL).await));
+        assert!(is_forbidden(require_channel_permission(&pool, b.voice_channel, alice, Permissions::CONNECT).await));
+
+        // An administrator role in A grants nothing in B
+        test_util::join(&pool, b.id, bob).await;
+        let admin = grant(&pool, a.id, bob, Permissions::ADMINISTRATOR).await;
+        assert!(require_server_member(&pool, a.id, bob).await.unwrap().has(Permissions::MANAGE_SERVER));
+        assert!(is_forbidden(require_server_permission(&pool, b.id, bob, Permissions::MANAGE_SERVER).await));
+
+        // Nor does an overwrite in B that targets A's role
+        let everyone = everyone_role(&pool, b.id).await;
+        overwrite(&pool, b.text_channel, everyone, "role", Permissions::VIEW_CHANNEL, false).await;
+        overwrite(&pool, b.text_channel, admin, "role", Permissions::VIEW_CHANNEL, true).await;
+        assert!(is_forbidden(require_channel_permission(&pool, b.text_channel, bob, Permissions::VIEW_CHANNEL).await));
+
+        // Fan-out only reaches members of the channel's own server
+        let channel = load_channel(&pool, b.text_channel).await.unwrap();
+        let viewers =
+            permissions::members_with_channel_permission(&pool, &channel, &[alice, bob, carol], Permissions::VIEW_CHANNEL)
+                .await
+                .unwrap();
+        assert_eq!(viewers, vec![carol]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn bans_and_dms_are_checked() {
+        let (pool, a, _, [alice, bob, carol]) = setup().await;
+
+        require_not_banned(&pool, a.id, carol).await.unwrap();
+        sqlx::query("INSERT INTO server_bans (server_id, user_id, banned_by) VALUES ($1, $2, $3)")
+            .bind(a.id)
+            .bind(carol)
+            .bind(alice)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(is_forbidden(require_not_banned(&pool, a.id, carol).await));
+
+        let dm_id = sqlx::query_scalar::<_, Uuid>("INSERT INTO dm_channels DEFAULT VALUES RETURNING id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO dm_members (dm_channel_id, user_id) VALUES ($1, $2), ($1, $3)")
+            .bind(dm_id)
+            .bind(alice)
+            .bind(bob)
+            .execute(&pool)
+            .await
+            .unwrap();
+        require_dm_member(&pool, dm_id, bob).await.unwrap();
+        assert!(is_forbidden(require_dm_member(&pool, dm_id, carol).await));
+    }
+}
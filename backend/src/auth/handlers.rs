@@ -1,4 +1,6 @@
-/// Auth-related REST handlers: GET /auth/me, PATCH /auth/me
+//! Auth-related REST handlers: GET /auth/me, PATCH /auth/me
+//!
+//! Avatar and banner images are uploaded through `handlers::images`.
 
 use axum::extract::State;
 use axum::Json;
@@ -25,11 +27,15 @@ pub async fn get_me(
     Ok(Json(profile))
 }
 
+/// Longest emoji avatar accepted by PATCH /auth/me, in characters.
+const MAX_EMOJI_AVATAR_CHARS: usize = 8;
+
 /// Request body for PATCH /auth/me
 #[derive(Debug, Deserialize)]
 pub struct UpdateProfileRequest {
     pub username: Option<String>,
     pub display_name: Option<String>,
+    /// An emoji avatar; images go through PUT /auth/me/avatar
     pub avatar_url: Option<String>,
 }
 
@@ -39,6 +45,18 @@ pub async fn update_me(
     State(state): State<AppState>,
     Json(body): Json<UpdateProfileRequest>,
 ) -> AppResult<Json<Profile>> {
+    if let Some(avatar) = &body.avatar_url {
+        let is_emoji = !avatar.is_empty()
+            && avatar.chars().count() <= MAX_EMOJI_AVATAR_CHARS
+            && !avatar.is_ascii()
+            && !avatar.chars().any(|c| c.is_whitespace() || c.is_control());
+        if !is_emoji {
+            return Err(AppError::BadRequest(
+                "avatar_url must be an emoji; upload images with PUT /auth/me/avatar".into(),
+            ));
+        }
+    }
+
     let profile = sqlx::query_as::<_, Profile>(
         r#"
         UPDATE profiles
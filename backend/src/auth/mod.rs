@@ -1,4 +1,5 @@
 pub mod supabase;
 pub mod handlers;
+pub mod access;
 
 pub use supabase::{AuthUser, verify_token};
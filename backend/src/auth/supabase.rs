@@ -1,8 +1,8 @@
-/// Supabase JWT verification middleware.
-///
-/// Extracts the `Authorization: Bearer <token>` header, decodes the Supabase
-/// JWT using the project's JWT secret, and provides the authenticated user's
-/// UUID as an `AuthUser` extractor.
+//! Supabase JWT verification middleware.
+//!
+//! Extracts the `Authorization: Bearer <token>` header, decodes the Supabase
+//! JWT using the project's JWT secret, and provides the authenticated user's
+//! UUID as an `AuthUser` extractor.
 
 use async_trait::async_trait;
 use axum::extract::FromRequestParts;
@@ -1,4 +1,4 @@
-/// Application configuration loaded from environment variables.
+//! Application configuration loaded from environment variables.
 
 #[derive(Clone, Debug)]
 pub struct AppConfig {
@@ -11,10 +11,63 @@ pub struct AppConfig {
     pub livekit_api_key: String,
     pub livekit_api_secret: String,
     pub backend_port: u16,
+    /// How often gateway clients must send `heartbeat`, in milliseconds
+    pub ws_heartbeat_interval_ms: u64,
+    pub ws_slow_consumer_policy: SlowConsumerPolicy,
+    /// How long a gateway session can go without client events (other than
+    /// heartbeats) before it shows as idle, in seconds
+    pub presence_idle_timeout_secs: u64,
+    pub event_bus: EventBusConfig,
+    pub storage: StorageConfig,
+}
+
+/// Where uploaded files are kept, selected by `STORAGE_BACKEND`.
+#[derive(Clone, Debug)]
+pub enum StorageConfig {
+    /// Files on local disk, served by the backend under `/uploads`
+    Local {
+        dir: String,
+        public_url: String,
+        /// Key signing attachment URLs
+        url_secret: String,
+    },
+    /// Any S3-compatible object store (AWS, MinIO, R2, ...), path-style
+    S3 {
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+        public_url: String,
+    },
+}
+
+/// What the gateway does when a client can't keep up with its events,
+/// selected by `WS_SLOW_CONSUMER_POLICY`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlowConsumerPolicy {
+    /// Drop non-critical events (typing) while the socket's queue is full,
+    /// and disconnect only when a critical one doesn't fit
+    DropNonCritical,
+    /// Disconnect as soon as the socket's queue is full
+    Disconnect,
+}
+
+/// How gateway events reach users connected to other backend nodes,
+/// selected by `EVENT_BUS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventBusConfig {
+    /// A single node; events stay in process
+    Local,
+    /// `LISTEN` / `NOTIFY` on the database (needs a session-mode connection,
+    /// not a transaction pooler)
+    Postgres,
 }
 
 impl AppConfig {
     pub fn from_env() -> Self {
+        let backend_port = env("BACKEND_PORT").parse().unwrap_or(8080);
+
         Self {
             supabase_url: env("SUPABASE_URL"),
             supabase_anon_key: env("SUPABASE_ANON_KEY"),
@@ -24,9 +77,61 @@ impl AppConfig {
             livekit_url: env("LIVEKIT_URL"),
             livekit_api_key: env("LIVEKIT_API_KEY"),
             livekit_api_secret: env("LIVEKIT_API_SECRET"),
-            backend_port: env("BACKEND_PORT")
+            backend_port,
+            ws_heartbeat_interval_ms: env_or("WS_HEARTBEAT_INTERVAL_MS", "30000")
+                .parse()
+                .expect("WS_HEARTBEAT_INTERVAL_MS must be a number of milliseconds"),
+            ws_slow_consumer_policy: SlowConsumerPolicy::from_env(),
+            presence_idle_timeout_secs: env_or("PRESENCE_IDLE_TIMEOUT_SECS", "300")
                 .parse()
-                .unwrap_or(8080),
+                .expect("PRESENCE_IDLE_TIMEOUT_SECS must be a number of seconds"),
+            event_bus: EventBusConfig::from_env(),
+            storage: StorageConfig::from_env(backend_port),
+        }
+    }
+}
+
+impl StorageConfig {
+    fn from_env(backend_port: u16) -> Self {
+        match env_or("STORAGE_BACKEND", "local").as_str() {
+            "local" => Self::Local {
+                dir: env_or("STORAGE_LOCAL_DIR", "./uploads"),
+                public_url: env_or("STORAGE_PUBLIC_URL", &format!("http://localhost:{backend_port}/uploads")),
+                url_secret: env("STORAGE_URL_SECRET"),
+            },
+            "s3" => {
+                let endpoint = env("S3_ENDPOINT").trim_end_matches('/').to_string();
+                let bucket = env("S3_BUCKET");
+                Self::S3 {
+                    public_url: env_or("STORAGE_PUBLIC_URL", &format!("{endpoint}/{bucket}")),
+                    endpoint,
+                    bucket,
+                    region: env_or("S3_REGION", "us-east-1"),
+                    access_key: env("S3_ACCESS_KEY"),
+                    secret_key: env("S3_SECRET_KEY"),
+                }
+            }
+            other => panic!("Unknown STORAGE_BACKEND: {other} (expected \"local\" or \"s3\")"),
+        }
+    }
+}
+
+impl SlowConsumerPolicy {
+    fn from_env() -> Self {
+        match env_or("WS_SLOW_CONSUMER_POLICY", "drop").as_str() {
+            "drop" => Self::DropNonCritical,
+            "disconnect" => Self::Disconnect,
+            other => panic!("Unknown WS_SLOW_CONSUMER_POLICY: {other} (expected \"drop\" or \"disconnect\")"),
+        }
+    }
+}
+
+impl EventBusConfig {
+    fn from_env() -> Self {
+        match env_or("EVENT_BUS", "local").as_str() {
+            "local" => Self::Local,
+            "postgres" => Self::Postgres,
+            other => panic!("Unknown EVENT_BUS: {other} (expected \"local\" or \"postgres\")"),
         }
     }
 }
@@ -34,3 +139,7 @@ impl AppConfig {
 fn env(key: &str) -> String {
     std::env::var(key).unwrap_or_else(|_| panic!("Missing environment variable: {key}"))
 }
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
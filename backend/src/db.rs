@@ -1,4 +1,4 @@
-/// Database connection pool setup using SQLx.
+//! Database connection pool setup using SQLx.
 
 use sqlx::postgres::{PgPool, PgPoolOptions};
 
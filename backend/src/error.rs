@@ -1,4 +1,4 @@
-/// Unified error type that converts into Axum HTTP responses.
+//! Unified error type that converts into Axum HTTP responses.
 
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
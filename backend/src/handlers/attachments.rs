@@ -0,0 +1,377 @@
+//! Attachment handlers: multipart upload to a channel / DM, claiming uploads
+//! for a message, and sweeping files nobody needs any more
+//!
+//! Clients upload each file first, then pass the returned ids in
+//! `attachments` when sending the message (see `channels::create_message`
+//! and `dms::create_dm_message`).
+//!
+//! Attachment URLs are signed and expire (see `storage`), and are only
+//! handed out with messages the caller can read, so files in private
+//! channels and DMs can't be fetched by anyone who merely guesses or keeps
+//! an old link.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::extract::{Multipart, Path, State};
+use axum::Json;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{Attachment, MessageKind};
+use crate::permissions::Permissions;
+use crate::storage::{self, Storage};
+
+/// Largest file accepted by the upload endpoints.
+pub const MAX_ATTACHMENT_BYTES: usize = 25 * 1024 * 1024;
+
+/// Most attachments a single message can carry.
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 10;
+
+/// Longest filename kept, in characters.
+const MAX_FILENAME_CHARS: usize = 255;
+
+/// Content types accepted for upload, with the extension files of each type
+/// are stored under. The client's own extension is never used, since it
+/// decides how the file is served.
+const ALLOWED_CONTENT_TYPES: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("audio/mpeg", "mp3"),
+    ("audio/ogg", "ogg"),
+    ("audio/wav", "wav"),
+    ("application/pdf", "pdf"),
+    ("application/zip", "zip"),
+    ("text/plain", "txt"),
+];
+
+/// How long an upload may sit unclaimed before it's swept.
+const UNCLAIMED_TTL_HOURS: i32 = 24;
+
+/// How often the sweeper runs.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(sqlx::FromRow)]
+struct AttachmentRow {
+    id: Uuid,
+    message_id: Option<Uuid>,
+    storage_key: String,
+    filename: String,
+    content_type: String,
+    size: i64,
+    width: Option<i32>,
+    height: Option<i32>,
+}
+
+impl AttachmentRow {
+    /// The attachment with a signed URL, for callers who may read its message.
+    fn into_attachment(self, storage: &dyn Storage) -> Attachment {
+        Attachment {
+            url: storage.signed_url(&self.storage_key, storage::url_expires()),
+            id: self.id,
+            filename: self.filename,
+            content_type: self.content_type,
+            size: self.size,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Columns linking an attachment to its channel and message for `kind`.
+fn columns(kind: MessageKind) -> (&'static str, &'static str) {
+    match kind {
+        MessageKind::Channel => ("channel_id", "message_id"),
+        MessageKind::Dm => ("dm_channel_id", "dm_message_id"),
+    }
+}
+
+/// POST /api/v1/channels/:id/attachments — multipart, one `file` field
+pub async fn upload_attachment(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(channel_id): Path<Uuid>,
+    multipart: Multipart,
+) -> AppResult<Json<Attachment>> {
+    access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+    )
+    .await?;
+
+    let attachment = store(&state, MessageKind::Channel, channel_id, auth.user_id, multipart).await?;
+    Ok(Json(attachment))
+}
+
+/// POST /api/v1/dms/:id/attachments — multipart, one `file` field
+pub async fn upload_dm_attachment(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(dm_channel_id): Path<Uuid>,
+    multipart: Multipart,
+) -> AppResult<Json<Attachment>> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    let attachment = store(&state, MessageKind::Dm, dm_channel_id, auth.user_id, multipart).await?;
+    Ok(Json(attachment))
+}
+
+/// Validate the uploaded file, write it to storage and record it as unclaimed.
+async fn store(
+    state: &AppState,
+    kind: MessageKind,
+    channel_id: Uuid,
+    uploader_id: Uuid,
+    mut multipart: Multipart,
+) -> AppResult<Attachment> {
+    let invalid = |e: axum::extract::multipart::MultipartError| AppError::BadRequest(e.body_text());
+
+    let field = loop {
+        match multipart.next_field().await.map_err(invalid)? {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue,
+            None => return Err(AppError::BadRequest("Missing `file` field".into())),
+        }
+    };
+
+    let filename = sanitize_filename(field.file_name().unwrap_or_default());
+    let content_type = field
+        .content_type()
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| ct.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    let body = field.bytes().await.map_err(invalid)?.to_vec();
+
+    if body.is_empty() {
+        return Err(AppError::BadRequest("File is empty".into()));
+    }
+    if body.len() > MAX_ATTACHMENT_BYTES {
+        return Err(AppError::BadRequest(format!(
+            "File cannot exceed {} MiB",
+            MAX_ATTACHMENT_BYTES / (1024 * 1024)
+        )));
+    }
+    let Some(&(_, extension)) = ALLOWED_CONTENT_TYPES.iter().find(|(ct, _)| *ct == content_type) else {
+        return Err(AppError::BadRequest(format!("Unsupported file type: {content_type}")));
+    };
+
+    // Images must really be the format they claim, so clients can trust the
+    // dimensions and render them inline
+    let (width, height) = if content_type.starts_with("image/") {
+        if sniff_image(&body) != Some(content_type.as_str()) {
+            return Err(AppError::BadRequest("File is not a valid image of its declared type".into()));
+        }
+        let size = imagesize::blob_size(&body)
+            .map_err(|_| AppError::BadRequest("File is not a valid image".into()))?;
+        (i32::try_from(size.width).ok(), i32::try_from(size.height).ok())
+    } else {
+        (None, None)
+    };
+
+    let id = Uuid::new_v4();
+    let storage_key = format!("attachments/{id}/{}.{extension}", storage_safe(file_stem(&filename)));
+    let size = body.len() as i64;
+
+    state.storage.put(&storage_key, body, &content_type).await?;
+
+    let (channel_column, _) = columns(kind);
+    let inserted = sqlx::query_as::<_, AttachmentRow>(&format!(
+        r#"
+        INSERT INTO attachments (id, uploader_id, {channel_column}, storage_key, filename, content_type, size, width, height)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id, NULL::uuid as message_id, storage_key, filename, content_type, size, width, height
+        "#
+    ))
+    .bind(id)
+    .bind(uploader_id)
+    .bind(channel_id)
+    .bind(&storage_key)
+    .bind(&filename)
+    .bind(&content_type)
+    .bind(size)
+    .bind(width)
+    .bind(height)
+    .fetch_one(&state.pool)
+    .await;
+
+    match inserted {
+        Ok(row) => Ok(row.into_attachment(state.storage.as_ref())),
+        Err(e) => {
+            // Don't leave an object behind that nothing refers to
+            let _ = state.storage.delete(&storage_key).await;
+            Err(e.into())
+        }
+    }
+}
+
+/// Reject attachment lists a single message can't carry.
+pub fn check_count(attachment_ids: &[Uuid]) -> AppResult<()> {
+    if attachment_ids.len() > MAX_ATTACHMENTS_PER_MESSAGE {
+        return Err(AppError::BadRequest(format!(
+            "A message can have at most {MAX_ATTACHMENTS_PER_MESSAGE} attachments"
+        )));
+    }
+    Ok(())
+}
+
+/// Attach the caller's unclaimed uploads in `channel_id` to `message_id`.
+///
+/// Fails if any id is unknown, was uploaded by someone else or to another
+/// channel, or already belongs to a message; run it in the transaction that
+/// inserts the message so nothing is half-sent.
+pub async fn claim<'e>(
+    executor: impl PgExecutor<'e>,
+    kind: MessageKind,
+    channel_id: Uuid,
+    uploader_id: Uuid,
+    message_id: Uuid,
+    attachment_ids: &[Uuid],
+) -> AppResult<()> {
+    let mut ids = attachment_ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let (channel_column, message_column) = columns(kind);
+    let claimed = sqlx::query(&format!(
+        r#"
+        UPDATE attachments SET {message_column} = $1
+        WHERE id = ANY($2) AND uploader_id = $3 AND {channel_column} = $4
+          AND message_id IS NULL AND dm_message_id IS NULL
+        "#
+    ))
+    .bind(message_id)
+    .bind(&ids)
+    .bind(uploader_id)
+    .bind(channel_id)
+    .execute(executor)
+    .await?
+    .rows_affected();
+
+    if claimed != ids.len() as u64 {
+        return Err(AppError::BadRequest("Unknown or already used attachment".into()));
+    }
+    Ok(())
+}
+
+/// Attachments for a page of messages, in upload order.
+pub async fn load_for(
+    state: &AppState,
+    kind: MessageKind,
+    message_ids: &[Uuid],
+) -> AppResult<HashMap<Uuid, Vec<Attachment>>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let (_, message_column) = columns(kind);
+    let rows = sqlx::query_as::<_, AttachmentRow>(&format!(
+        r#"
+        SELECT id, {message_column} as message_id, storage_key, filename, content_type, size, width, height
+        FROM attachments
+        WHERE {message_column} = ANY($1)
+        ORDER BY created_at ASC
+        "#
+    ))
+    .bind(message_ids)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut attachments: HashMap<Uuid, Vec<Attachment>> = HashMap::new();
+    for row in rows {
+        if let Some(message_id) = row.message_id {
+            attachments
+                .entry(message_id)
+                .or_default()
+                .push(row.into_attachment(state.storage.as_ref()));
+        }
+    }
+
+    Ok(attachments)
+}
+
+/// Periodically delete uploads that were never sent, and files of messages
+/// that have been deleted.
+pub async fn run_cleanup(state: AppState) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = sweep(&state).await {
+            tracing::error!("Attachment cleanup failed: {e}");
+        }
+    }
+}
+
+async fn sweep(state: &AppState) -> AppResult<()> {
+    let keys = sqlx::query_scalar::<_, String>(
+        r#"
+        DELETE FROM attachments a
+        WHERE (a.message_id IS NULL AND a.dm_message_id IS NULL
+               AND a.created_at < now() - make_interval(hours => $1))
+           OR EXISTS (SELECT 1 FROM messages m WHERE m.id = a.message_id AND m.deleted_at IS NOT NULL)
+           OR EXISTS (SELECT 1 FROM dm_messages m WHERE m.id = a.dm_message_id AND m.deleted_at IS NOT NULL)
+        RETURNING a.storage_key
+        "#,
+    )
+    .bind(UNCLAIMED_TTL_HOURS)
+    .fetch_all(&state.pool)
+    .await?;
+
+    for key in &keys {
+        if let Err(e) = state.storage.delete(key).await {
+            tracing::warn!("Failed to delete stored file {key}: {e}");
+        }
+    }
+
+    if !keys.is_empty() {
+        tracing::info!("Swept {} attachment(s)", keys.len());
+    }
+    Ok(())
+}
+
+/// Content type of an image, judged by its magic bytes.
+fn sniff_image(body: &[u8]) -> Option<&'static str> {
+    match imagesize::image_type(body).ok()? {
+        imagesize::ImageType::Png => Some("image/png"),
+        imagesize::ImageType::Jpeg => Some("image/jpeg"),
+        imagesize::ImageType::Gif => Some("image/gif"),
+        imagesize::ImageType::Webp => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Strip any client-side directory and cap the length of an uploaded filename.
+fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    let name: String = name.chars().filter(|c| !c.is_control()).take(MAX_FILENAME_CHARS).collect();
+    if name.is_empty() { "file".to_string() } else { name }
+}
+
+/// `filename` without its extension, if it has one.
+fn file_stem(filename: &str) -> &str {
+    match filename.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => filename,
+    }
+}
+
+/// Reduce a filename to characters that are safe in storage keys and URLs
+/// (never a leading dot, so `.` / `..` can't become path segments).
+fn storage_safe(filename: &str) -> String {
+    let safe: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    if safe.starts_with('.') { format!("_{safe}") } else { safe }
+}
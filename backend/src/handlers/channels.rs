@@ -1,16 +1,37 @@
-/// Channel REST handlers: list channels, create channel, get messages
+//! Channel REST handlers: list / create channels, permission overwrites, get / edit / delete messages
+//!
+//! Threads are channels too (see `threads`), so the message endpoints here
+//! serve them as well.
+
+use std::collections::HashMap;
 
 use axum::extract::{Path, State, Query};
+use axum::http::StatusCode;
 use axum::Json;
+use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::AppState;
-use crate::auth::AuthUser;
+use crate::audit::{AuditEntry, AuditReason};
+use crate::auth::{access, AuthUser};
 use crate::error::{AppError, AppResult};
+use crate::handlers::attachments;
+use crate::handlers::reactions;
+use crate::handlers::{mentions, read_states};
+use crate::handlers::threads;
 use crate::models::{
-    Channel, CreateChannelRequest, MessageQuery,
-    ProfileSummary, MessageWithAuthor, VoiceState,
+    AuditAction, BulkDeleteMessagesRequest, Channel, ChannelOverwrite, CreateChannelRequest, Message,
+    MessageKind, MessageQuery, MessageReference, OverwriteKind, ProfileSummary, MessageWithAuthor,
+    PutOverwriteRequest, UpdateMessageRequest, VoiceState,
 };
+use crate::permissions::{self, MemberPermissions, Permissions};
+use crate::ws::events::WsEvent;
+
+/// Upper bound on ids accepted by the bulk-delete endpoint.
+const MAX_BULK_DELETE: usize = 100;
+
+/// Characters of a replied-to message included in a reply's preview.
+const REPLY_PREVIEW_CHARS: usize = 100;
 
 /// GET /api/v1/servers/:id/channels
 pub async fn list_channels(
@@ -18,27 +39,34 @@ pub async fn list_channels(
     State(state): State<AppState>,
     Path(server_id): Path<Uuid>,
 ) -> AppResult<Json<Vec<Channel>>> {
-    // Verify membership
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)"
-    )
-    .bind(server_id)
-    .bind(auth.user_id)
-    .fetch_one(&state.pool)
-    .await?;
+    let perms = access::require_server_member(&state.pool, server_id, auth.user_id).await?;
 
-    if !is_member {
-        return Err(AppError::Forbidden("Not a member".into()));
-    }
+    let channels = visible_channels(&state.pool, server_id, auth.user_id, &perms).await?;
+    Ok(Json(channels))
+}
 
-    let channels = sqlx::query_as::<_, Channel>(
-        "SELECT * FROM channels WHERE server_id = $1 ORDER BY kind, position"
+/// A server's top-level channels that `user_id` (with `perms` there) can view.
+pub async fn visible_channels(
+    pool: &PgPool,
+    server_id: Uuid,
+    user_id: Uuid,
+    perms: &MemberPermissions,
+) -> AppResult<Vec<Channel>> {
+    let mut channels = sqlx::query_as::<_, Channel>(
+        "SELECT * FROM channels WHERE server_id = $1 AND parent_id IS NULL ORDER BY kind, position"
     )
     .bind(server_id)
-    .fetch_all(&state.pool)
+    .fetch_all(pool)
     .await?;
 
-    Ok(Json(channels))
+    // Hide channels the caller can't view
+    let overwrites = permissions::member_overwrites_by_channel(pool, server_id, user_id).await?;
+    channels.retain(|c| {
+        let channel_overwrites = overwrites.get(&c.id).map(Vec::as_slice).unwrap_or_default();
+        perms.in_channel(channel_overwrites).has(Permissions::VIEW_CHANNEL)
+    });
+
+    Ok(channels)
 }
 
 /// POST /api/v1/servers/:id/channels
@@ -46,20 +74,12 @@ pub async fn create_channel(
     auth: AuthUser,
     State(state): State<AppState>,
     Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
     Json(body): Json<CreateChannelRequest>,
 ) -> AppResult<Json<Channel>> {
-    // Only owner/admin can create channels (simplified: check membership for now)
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)"
-    )
-    .bind(server_id)
-    .bind(auth.user_id)
-    .fetch_one(&state.pool)
-    .await?;
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_CHANNELS).await?;
 
-    if !is_member {
-        return Err(AppError::Forbidden("Not a member".into()));
-    }
+    let mut tx = state.pool.begin().await?;
 
     let channel = sqlx::query_as::<_, Channel>(
         r#"
@@ -71,12 +91,205 @@ pub async fn create_channel(
     .bind(server_id)
     .bind(&body.name)
     .bind(&body.kind)
-    .fetch_one(&state.pool)
+    .fetch_one(&mut *tx)
     .await?;
 
+    if body.private {
+        // Deny @everyone, then let the creator back in
+        sqlx::query(
+            r#"
+            INSERT INTO channel_overwrites (channel_id, target_id, kind, deny)
+            SELECT $1, id, 'role', $2 FROM roles WHERE server_id = $3 AND is_default
+            "#,
+        )
+        .bind(channel.id)
+        .bind(Permissions::VIEW_CHANNEL)
+        .bind(server_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO channel_overwrites (channel_id, target_id, kind, allow) VALUES ($1, $2, 'member', $3)"
+        )
+        .bind(channel.id)
+        .bind(auth.user_id)
+        .bind(Permissions::VIEW_CHANNEL)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::ChannelCreate)
+        .target(channel.id)
+        .created("name", &channel.name)
+        .created("kind", &channel.kind)
+        .created("private", body.private)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
     Ok(Json(channel))
 }
 
+/// GET /api/v1/channels/:id/overwrites
+pub async fn list_overwrites(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(channel_id): Path<Uuid>,
+) -> AppResult<Json<Vec<ChannelOverwrite>>> {
+    access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::MANAGE_CHANNELS).await?;
+
+    let overwrites = sqlx::query_as::<_, ChannelOverwrite>(
+        "SELECT * FROM channel_overwrites WHERE channel_id = $1"
+    )
+    .bind(channel_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(overwrites))
+}
+
+/// PUT /api/v1/channels/:id/overwrites/:target_id — create or replace an overwrite
+pub async fn put_overwrite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, target_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+    Json(body): Json<PutOverwriteRequest>,
+) -> AppResult<Json<ChannelOverwrite>> {
+    let channel = access::load_channel(&state.pool, channel_id).await?;
+    let perms = permissions::channel_permissions(&state.pool, &channel, auth.user_id).await?;
+    perms.require(Permissions::MANAGE_CHANNELS)?;
+
+    if channel.parent_id.is_some() {
+        return Err(AppError::BadRequest("Threads use their parent channel's overwrites".into()));
+    }
+
+    // Members can't hand out (or take away) permissions they don't hold
+    let (allow, deny) = (body.allow.truncate(), body.deny.truncate());
+    if !perms.permissions.contains(allow | deny) {
+        return Err(AppError::Forbidden("You cannot overwrite permissions you do not have".into()));
+    }
+
+    let target_exists = match body.kind {
+        OverwriteKind::Role => sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM roles WHERE id = $1 AND server_id = $2)"
+        ),
+        OverwriteKind::Member => sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM server_members WHERE user_id = $1 AND server_id = $2)"
+        ),
+    }
+    .bind(target_id)
+    .bind(channel.server_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    if !target_exists {
+        return Err(AppError::NotFound("Overwrite target not found".into()));
+    }
+
+    let previous = fetch_overwrite(&state.pool, channel_id, target_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let overwrite = sqlx::query_as::<_, ChannelOverwrite>(
+        r#"
+        INSERT INTO channel_overwrites (channel_id, target_id, kind, allow, deny)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (channel_id, target_id)
+        DO UPDATE SET kind = EXCLUDED.kind, allow = EXCLUDED.allow, deny = EXCLUDED.deny
+        RETURNING *
+        "#,
+    )
+    .bind(channel_id)
+    .bind(target_id)
+    .bind(body.kind)
+    .bind(allow)
+    .bind(deny)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    AuditEntry::new(channel.server_id, auth.user_id, AuditAction::ChannelOverwriteUpdate)
+        .target(channel_id)
+        .created("overwrite_target", target_id)
+        .change("kind", previous.as_ref().map(|o| o.kind), overwrite.kind)
+        .change("allow", previous.as_ref().map(|o| o.allow), overwrite.allow)
+        .change("deny", previous.as_ref().map(|o| o.deny), overwrite.deny)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    state.ws_state.permissions_changed(channel.server_id);
+    Ok(Json(overwrite))
+}
+
+/// DELETE /api/v1/channels/:id/overwrites/:target_id
+pub async fn delete_overwrite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, target_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    let channel = access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::MANAGE_CHANNELS).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let removed = sqlx::query_as::<_, ChannelOverwrite>(
+        "DELETE FROM channel_overwrites WHERE channel_id = $1 AND target_id = $2 RETURNING *"
+    )
+    .bind(channel_id)
+    .bind(target_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(removed) = removed {
+        AuditEntry::new(channel.server_id, auth.user_id, AuditAction::ChannelOverwriteDelete)
+            .target(channel_id)
+            .removed("overwrite_target", target_id)
+            .removed("kind", removed.kind)
+            .removed("allow", removed.allow)
+            .removed("deny", removed.deny)
+            .reason(reason)
+            .record(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    state.ws_state.permissions_changed(channel.server_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn fetch_overwrite(pool: &PgPool, channel_id: Uuid, target_id: Uuid) -> AppResult<Option<ChannelOverwrite>> {
+    Ok(sqlx::query_as::<_, ChannelOverwrite>(
+        "SELECT * FROM channel_overwrites WHERE channel_id = $1 AND target_id = $2"
+    )
+    .bind(channel_id)
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Select list + joins producing a `MessageRow`; callers append WHERE / ORDER BY.
+const MESSAGE_SELECT: &str = r#"
+    SELECT
+        m.id, m.channel_id, m.content, m.created_at, m.updated_at,
+        m.mention_everyone, m.mention_role_ids,
+        p.id as author_id, p.username as author_username,
+        p.display_name as author_display_name,
+        p.avatar_url as author_avatar_url,
+        r.id as reply_id, r.content as reply_content, r.deleted_at as reply_deleted_at,
+        rp.id as reply_author_id, rp.username as reply_author_username,
+        rp.display_name as reply_author_display_name,
+        rp.avatar_url as reply_author_avatar_url,
+        t.channel_id as thread_id
+    FROM messages m
+    INNER JOIN profiles p ON p.id = m.author_id
+    LEFT JOIN messages r ON r.id = m.reply_to_id
+    LEFT JOIN profiles rp ON rp.id = r.author_id
+    LEFT JOIN threads t ON t.source_message_id = m.id
+"#;
+
 /// Helper struct for the joined message + author query
 #[derive(sqlx::FromRow)]
 struct MessageRow {
@@ -88,84 +301,450 @@ struct MessageRow {
     author_username: Option<String>,
     author_display_name: String,
     author_avatar_url: Option<String>,
+    updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    reply_id: Option<Uuid>,
+    reply_content: Option<String>,
+    reply_deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    reply_author_id: Option<Uuid>,
+    reply_author_username: Option<String>,
+    reply_author_display_name: Option<String>,
+    reply_author_avatar_url: Option<String>,
+    thread_id: Option<Uuid>,
+    mention_everyone: bool,
+    mention_role_ids: Vec<Uuid>,
+}
+
+impl From<MessageRow> for MessageWithAuthor {
+    fn from(r: MessageRow) -> Self {
+        let reply_to = match (r.reply_id, r.reply_author_id) {
+            (Some(id), Some(author_id)) => Some(MessageReference {
+                id,
+                author: ProfileSummary {
+                    id: author_id,
+                    username: r.reply_author_username,
+                    display_name: r.reply_author_display_name.unwrap_or_default(),
+                    avatar_url: r.reply_author_avatar_url,
+                },
+                content: r
+                    .reply_content
+                    .unwrap_or_default()
+                    .chars()
+                    .take(REPLY_PREVIEW_CHARS)
+                    .collect(),
+                deleted: r.reply_deleted_at.is_some(),
+            }),
+            _ => None,
+        };
+
+        MessageWithAuthor {
+            id: r.id,
+            channel_id: r.channel_id,
+            author: ProfileSummary {
+                id: r.author_id,
+                username: r.author_username,
+                display_name: r.author_display_name,
+                avatar_url: r.author_avatar_url,
+            },
+            content: r.content,
+            created_at: r.created_at,
+            edited_at: r.updated_at,
+            reply_to,
+            thread_id: r.thread_id,
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+            mentions: Vec::new(),
+            mention_roles: r.mention_role_ids,
+            mention_everyone: r.mention_everyone,
+        }
+    }
 }
 
 /// GET /api/v1/channels/:id/messages?before=&limit=
 pub async fn get_messages(
-    _auth: AuthUser,
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(channel_id): Path<Uuid>,
     Query(q): Query<MessageQuery>,
 ) -> AppResult<Json<Vec<MessageWithAuthor>>> {
+    access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY,
+    )
+    .await?;
+
     let limit = q.limit.unwrap_or(50).min(100);
 
     let rows = if let Some(before_id) = q.before {
-        sqlx::query_as::<_, MessageRow>(
-            r#"
-            SELECT
-                m.id, m.channel_id, m.content, m.created_at,
-                p.id as author_id, p.username as author_username,
-                p.display_name as author_display_name,
-                p.avatar_url as author_avatar_url
-            FROM messages m
-            INNER JOIN profiles p ON p.id = m.author_id
-            WHERE m.channel_id = $1
+        sqlx::query_as::<_, MessageRow>(&format!(
+            r#"{MESSAGE_SELECT}
+            WHERE m.channel_id = $1 AND m.deleted_at IS NULL
               AND m.created_at < (SELECT created_at FROM messages WHERE id = $2)
             ORDER BY m.created_at DESC
             LIMIT $3
-            "#,
-        )
+            "#
+        ))
         .bind(channel_id)
         .bind(before_id)
         .bind(limit)
         .fetch_all(&state.pool)
         .await?
     } else {
-        sqlx::query_as::<_, MessageRow>(
-            r#"
-            SELECT
-                m.id, m.channel_id, m.content, m.created_at,
-                p.id as author_id, p.username as author_username,
-                p.display_name as author_display_name,
-                p.avatar_url as author_avatar_url
-            FROM messages m
-            INNER JOIN profiles p ON p.id = m.author_id
-            WHERE m.channel_id = $1
+        sqlx::query_as::<_, MessageRow>(&format!(
+            r#"{MESSAGE_SELECT}
+            WHERE m.channel_id = $1 AND m.deleted_at IS NULL
             ORDER BY m.created_at DESC
             LIMIT $2
-            "#,
-        )
+            "#
+        ))
         .bind(channel_id)
         .bind(limit)
         .fetch_all(&state.pool)
         .await?
     };
 
-    let messages: Vec<MessageWithAuthor> = rows
-        .into_iter()
-        .map(|r| MessageWithAuthor {
-            id: r.id,
-            channel_id: r.channel_id,
-            author: ProfileSummary {
-                id: r.author_id,
-                username: r.author_username,
-                display_name: r.author_display_name,
-                avatar_url: r.author_avatar_url,
-            },
-            content: r.content,
-            created_at: r.created_at,
-        })
-        .collect();
+    let mut messages: Vec<MessageWithAuthor> = rows.into_iter().map(Into::into).collect();
+    hydrate(&state, &mut messages, auth.user_id).await?;
 
     Ok(Json(messages))
 }
 
+/// Post a message (optionally replying to another message in the same
+/// channel, and carrying the author's uploads from `attachments`), broadcast
+/// `MessageCreate` and send `MentionCreate` to everyone it mentions. Ends
+/// the author's typing indicator in the channel.
+///
+/// Used by the gateway; callers must already have verified `VIEW_CHANNEL`
+/// and `SEND_MESSAGES`. Posting in a thread also bumps the thread.
+pub async fn create_message(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Uuid,
+    content: String,
+    reply_to: Option<Uuid>,
+    attachment_ids: Vec<Uuid>,
+) -> AppResult<MessageWithAuthor> {
+    if content.trim().is_empty() && attachment_ids.is_empty() {
+        return Err(AppError::BadRequest("Message content cannot be empty".into()));
+    }
+    attachments::check_count(&attachment_ids)?;
+
+    if let Some(reply_to) = reply_to {
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE id = $1 AND channel_id = $2 AND deleted_at IS NULL)"
+        )
+        .bind(reply_to)
+        .bind(channel_id)
+        .fetch_one(&state.pool)
+        .await?;
+
+        if !exists {
+            return Err(AppError::NotFound("Referenced message not found".into()));
+        }
+    }
+
+    let channel = access::load_channel(&state.pool, channel_id).await?;
+    let mentioned = mentions::resolve(state, &channel, user_id, &mentions::parse(&content)).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let message_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO messages (channel_id, author_id, content, reply_to_id, mention_everyone, mention_role_ids)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .bind(&content)
+    .bind(reply_to)
+    .bind(mentioned.everyone)
+    .bind(&mentioned.roles)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    attachments::claim(&mut *tx, MessageKind::Channel, channel_id, user_id, message_id, &attachment_ids).await?;
+    mentions::store(&mut *tx, message_id, &mentioned).await?;
+    read_states::advance(&mut *tx, MessageKind::Channel, user_id, channel_id, message_id).await?;
+    let unarchived = threads::record_activity(&mut *tx, channel_id, user_id).await?;
+
+    tx.commit().await?;
+    read_states::sync_own_message(state, MessageKind::Channel, user_id, channel_id).await;
+    if unarchived {
+        threads::broadcast_unarchived(state, channel_id).await;
+    }
+
+    let message = fetch_message(state, message_id, user_id).await?;
+
+    state.ws_state.broadcast_to_channel(&channel_id, WsEvent::MessageCreate {
+        id: message.id,
+        channel_id,
+        author: message.author.clone(),
+        content: message.content.clone(),
+        created_at: message.created_at.to_rfc3339(),
+        reply_to: message.reply_to.clone(),
+        attachments: message.attachments.clone(),
+        mentions: message.mentions.clone(),
+        mention_roles: message.mention_roles.clone(),
+        mention_everyone: message.mention_everyone,
+    });
+    state.ws_state.typing_stop(user_id, channel_id);
+
+    // Reaches mentioned users even when they aren't subscribed to the channel
+    let event = WsEvent::MentionCreate { server_id: channel.server_id, message: message.clone() };
+    for user_id in &mentioned.recipients {
+        state.ws_state.send_to_user(user_id, &event);
+    }
+
+    Ok(message)
+}
+
+/// PATCH /api/v1/channels/:id/messages/:msg_id
+pub async fn update_message(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(body): Json<UpdateMessageRequest>,
+) -> AppResult<Json<MessageWithAuthor>> {
+    access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+    )
+    .await?;
+
+    let message = edit_message(&state, auth.user_id, channel_id, message_id, body.content).await?;
+    Ok(Json(message))
+}
+
+/// Edit a channel message as its author and broadcast `MessageUpdate`.
+///
+/// Shared by the REST endpoint and the gateway; callers must already have
+/// verified `VIEW_CHANNEL` and `SEND_MESSAGES` (so timed-out members can't
+/// edit).
+pub async fn edit_message(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Uuid,
+    message_id: Uuid,
+    content: String,
+) -> AppResult<MessageWithAuthor> {
+    if content.trim().is_empty() {
+        return Err(AppError::BadRequest("Message content cannot be empty".into()));
+    }
+
+    let existing = sqlx::query_as::<_, Message>(
+        "SELECT * FROM messages WHERE id = $1 AND channel_id = $2 AND deleted_at IS NULL"
+    )
+    .bind(message_id)
+    .bind(channel_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Message not found".into()))?;
+
+    if existing.author_id != user_id {
+        return Err(AppError::Forbidden("You can only edit your own messages".into()));
+    }
+
+    let edited_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+        "UPDATE messages SET content = $2, updated_at = now() WHERE id = $1 RETURNING updated_at"
+    )
+    .bind(message_id)
+    .bind(&content)
+    .fetch_one(&state.pool)
+    .await?;
+
+    state.ws_state.broadcast_to_channel(&channel_id, WsEvent::MessageUpdate {
+        id: message_id,
+        channel_id,
+        content,
+        edited_at: edited_at.to_rfc3339(),
+    });
+
+    fetch_message(state, message_id, user_id).await
+}
+
+/// DELETE /api/v1/channels/:id/messages/:msg_id
+pub async fn delete_message(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+
+    tombstone_message(&state, auth.user_id, channel_id, message_id, reason).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a channel message and broadcast `MessageDelete`.
+///
+/// Authors can always delete their own messages; members with
+/// `MANAGE_MESSAGES` can delete anyone's, which is audit-logged with `reason`.
+/// Callers must already have verified `VIEW_CHANNEL`.
+pub async fn tombstone_message(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Uuid,
+    message_id: Uuid,
+    reason: Option<String>,
+) -> AppResult<()> {
+    let existing = sqlx::query_as::<_, Message>(
+        "SELECT * FROM messages WHERE id = $1 AND channel_id = $2 AND deleted_at IS NULL"
+    )
+    .bind(message_id)
+    .bind(channel_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Message not found".into()))?;
+
+    let moderated_in = if existing.author_id != user_id {
+        Some(access::require_channel_permission(&state.pool, channel_id, user_id, Permissions::MANAGE_MESSAGES).await?)
+    } else {
+        None
+    };
+
+    let mut tx = state.pool.begin().await?;
+
+    sqlx::query("UPDATE messages SET content = '', deleted_at = now() WHERE id = $1")
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await?;
+
+    if let Some(channel) = moderated_in {
+        AuditEntry::new(channel.server_id, user_id, AuditAction::MessageDelete)
+            .target(message_id)
+            .removed("channel_id", channel_id)
+            .removed("author_id", existing.author_id)
+            .reason(reason)
+            .record(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    state.ws_state.broadcast_to_channel(&channel_id, WsEvent::MessageDelete {
+        id: message_id,
+        channel_id,
+    });
+
+    Ok(())
+}
+
+/// POST /api/v1/channels/:id/messages/bulk-delete — requires `MANAGE_MESSAGES`, up to 100 ids
+pub async fn bulk_delete_messages(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(channel_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+    Json(body): Json<BulkDeleteMessagesRequest>,
+) -> AppResult<StatusCode> {
+    let channel = access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::MANAGE_MESSAGES,
+    )
+    .await?;
+
+    if body.message_ids.is_empty() || body.message_ids.len() > MAX_BULK_DELETE {
+        return Err(AppError::BadRequest(format!(
+            "Between 1 and {MAX_BULK_DELETE} message ids are required"
+        )));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let ids = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE messages SET content = '', deleted_at = now()
+        WHERE channel_id = $1 AND id = ANY($2) AND deleted_at IS NULL
+        RETURNING id
+        "#,
+    )
+    .bind(channel_id)
+    .bind(&body.message_ids)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if !ids.is_empty() {
+        AuditEntry::new(channel.server_id, auth.user_id, AuditAction::MessageBulkDelete)
+            .target(channel_id)
+            .removed("message_ids", &ids)
+            .reason(reason)
+            .record(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    if !ids.is_empty() {
+        state.ws_state.broadcast_to_channel(&channel_id, WsEvent::MessageDeleteBulk { ids, channel_id });
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Load a single message with its author, attachments and reactions as seen by `viewer`.
+async fn fetch_message(state: &AppState, message_id: Uuid, viewer: Uuid) -> AppResult<MessageWithAuthor> {
+    let row = sqlx::query_as::<_, MessageRow>(&format!(
+        "{MESSAGE_SELECT} WHERE m.id = $1 AND m.deleted_at IS NULL"
+    ))
+    .bind(message_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Message not found".into()))?;
+
+    let mut messages = vec![MessageWithAuthor::from(row)];
+    hydrate(state, &mut messages, viewer).await?;
+
+    Ok(messages.remove(0))
+}
+
+/// Load messages by id with their authors, attachments and reactions as seen
+/// by `viewer`, in the order of `message_ids`. Deleted or unknown ids are skipped.
+pub async fn load_messages(state: &AppState, message_ids: &[Uuid], viewer: Uuid) -> AppResult<Vec<MessageWithAuthor>> {
+    let rows = sqlx::query_as::<_, MessageRow>(&format!(
+        "{MESSAGE_SELECT} WHERE m.id = ANY($1) AND m.deleted_at IS NULL"
+    ))
+    .bind(message_ids)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut by_id: HashMap<Uuid, MessageWithAuthor> = rows.into_iter().map(|r| (r.id, r.into())).collect();
+    let mut messages: Vec<MessageWithAuthor> = message_ids.iter().filter_map(|id| by_id.remove(id)).collect();
+    hydrate(state, &mut messages, viewer).await?;
+
+    Ok(messages)
+}
+
+/// Fill in attachments, mentioned users and `viewer`'s view of reactions for
+/// loaded messages.
+async fn hydrate(state: &AppState, messages: &mut [MessageWithAuthor], viewer: Uuid) -> AppResult<()> {
+    let ids: Vec<Uuid> = messages.iter().map(|m| m.id).collect();
+    let mut files = attachments::load_for(state, MessageKind::Channel, &ids).await?;
+    let mut counts = reactions::load_counts(&state.pool, MessageKind::Channel, &ids, viewer).await?;
+    let mut mentioned = mentions::load_for(&state.pool, &ids).await?;
+
+    for message in messages {
+        message.attachments = files.remove(&message.id).unwrap_or_default();
+        message.reactions = counts.remove(&message.id).unwrap_or_default();
+        message.mentions = mentioned.remove(&message.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
 /// GET /api/v1/channels/:id/voice-state
 pub async fn get_voice_state(
-    _auth: AuthUser,
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(channel_id): Path<Uuid>,
 ) -> AppResult<Json<Vec<VoiceState>>> {
+    access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+
     let states = sqlx::query_as::<_, VoiceState>(
         "SELECT * FROM voice_states WHERE channel_id = $1"
     )
@@ -1,22 +1,36 @@
-/// DM REST handlers: list DM channels, create/find DM, get DM messages
+//! DM REST handlers: list DM channels, create/find DM, get / send / edit / delete DM messages
+
+use std::collections::HashMap;
 
 use axum::extract::{Path, State, Query};
+use axum::http::StatusCode;
 use axum::Json;
+use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::AppState;
-use crate::auth::AuthUser;
-use crate::error::AppResult;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::handlers::attachments;
+use crate::handlers::reactions;
+use crate::handlers::read_states;
 use crate::models::{
-    DmChannel, CreateDmRequest, DmChannelSummary, DmMessageWithAuthor,
-    MessageQuery, ProfileSummary,
+    DmChannel, CreateDmRequest, DmChannelSummary, DmMessage, DmMessageWithAuthor,
+    MessageKind, MessageQuery, ProfileSummary, UpdateMessageRequest,
 };
+use crate::ws::events::WsEvent;
 
 /// GET /api/v1/dms — list DM channels for the authenticated user
 pub async fn list_dms(
     auth: AuthUser,
     State(state): State<AppState>,
 ) -> AppResult<Json<Vec<DmChannelSummary>>> {
+    let summaries = dm_summaries(&state.pool, auth.user_id).await?;
+    Ok(Json(summaries))
+}
+
+/// The user's DMs, most recently active first.
+pub async fn dm_summaries(pool: &PgPool, user_id: Uuid) -> AppResult<Vec<DmChannelSummary>> {
     // Get all DM channels the user is part of, with the other user's profile
     // and last message info
     let rows = sqlx::query_as::<_, DmSummaryRow>(
@@ -29,12 +43,12 @@ pub async fn list_dms(
             p.avatar_url as other_avatar_url,
             (
                 SELECT content FROM dm_messages
-                WHERE dm_channel_id = dc.id
+                WHERE dm_channel_id = dc.id AND deleted_at IS NULL
                 ORDER BY created_at DESC LIMIT 1
             ) as last_message,
             (
                 SELECT created_at FROM dm_messages
-                WHERE dm_channel_id = dc.id
+                WHERE dm_channel_id = dc.id AND deleted_at IS NULL
                 ORDER BY created_at DESC LIMIT 1
             ) as last_message_at
         FROM dm_channels dc
@@ -44,27 +58,33 @@ pub async fn list_dms(
         ORDER BY last_message_at DESC NULLS LAST
         "#,
     )
-    .bind(auth.user_id)
-    .fetch_all(&state.pool)
+    .bind(user_id)
+    .fetch_all(pool)
     .await?;
 
+    let mut read_states = read_states::dm_read_states(pool, user_id, None).await?;
+
     let summaries = rows
         .into_iter()
-        .map(|r| DmChannelSummary {
-            id: r.dm_channel_id,
-            other_user: ProfileSummary {
-                id: r.other_user_id,
-                username: r.other_username,
-                display_name: r.other_display_name,
-                avatar_url: r.other_avatar_url,
-            },
-            last_message: r.last_message,
-            last_message_at: r.last_message_at,
-            unread_count: 0, // TODO: implement unread tracking
+        .map(|r| {
+            let read_state = read_states.remove(&r.dm_channel_id);
+            DmChannelSummary {
+                id: r.dm_channel_id,
+                other_user: ProfileSummary {
+                    id: r.other_user_id,
+                    username: r.other_username,
+                    display_name: r.other_display_name,
+                    avatar_url: r.other_avatar_url,
+                },
+                last_message: r.last_message,
+                last_message_at: r.last_message_at,
+                unread_count: read_state.as_ref().map_or(0, |rs| rs.unread_count),
+                mention_count: read_state.as_ref().map_or(0, |rs| rs.mention_count),
+            }
         })
         .collect();
 
-    Ok(Json(summaries))
+    Ok(summaries)
 }
 
 #[derive(sqlx::FromRow)]
@@ -132,28 +152,51 @@ struct DmMessageRow {
     author_username: Option<String>,
     author_display_name: String,
     author_avatar_url: Option<String>,
+    updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<DmMessageRow> for DmMessageWithAuthor {
+    fn from(r: DmMessageRow) -> Self {
+        DmMessageWithAuthor {
+            id: r.id,
+            dm_channel_id: r.dm_channel_id,
+            author: ProfileSummary {
+                id: r.author_id,
+                username: r.author_username,
+                display_name: r.author_display_name,
+                avatar_url: r.author_avatar_url,
+            },
+            content: r.content,
+            created_at: r.created_at,
+            edited_at: r.updated_at,
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+        }
+    }
 }
 
 /// GET /api/v1/dms/:id/messages?before=&limit=
 pub async fn get_dm_messages(
-    _auth: AuthUser,
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(dm_channel_id): Path<Uuid>,
     Query(q): Query<MessageQuery>,
 ) -> AppResult<Json<Vec<DmMessageWithAuthor>>> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
     let limit = q.limit.unwrap_or(50).min(100);
 
     let rows = if let Some(before_id) = q.before {
         sqlx::query_as::<_, DmMessageRow>(
             r#"
             SELECT
-                m.id, m.dm_channel_id, m.content, m.created_at,
+                m.id, m.dm_channel_id, m.content, m.created_at, m.updated_at,
                 p.id as author_id, p.username as author_username,
                 p.display_name as author_display_name,
                 p.avatar_url as author_avatar_url
             FROM dm_messages m
             INNER JOIN profiles p ON p.id = m.author_id
-            WHERE m.dm_channel_id = $1
+            WHERE m.dm_channel_id = $1 AND m.deleted_at IS NULL
               AND m.created_at < (SELECT created_at FROM dm_messages WHERE id = $2)
             ORDER BY m.created_at DESC
             LIMIT $3
@@ -168,13 +211,13 @@ pub async fn get_dm_messages(
         sqlx::query_as::<_, DmMessageRow>(
             r#"
             SELECT
-                m.id, m.dm_channel_id, m.content, m.created_at,
+                m.id, m.dm_channel_id, m.content, m.created_at, m.updated_at,
                 p.id as author_id, p.username as author_username,
                 p.display_name as author_display_name,
                 p.avatar_url as author_avatar_url
             FROM dm_messages m
             INNER JOIN profiles p ON p.id = m.author_id
-            WHERE m.dm_channel_id = $1
+            WHERE m.dm_channel_id = $1 AND m.deleted_at IS NULL
             ORDER BY m.created_at DESC
             LIMIT $2
             "#,
@@ -185,21 +228,242 @@ pub async fn get_dm_messages(
         .await?
     };
 
-    let messages = rows
-        .into_iter()
-        .map(|r| DmMessageWithAuthor {
-            id: r.id,
-            dm_channel_id: r.dm_channel_id,
-            author: ProfileSummary {
-                id: r.author_id,
-                username: r.author_username,
-                display_name: r.author_display_name,
-                avatar_url: r.author_avatar_url,
-            },
-            content: r.content,
-            created_at: r.created_at,
-        })
-        .collect();
+    let mut messages: Vec<DmMessageWithAuthor> = rows.into_iter().map(Into::into).collect();
+    hydrate(&state, &mut messages, auth.user_id).await?;
 
     Ok(Json(messages))
 }
+
+/// Send a DM (carrying the author's uploads from `attachments`) and
+/// broadcast `DmCreate`, ending the author's typing indicator in it.
+///
+/// Used by the gateway; callers must already have verified DM membership.
+/// The event is also pushed straight to the other participants, in case
+/// they haven't subscribed to this DM yet.
+pub async fn create_dm_message(
+    state: &AppState,
+    user_id: Uuid,
+    dm_channel_id: Uuid,
+    content: String,
+    attachment_ids: Vec<Uuid>,
+) -> AppResult<DmMessageWithAuthor> {
+    if content.trim().is_empty() && attachment_ids.is_empty() {
+        return Err(AppError::BadRequest("Message content cannot be empty".into()));
+    }
+    attachments::check_count(&attachment_ids)?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let message_id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO dm_messages (dm_channel_id, author_id, content) VALUES ($1, $2, $3) RETURNING id"
+    )
+    .bind(dm_channel_id)
+    .bind(user_id)
+    .bind(&content)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    attachments::claim(&mut *tx, MessageKind::Dm, dm_channel_id, user_id, message_id, &attachment_ids).await?;
+    read_states::advance(&mut *tx, MessageKind::Dm, user_id, dm_channel_id, message_id).await?;
+
+    tx.commit().await?;
+    read_states::sync_own_message(state, MessageKind::Dm, user_id, dm_channel_id).await;
+
+    let message = fetch_dm_message(state, message_id, user_id).await?;
+
+    let event = WsEvent::DmCreate {
+        id: message.id,
+        dm_channel_id,
+        author: message.author.clone(),
+        content: message.content.clone(),
+        created_at: message.created_at.to_rfc3339(),
+        attachments: message.attachments.clone(),
+    };
+    state.ws_state.broadcast_to_channel(&dm_channel_id, event.clone());
+    state.ws_state.typing_stop(user_id, dm_channel_id);
+
+    let others = sqlx::query_scalar::<_, Uuid>(
+        "SELECT user_id FROM dm_members WHERE dm_channel_id = $1 AND user_id != $2"
+    )
+    .bind(dm_channel_id)
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    for member_id in others {
+        state.ws_state.send_to_user(&member_id, &event);
+    }
+
+    Ok(message)
+}
+
+/// PATCH /api/v1/dms/:id/messages/:msg_id
+pub async fn update_dm_message(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((dm_channel_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(body): Json<UpdateMessageRequest>,
+) -> AppResult<Json<DmMessageWithAuthor>> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    let message = edit_dm_message(&state, auth.user_id, dm_channel_id, message_id, body.content).await?;
+    Ok(Json(message))
+}
+
+/// Edit a DM as its author and broadcast `DmUpdate`.
+///
+/// Shared by the REST endpoint and the gateway; callers must already have
+/// verified DM membership.
+pub async fn edit_dm_message(
+    state: &AppState,
+    user_id: Uuid,
+    dm_channel_id: Uuid,
+    message_id: Uuid,
+    content: String,
+) -> AppResult<DmMessageWithAuthor> {
+    if content.trim().is_empty() {
+        return Err(AppError::BadRequest("Message content cannot be empty".into()));
+    }
+
+    let existing = sqlx::query_as::<_, DmMessage>(
+        "SELECT * FROM dm_messages WHERE id = $1 AND dm_channel_id = $2 AND deleted_at IS NULL"
+    )
+    .bind(message_id)
+    .bind(dm_channel_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Message not found".into()))?;
+
+    if existing.author_id != user_id {
+        return Err(AppError::Forbidden("You can only edit your own messages".into()));
+    }
+
+    let edited_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+        "UPDATE dm_messages SET content = $2, updated_at = now() WHERE id = $1 RETURNING updated_at"
+    )
+    .bind(message_id)
+    .bind(&content)
+    .fetch_one(&state.pool)
+    .await?;
+
+    state.ws_state.broadcast_to_channel(&dm_channel_id, WsEvent::DmUpdate {
+        id: message_id,
+        dm_channel_id,
+        content,
+        edited_at: edited_at.to_rfc3339(),
+    });
+
+    fetch_dm_message(state, message_id, user_id).await
+}
+
+/// DELETE /api/v1/dms/:id/messages/:msg_id
+pub async fn delete_dm_message(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((dm_channel_id, message_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<StatusCode> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    tombstone_dm_message(&state, auth.user_id, dm_channel_id, message_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a DM as its author and broadcast `DmDelete`.
+///
+/// DMs have no moderators, so only the author may delete. Callers must
+/// already have verified DM membership.
+pub async fn tombstone_dm_message(
+    state: &AppState,
+    user_id: Uuid,
+    dm_channel_id: Uuid,
+    message_id: Uuid,
+) -> AppResult<()> {
+    let existing = sqlx::query_as::<_, DmMessage>(
+        "SELECT * FROM dm_messages WHERE id = $1 AND dm_channel_id = $2 AND deleted_at IS NULL"
+    )
+    .bind(message_id)
+    .bind(dm_channel_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Message not found".into()))?;
+
+    if existing.author_id != user_id {
+        return Err(AppError::Forbidden("You can only delete your own messages".into()));
+    }
+
+    sqlx::query("UPDATE dm_messages SET content = '', deleted_at = now() WHERE id = $1")
+        .bind(message_id)
+        .execute(&state.pool)
+        .await?;
+
+    state.ws_state.broadcast_to_channel(&dm_channel_id, WsEvent::DmDelete {
+        id: message_id,
+        dm_channel_id,
+    });
+
+    Ok(())
+}
+
+/// Load a single DM with its author, attachments and reactions as seen by `viewer`.
+async fn fetch_dm_message(state: &AppState, message_id: Uuid, viewer: Uuid) -> AppResult<DmMessageWithAuthor> {
+    let row = sqlx::query_as::<_, DmMessageRow>(
+        r#"
+        SELECT
+            m.id, m.dm_channel_id, m.content, m.created_at, m.updated_at,
+            p.id as author_id, p.username as author_username,
+            p.display_name as author_display_name,
+            p.avatar_url as author_avatar_url
+        FROM dm_messages m
+        INNER JOIN profiles p ON p.id = m.author_id
+        WHERE m.id = $1 AND m.deleted_at IS NULL
+        "#,
+    )
+    .bind(message_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Message not found".into()))?;
+
+    let mut messages = vec![DmMessageWithAuthor::from(row)];
+    hydrate(state, &mut messages, viewer).await?;
+
+    Ok(messages.remove(0))
+}
+
+/// Load DMs by id with their authors, attachments and reactions as seen by
+/// `viewer`, in the order of `message_ids`. Deleted or unknown ids are skipped.
+pub async fn load_dm_messages(state: &AppState, message_ids: &[Uuid], viewer: Uuid) -> AppResult<Vec<DmMessageWithAuthor>> {
+    let rows = sqlx::query_as::<_, DmMessageRow>(
+        r#"
+        SELECT
+            m.id, m.dm_channel_id, m.content, m.created_at, m.updated_at,
+            p.id as author_id, p.username as author_username,
+            p.display_name as author_display_name,
+            p.avatar_url as author_avatar_url
+        FROM dm_messages m
+        INNER JOIN profiles p ON p.id = m.author_id
+        WHERE m.id = ANY($1) AND m.deleted_at IS NULL
+        "#,
+    )
+    .bind(message_ids)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut by_id: HashMap<Uuid, DmMessageWithAuthor> = rows.into_iter().map(|r| (r.id, r.into())).collect();
+    let mut messages: Vec<DmMessageWithAuthor> = message_ids.iter().filter_map(|id| by_id.remove(id)).collect();
+    hydrate(state, &mut messages, viewer).await?;
+
+    Ok(messages)
+}
+
+/// Fill in attachments and `viewer`'s view of reactions for loaded DMs.
+async fn hydrate(state: &AppState, messages: &mut [DmMessageWithAuthor], viewer: Uuid) -> AppResult<()> {
+    let ids: Vec<Uuid> = messages.iter().map(|m| m.id).collect();
+    let mut files = attachments::load_for(state, MessageKind::Dm, &ids).await?;
+    let mut counts = reactions::load_counts(&state.pool, MessageKind::Dm, &ids, viewer).await?;
+
+    for message in messages {
+        message.attachments = files.remove(&message.id).unwrap_or_default();
+        message.reactions = counts.remove(&message.id).unwrap_or_default();
+    }
+    Ok(())
+}
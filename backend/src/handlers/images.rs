@@ -0,0 +1,193 @@
+//! Image upload handlers: user avatars and profile banners, server icons and banners
+//!
+//! Each endpoint takes a multipart body with one `file` field, runs it
+//! through `images::store` and saves the default variant's URL. Removing an
+//! avatar restores the default emoji avatar; removing a server icon falls back
+//! to the server's emoji `icon`.
+
+use axum::extract::{Multipart, Path, State};
+use axum::Json;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::audit::{AuditEntry, AuditReason};
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::images::{self, ImageKind};
+use crate::models::{AuditAction, Profile, Server};
+use crate::permissions::Permissions;
+
+/// Avatar given to new profiles (see the `handle_new_user` trigger).
+const DEFAULT_AVATAR: &str = "😎";
+
+/// PUT /api/v1/auth/me/avatar
+pub async fn upload_avatar(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<Profile>> {
+    let body = read_file(multipart).await?;
+    let url = images::store(&state, ImageKind::Avatar, body).await?;
+
+    let profile = set_profile_image(&state, auth.user_id, "avatar_url", Some(&url)).await?;
+    Ok(Json(profile))
+}
+
+/// DELETE /api/v1/auth/me/avatar — back to the default emoji avatar
+pub async fn delete_avatar(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> AppResult<Json<Profile>> {
+    let profile = set_profile_image(&state, auth.user_id, "avatar_url", Some(DEFAULT_AVATAR)).await?;
+    Ok(Json(profile))
+}
+
+/// PUT /api/v1/auth/me/banner
+pub async fn upload_profile_banner(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<Profile>> {
+    let body = read_file(multipart).await?;
+    let url = images::store(&state, ImageKind::ProfileBanner, body).await?;
+
+    let profile = set_profile_image(&state, auth.user_id, "banner_url", Some(&url)).await?;
+    Ok(Json(profile))
+}
+
+/// DELETE /api/v1/auth/me/banner
+pub async fn delete_profile_banner(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> AppResult<Json<Profile>> {
+    let profile = set_profile_image(&state, auth.user_id, "banner_url", None).await?;
+    Ok(Json(profile))
+}
+
+/// PUT /api/v1/servers/:id/icon — requires `MANAGE_SERVER`
+pub async fn upload_server_icon(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+    multipart: Multipart,
+) -> AppResult<Json<Server>> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_SERVER).await?;
+
+    let body = read_file(multipart).await?;
+    let url = images::store(&state, ImageKind::ServerIcon, body).await?;
+
+    let server = set_server_image(&state, server_id, auth.user_id, "icon_url", Some(url), reason).await?;
+    Ok(Json(server))
+}
+
+/// DELETE /api/v1/servers/:id/icon — requires `MANAGE_SERVER`; the emoji icon shows again
+pub async fn delete_server_icon(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<Json<Server>> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_SERVER).await?;
+
+    let server = set_server_image(&state, server_id, auth.user_id, "icon_url", None, reason).await?;
+    Ok(Json(server))
+}
+
+/// PUT /api/v1/servers/:id/banner — requires `MANAGE_SERVER`
+pub async fn upload_server_banner(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+    multipart: Multipart,
+) -> AppResult<Json<Server>> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_SERVER).await?;
+
+    let body = read_file(multipart).await?;
+    let url = images::store(&state, ImageKind::ServerBanner, body).await?;
+
+    let server = set_server_image(&state, server_id, auth.user_id, "banner_url", Some(url), reason).await?;
+    Ok(Json(server))
+}
+
+/// DELETE /api/v1/servers/:id/banner — requires `MANAGE_SERVER`
+pub async fn delete_server_banner(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<Json<Server>> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_SERVER).await?;
+
+    let server = set_server_image(&state, server_id, auth.user_id, "banner_url", None, reason).await?;
+    Ok(Json(server))
+}
+
+/// Read the `file` field of a multipart upload.
+async fn read_file(mut multipart: Multipart) -> AppResult<Vec<u8>> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::BadRequest(e.body_text()))?
+    {
+        if field.name() == Some("file") {
+            let body = field.bytes().await.map_err(|e| AppError::BadRequest(e.body_text()))?;
+            return Ok(body.to_vec());
+        }
+    }
+
+    Err(AppError::BadRequest("Missing `file` field".into()))
+}
+
+/// Set one of the profile's image columns (`column` is never user input).
+async fn set_profile_image(state: &AppState, user_id: Uuid, column: &str, url: Option<&str>) -> AppResult<Profile> {
+    let profile = sqlx::query_as::<_, Profile>(&format!(
+        "UPDATE profiles SET {column} = $2, updated_at = now() WHERE id = $1 RETURNING *"
+    ))
+    .bind(user_id)
+    .bind(url)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Profile not found".into()))?;
+
+    Ok(profile)
+}
+
+/// Set one of the server's image columns and audit-log the change.
+async fn set_server_image(
+    state: &AppState,
+    server_id: Uuid,
+    actor_id: Uuid,
+    column: &str,
+    url: Option<String>,
+    reason: Option<String>,
+) -> AppResult<Server> {
+    let mut tx = state.pool.begin().await?;
+
+    let old = sqlx::query_scalar::<_, Option<String>>(&format!(
+        "SELECT {column} FROM servers WHERE id = $1 FOR UPDATE"
+    ))
+    .bind(server_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Server not found".into()))?;
+
+    let server = sqlx::query_as::<_, Server>(&format!(
+        "UPDATE servers SET {column} = $2 WHERE id = $1 RETURNING *"
+    ))
+    .bind(server_id)
+    .bind(&url)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    AuditEntry::new(server_id, actor_id, AuditAction::ServerUpdate)
+        .target(server_id)
+        .change(column, old, url)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(server)
+}
@@ -0,0 +1,345 @@
+//! Invite REST handlers: create / list / revoke invites, preview and accept by code
+//!
+//! Invites are the only way into a private server; they also work for public
+//! ones. An invite stops working once it expires or reaches `max_uses`.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{TimeDelta, Utc};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::audit::{AuditEntry, AuditReason};
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AuditAction, CreateInviteRequest, Invite, InvitePreview, ProfileSummary, Server, ServerMember,
+    ServerWithMemberCount,
+};
+use crate::permissions::Permissions;
+
+/// Length of generated invite codes.
+const INVITE_CODE_LEN: usize = 8;
+
+/// Unambiguous characters used in invite codes (no 0/O, 1/l/I).
+const INVITE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789";
+
+/// Longest an expiring invite may last (7 days).
+const MAX_INVITE_AGE_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// How many fresh codes to try before giving up on a collision streak.
+const INVITE_CODE_ATTEMPTS: usize = 5;
+
+/// POST /api/v1/servers/:id/invites
+pub async fn create_invite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+    Json(body): Json<CreateInviteRequest>,
+) -> AppResult<Json<Invite>> {
+    match body.channel_id {
+        Some(channel_id) => {
+            let channel = access::require_channel_permission(
+                &state.pool,
+                channel_id,
+                auth.user_id,
+                Permissions::VIEW_CHANNEL | Permissions::CREATE_INVITE,
+            )
+            .await?;
+            if channel.server_id != server_id {
+                return Err(AppError::BadRequest("Channel does not belong to this server".into()));
+            }
+        }
+        None => {
+            access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::CREATE_INVITE).await?;
+        }
+    }
+
+    let max_uses = body.max_uses.filter(|n| *n != 0);
+    if max_uses.is_some_and(|n| n < 0) {
+        return Err(AppError::BadRequest("max_uses cannot be negative".into()));
+    }
+
+    let expires_at = match body.max_age_secs.filter(|n| *n != 0) {
+        Some(secs) if secs < 0 => {
+            return Err(AppError::BadRequest("max_age_secs cannot be negative".into()));
+        }
+        Some(secs) if secs > MAX_INVITE_AGE_SECS => {
+            return Err(AppError::BadRequest(format!("max_age_secs cannot exceed {MAX_INVITE_AGE_SECS}")));
+        }
+        Some(secs) => {
+            let expires_at = TimeDelta::try_seconds(secs).and_then(|age| Utc::now().checked_add_signed(age));
+            Some(expires_at.ok_or_else(|| AppError::BadRequest("max_age_secs is out of range".into()))?)
+        }
+        None => None,
+    };
+
+    let mut tx = state.pool.begin().await?;
+
+    // A clash with an existing code inserts nothing; draw another one
+    let mut invite = None;
+    for _ in 0..INVITE_CODE_ATTEMPTS {
+        invite = sqlx::query_as::<_, Invite>(
+            r#"
+            INSERT INTO invites (code, server_id, channel_id, inviter_id, max_uses, expires_at, temporary)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (code) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(generate_code())
+        .bind(server_id)
+        .bind(body.channel_id)
+        .bind(auth.user_id)
+        .bind(max_uses)
+        .bind(expires_at)
+        .bind(body.temporary)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if invite.is_some() {
+            break;
+        }
+    }
+    let invite = invite.ok_or_else(|| AppError::Internal("Could not generate a unique invite code".into()))?;
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::InviteCreate)
+        .created("code", &invite.code)
+        .created("channel_id", invite.channel_id)
+        .created("max_uses", invite.max_uses)
+        .created("expires_at", invite.expires_at)
+        .created("temporary", invite.temporary)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Json(invite))
+}
+
+/// GET /api/v1/servers/:id/invites — requires `MANAGE_SERVER`
+pub async fn list_invites(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+) -> AppResult<Json<Vec<Invite>>> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_SERVER).await?;
+
+    let invites = sqlx::query_as::<_, Invite>(
+        "SELECT * FROM invites WHERE server_id = $1 ORDER BY created_at DESC"
+    )
+    .bind(server_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(invites))
+}
+
+/// DELETE /api/v1/invites/:code — the inviter or anyone with `MANAGE_SERVER`
+pub async fn revoke_invite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    let invite = sqlx::query_as::<_, Invite>("SELECT * FROM invites WHERE code = $1")
+        .bind(&code)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Invite not found".into()))?;
+
+    if invite.inviter_id != Some(auth.user_id) {
+        access::require_server_permission(&state.pool, invite.server_id, auth.user_id, Permissions::MANAGE_SERVER).await?;
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    sqlx::query("DELETE FROM invites WHERE code = $1")
+        .bind(&code)
+        .execute(&mut *tx)
+        .await?;
+
+    AuditEntry::new(invite.server_id, auth.user_id, AuditAction::InviteDelete)
+        .removed("code", &invite.code)
+        .removed("channel_id", invite.channel_id)
+        .removed("inviter_id", invite.inviter_id)
+        .removed("uses", invite.uses)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/invites/:code — preview the server before joining
+pub async fn get_invite(
+    _auth: AuthUser,
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> AppResult<Json<InvitePreview>> {
+    let invite = fetch_usable_invite(&state.pool, &code).await?;
+
+    let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(invite.server_id)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let member_count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM server_members WHERE server_id = $1"
+    )
+    .bind(invite.server_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    let channel_name = match invite.channel_id {
+        Some(channel_id) => sqlx::query_scalar::<_, String>("SELECT name FROM channels WHERE id = $1")
+            .bind(channel_id)
+            .fetch_optional(&state.pool)
+            .await?,
+        None => None,
+    };
+
+    let inviter = match invite.inviter_id {
+        Some(inviter_id) => sqlx::query_as::<_, ProfileSummary>(
+            "SELECT id, username, display_name, avatar_url FROM profiles WHERE id = $1"
+        )
+        .bind(inviter_id)
+        .fetch_optional(&state.pool)
+        .await?,
+        None => None,
+    };
+
+    Ok(Json(InvitePreview {
+        code: invite.code,
+        server: ServerWithMemberCount { server, member_count },
+        channel_id: invite.channel_id,
+        channel_name,
+        inviter,
+        expires_at: invite.expires_at,
+        temporary: invite.temporary,
+    }))
+}
+
+/// POST /api/v1/invites/:code/accept — join the invite's server
+pub async fn accept_invite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> AppResult<Json<ServerMember>> {
+    let invite = fetch_usable_invite(&state.pool, &code).await?;
+    access::require_not_banned(&state.pool, invite.server_id, auth.user_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let member = sqlx::query_as::<_, ServerMember>(
+        r#"
+        INSERT INTO server_members (server_id, user_id, temporary)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (server_id, user_id) DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(invite.server_id)
+    .bind(auth.user_id)
+    .bind(invite.temporary)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Already a member".into()))?;
+
+    // Claim a use atomically so concurrent accepts can't exceed max_uses
+    let claimed = sqlx::query(
+        r#"
+        UPDATE invites SET uses = uses + 1
+        WHERE code = $1
+          AND (max_uses IS NULL OR uses < max_uses)
+          AND (expires_at IS NULL OR expires_at > now())
+        "#,
+    )
+    .bind(&code)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if claimed == 0 {
+        return Err(AppError::NotFound("Invite is invalid or has expired".into()));
+    }
+
+    AuditEntry::new(invite.server_id, auth.user_id, AuditAction::MemberJoin)
+        .target(auth.user_id)
+        .created("invite_code", &invite.code)
+        .created("temporary", invite.temporary)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Json(member))
+}
+
+/// Load an invite that hasn't expired or run out of uses.
+async fn fetch_usable_invite(pool: &PgPool, code: &str) -> AppResult<Invite> {
+    sqlx::query_as::<_, Invite>(
+        r#"
+        SELECT * FROM invites
+        WHERE code = $1
+          AND (max_uses IS NULL OR uses < max_uses)
+          AND (expires_at IS NULL OR expires_at > now())
+        "#,
+    )
+    .bind(code)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Invite is invalid or has expired".into()))
+}
+
+/// Generate a random invite code from the OS CSPRNG.
+///
+/// Bytes at or above the largest multiple of the alphabet size are redrawn,
+/// so every character is equally likely.
+fn generate_code() -> String {
+    let limit = 256 - 256 % INVITE_ALPHABET.len();
+    let mut code = String::with_capacity(INVITE_CODE_LEN);
+    let mut buf = [0u8; INVITE_CODE_LEN * 2];
+    while code.len() < INVITE_CODE_LEN {
+        OsRng.fill_bytes(&mut buf);
+        code.extend(
+            buf.iter()
+                .map(|b| *b as usize)
+                .filter(|b| *b < limit)
+                .map(|b| INVITE_ALPHABET[b % INVITE_ALPHABET.len()] as char)
+                .take(INVITE_CODE_LEN - code.len()),
+        );
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_use_the_alphabet() {
+        for _ in 0..100 {
+            let code = generate_code();
+            assert_eq!(code.len(), INVITE_CODE_LEN);
+            assert!(code.bytes().all(|b| INVITE_ALPHABET.contains(&b)), "{code}");
+        }
+    }
+
+    #[test]
+    fn every_character_can_appear_anywhere() {
+        let mut seen = vec![[false; INVITE_CODE_LEN]; 256];
+        for _ in 0..5000 {
+            for (i, b) in generate_code().bytes().enumerate() {
+                seen[b as usize][i] = true;
+            }
+        }
+        for b in INVITE_ALPHABET {
+            assert!(seen[*b as usize].iter().all(|s| *s), "{} missing somewhere", *b as char);
+        }
+    }
+}
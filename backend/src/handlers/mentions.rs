@@ -0,0 +1,367 @@
+//! Mentions: parsing `<@user_id>`, `<@&role_id>`, `@everyone` and `@here` out
+//! of channel messages, notifying whoever they reach, and the recent-mentions
+//! inbox
+//!
+//! Mentions are resolved once, when the message is sent; editing it doesn't
+//! change who was mentioned. `@everyone` and `@here` need `MENTION_EVERYONE`
+//! in the channel and are plain text otherwise, and `@here` only reaches
+//! members connected to the gateway. Members who can't see the channel are never
+//! notified or listed.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::extract::{Query, State};
+use axum::Json;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::AuthUser;
+use crate::error::AppResult;
+use crate::handlers::channels;
+use crate::models::{Channel, MentionQuery, ProfileSummary, RecentMention};
+use crate::permissions::{self, Permissions};
+
+/// Most distinct users plus roles one message can mention; the rest are
+/// left as plain text.
+const MAX_MENTIONS_PER_MESSAGE: usize = 50;
+
+/// Mentions found in a message's content, before checking them against the server.
+#[derive(Debug, Default)]
+pub struct ParsedMentions {
+    pub users: Vec<Uuid>,
+    pub roles: Vec<Uuid>,
+    pub everyone: bool,
+    pub here: bool,
+}
+
+/// Mentions checked against the server and channel, ready to store.
+#[derive(Debug, Default)]
+pub struct ResolvedMentions {
+    /// Everyone to notify (never the author)
+    pub recipients: Vec<Uuid>,
+    /// Recipients named with `<@id>`
+    pub direct: Vec<Uuid>,
+    /// Roles of this server named with `<@&id>`
+    pub roles: Vec<Uuid>,
+    /// `@everyone` / `@here` was used with permission
+    pub everyone: bool,
+}
+
+/// Find `<@user_id>`, `<@&role_id>`, `@everyone` and `@here` in `content`.
+pub fn parse(content: &str) -> ParsedMentions {
+    let mut parsed = ParsedMentions {
+        everyone: content.contains("@everyone"),
+        here: content.contains("@here"),
+        ..Default::default()
+    };
+
+    let mut total = 0;
+    let mut rest = content;
+    while let Some(start) = rest.find("<@") {
+        rest = &rest[start + 2..];
+        let (is_role, body) = match rest.strip_prefix('&') {
+            Some(body) => (true, body),
+            None => (false, rest),
+        };
+
+        // Only the hyphenated form, e.g. <@00000000-0000-0000-0000-000000000000>
+        let Some(id) = body.get(..36).filter(|_| body[36..].starts_with('>')) else {
+            continue;
+        };
+        let Ok(id) = Uuid::parse_str(id) else {
+            continue;
+        };
+
+        let ids = if is_role { &mut parsed.roles } else { &mut parsed.users };
+        if !ids.contains(&id) && total < MAX_MENTIONS_PER_MESSAGE {
+            ids.push(id);
+            total += 1;
+        }
+        rest = &body[37..];
+    }
+
+    parsed
+}
+
+/// Work out who a new message by `author_id` in `channel` notifies.
+pub async fn resolve(
+    state: &AppState,
+    channel: &Channel,
+    author_id: Uuid,
+    parsed: &ParsedMentions,
+) -> AppResult<ResolvedMentions> {
+    if parsed.users.is_empty() && parsed.roles.is_empty() && !parsed.everyone && !parsed.here {
+        return Ok(ResolvedMentions::default());
+    }
+
+    let roles = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM roles WHERE server_id = $1 AND id = ANY($2) AND NOT is_default"
+    )
+    .bind(channel.server_id)
+    .bind(&parsed.roles)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let (everyone, here) = if parsed.everyone || parsed.here {
+        let perms = permissions::channel_permissions(&state.pool, channel, author_id).await?;
+        let allowed = perms.has(Permissions::MENTION_EVERYONE);
+        (allowed && parsed.everyone, allowed && parsed.here)
+    } else {
+        (false, false)
+    };
+
+    // `named`: mentioned directly or through a role
+    let members = sqlx::query_as::<_, (Uuid, bool)>(
+        r#"
+        SELECT sm.user_id, named
+        FROM server_members sm
+        CROSS JOIN LATERAL (
+            SELECT sm.user_id = ANY($3) OR EXISTS (
+                SELECT 1 FROM member_roles mr
+                WHERE mr.server_id = $1 AND mr.user_id = sm.user_id AND mr.role_id = ANY($4)
+            ) as named
+        ) n
+        WHERE sm.server_id = $1 AND sm.user_id <> $2 AND (named OR $5)
+        "#,
+    )
+    .bind(channel.server_id)
+    .bind(author_id)
+    .bind(&parsed.users)
+    .bind(&roles)
+    .bind(everyone || here)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let candidates: Vec<Uuid> = members
+        .into_iter()
+        .filter(|&(user_id, named)| named || everyone || state.ws_state.user_is_connected(&user_id))
+        .map(|(user_id, _)| user_id)
+        .collect();
+
+    let recipients =
+        permissions::members_with_channel_permission(&state.pool, channel, &candidates, Permissions::VIEW_CHANNEL)
+            .await?;
+    let direct = recipients.iter().copied().filter(|id| parsed.users.contains(id)).collect();
+
+    Ok(ResolvedMentions { recipients, direct, roles, everyone: everyone || here })
+}
+
+/// Store a message's resolved mentions. Run it in the transaction that
+/// inserts the message.
+pub async fn store<'e>(
+    executor: impl PgExecutor<'e>,
+    message_id: Uuid,
+    resolved: &ResolvedMentions,
+) -> AppResult<()> {
+    if resolved.recipients.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO message_mentions (message_id, user_id, direct)
+        SELECT $1, user_id, user_id = ANY($3)
+        FROM unnest($2::UUID[]) as user_id
+        "#,
+    )
+    .bind(message_id)
+    .bind(&resolved.recipients)
+    .bind(&resolved.direct)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Users each message names directly, for message payloads.
+pub async fn load_for(pool: &PgPool, message_ids: &[Uuid]) -> AppResult<HashMap<Uuid, Vec<ProfileSummary>>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows = sqlx::query_as::<_, MentionedUserRow>(
+        r#"
+        SELECT mm.message_id, p.id, p.username, p.display_name, p.avatar_url
+        FROM message_mentions mm
+        INNER JOIN profiles p ON p.id = mm.user_id
+        WHERE mm.message_id = ANY($1) AND mm.direct
+        ORDER BY p.display_name
+        "#,
+    )
+    .bind(message_ids)
+    .fetch_all(pool)
+    .await?;
+
+    let mut mentions: HashMap<Uuid, Vec<ProfileSummary>> = HashMap::new();
+    for row in rows {
+        mentions.entry(row.message_id).or_default().push(row.user);
+    }
+    Ok(mentions)
+}
+
+#[derive(sqlx::FromRow)]
+struct MentionedUserRow {
+    message_id: Uuid,
+    #[sqlx(flatten)]
+    user: ProfileSummary,
+}
+
+#[derive(sqlx::FromRow)]
+struct InboxRow {
+    message_id: Uuid,
+    server_id: Uuid,
+    channel_id: Uuid,
+    parent_id: Option<Uuid>,
+}
+
+/// GET /api/v1/users/@me/mentions?before=&limit=&server_id= — recent
+/// messages that mentioned the caller, newest first
+pub async fn list_mentions(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Query(q): Query<MentionQuery>,
+) -> AppResult<Json<Vec<RecentMention>>> {
+    let limit = q.limit.unwrap_or(25).clamp(1, 100);
+
+    let rows = sqlx::query_as::<_, InboxRow>(
+        r#"
+        SELECT mm.message_id, c.server_id, c.id as channel_id, c.parent_id
+        FROM message_mentions mm
+        INNER JOIN messages m ON m.id = mm.message_id AND m.deleted_at IS NULL
+        INNER JOIN channels c ON c.id = m.channel_id
+        INNER JOIN server_members sm ON sm.server_id = c.server_id AND sm.user_id = mm.user_id
+        WHERE mm.user_id = $1
+          AND ($2::UUID IS NULL OR c.server_id = $2)
+          AND ($3::UUID IS NULL OR mm.created_at < (
+              SELECT created_at FROM message_mentions WHERE message_id = $3 AND user_id = $1
+          ))
+        ORDER BY mm.created_at DESC
+        LIMIT $4
+        "#,
+    )
+    .bind(auth.user_id)
+    .bind(q.server_id)
+    .bind(q.before)
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await?;
+
+    // Access may have been lost since the mention; check it again now
+    let mut visible: HashSet<Uuid> = HashSet::new();
+    let server_ids: HashSet<Uuid> = rows.iter().map(|r| r.server_id).collect();
+    for server_id in server_ids {
+        let perms = permissions::server_permissions(&state.pool, server_id, auth.user_id).await?;
+        let overwrites = permissions::member_overwrites_by_channel(&state.pool, server_id, auth.user_id).await?;
+        for row in rows.iter().filter(|r| r.server_id == server_id) {
+            let channel_overwrites = overwrites
+                .get(&row.parent_id.unwrap_or(row.channel_id))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if perms.in_channel(channel_overwrites).has(Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY) {
+                visible.insert(row.message_id);
+            }
+        }
+    }
+
+    let rows: Vec<InboxRow> = rows.into_iter().filter(|r| visible.contains(&r.message_id)).collect();
+    let ids: Vec<Uuid> = rows.iter().map(|r| r.message_id).collect();
+    let servers: HashMap<Uuid, Uuid> = rows.iter().map(|r| (r.message_id, r.server_id)).collect();
+
+    let mentions = channels::load_messages(&state, &ids, auth.user_id)
+        .await?
+        .into_iter()
+        .map(|message| RecentMention { server_id: servers[&message.id], message })
+        .collect();
+
+    Ok(Json(mentions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::access;
+    use crate::test_util;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    #[test]
+    fn finds_user_and_role_mentions() {
+        let content = format!("hi <@{}> and <@&{}>, also @here", id(1), id(2));
+        let parsed = parse(&content);
+        assert_eq!(parsed.users, [id(1)]);
+        assert_eq!(parsed.roles, [id(2)]);
+        assert!(parsed.here);
+        assert!(!parsed.everyone);
+    }
+
+    #[test]
+    fn skips_malformed_ids() {
+        let content = format!(
+            "<@> <@{simple}> <@{short}> <@{long}0> <@&nope> <@{unterminated} <@{ok}>",
+            simple = id(1).simple(),
+            short = &id(2).to_string()[..35],
+            long = id(3),
+            unterminated = id(4),
+            ok = id(5),
+        );
+        let parsed = parse(&content);
+        assert_eq!(parsed.users, [id(5)]);
+        assert!(parsed.roles.is_empty());
+    }
+
+    #[test]
+    fn handles_multi_byte_text_around_mentions() {
+        // A 36-byte prefix that ends inside a multi-byte character
+        let broken = format!("<@{}é>", "0".repeat(35));
+        let content = format!("🎉<@{}>ünïcødé <@é{broken}<@&{}>🎉", id(1), id(2));
+        let parsed = parse(&content);
+        assert_eq!(parsed.users, [id(1)]);
+        assert_eq!(parsed.roles, [id(2)]);
+    }
+
+    #[test]
+    fn counts_duplicates_once() {
+        let content = format!("<@{0}> <@{0}> <@&{0}> <@&{0}>", id(1));
+        let parsed = parse(&content);
+        assert_eq!(parsed.users, [id(1)]);
+        assert_eq!(parsed.roles, [id(1)]);
+    }
+
+    #[test]
+    fn caps_mentions_per_message() {
+        let users: String = (0..40).map(|n| format!("<@{}>", id(n))).collect();
+        let roles: String = (100..140).map(|n| format!("<@&{}>", id(n))).collect();
+        let parsed = parse(&format!("{users}{roles}"));
+        assert_eq!(parsed.users.len(), 40);
+        assert_eq!(parsed.roles.len(), MAX_MENTIONS_PER_MESSAGE - 40);
+        // The first ones win
+        assert_eq!(parsed.roles.last(), Some(&id(100 + MAX_MENTIONS_PER_MESSAGE as u128 - 41)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn everyone_needs_mention_everyone() {
+        let pool = test_util::database().await;
+        let owner = test_util::user(&pool, "Owner").await;
+        let member = test_util::user(&pool, "Member").await;
+        let server = test_util::server(&pool, owner).await;
+        test_util::join(&pool, server.id, member).await;
+        let state = test_util::app_state(pool.clone());
+        let channel = access::load_channel(&pool, server.text_channel).await.unwrap();
+        let parsed = parse("@everyone look");
+
+        // Plain text without the permission
+        let resolved = resolve(&state, &channel, member, &parsed).await.unwrap();
+        assert!(!resolved.everyone);
+        assert!(resolved.recipients.is_empty());
+
+        // The owner has every permission
+        let resolved = resolve(&state, &channel, owner, &parsed).await.unwrap();
+        assert!(resolved.everyone);
+        assert_eq!(resolved.recipients, [member]);
+        assert!(resolved.direct.is_empty());
+    }
+}
@@ -2,3 +2,15 @@ pub mod servers;
 pub mod channels;
 pub mod dms;
 pub mod voice;
+pub mod roles;
+pub mod invites;
+pub mod moderation;
+pub mod audit_log;
+pub mod threads;
+pub mod reactions;
+pub mod attachments;
+pub mod images;
+pub mod search;
+pub mod read_states;
+pub mod mentions;
+pub mod presence;
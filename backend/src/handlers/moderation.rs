@@ -0,0 +1,380 @@
+//! Moderation REST handlers: kick, ban / unban and time out server members
+//!
+//! Moderators can only act on members whose highest role sits below their own,
+//! and never on the server owner or themselves. The affected user is told over
+//! the gateway, which also drops their subscriptions to the server's channels,
+//! and is taken out of the server's voice channels. Every action is
+//! audit-logged.
+
+use std::collections::HashMap;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::audit::{self, AuditEntry, AuditReason};
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AuditAction, BanRequest, BanWithUser, ProfileSummary, RemovalKind, ServerBan, ServerMember,
+    TimeoutRequest,
+};
+use crate::handlers::voice;
+use crate::permissions::{self, MemberPermissions, Permissions};
+use crate::ws::events::WsEvent;
+
+/// Furthest back a ban may delete the user's messages.
+const MAX_DELETE_MESSAGE_DAYS: i64 = 7;
+
+/// Longest timeout a moderator can hand out (28 days).
+const MAX_TIMEOUT_SECS: i64 = 28 * 24 * 60 * 60;
+
+/// DELETE /api/v1/servers/:id/members/:user_id — requires `KICK_MEMBERS`
+pub async fn kick_member(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::KICK_MEMBERS).await?;
+    let target = load_target(&state, server_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Member not found".into()))?;
+    check_moderatable(&perms, auth.user_id, user_id, &target)?;
+
+    let mut tx = state.pool.begin().await?;
+
+    sqlx::query("DELETE FROM server_members WHERE server_id = $1 AND user_id = $2")
+        .bind(server_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::MemberKick)
+        .target(user_id)
+        .reason(reason.clone())
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    state.ws_state.send_to_user(&user_id, &WsEvent::ServerRemove {
+        server_id,
+        kind: RemovalKind::Kick,
+        reason,
+    });
+    voice::remove_from_server(&state, server_id, user_id).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/servers/:id/bans — requires `BAN_MEMBERS`
+pub async fn list_bans(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+) -> AppResult<Json<Vec<BanWithUser>>> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::BAN_MEMBERS).await?;
+
+    let rows = sqlx::query_as::<_, BanRow>(
+        r#"
+        SELECT
+            b.reason, b.banned_by, b.created_at,
+            p.id as user_id, p.username, p.display_name, p.avatar_url
+        FROM server_bans b
+        INNER JOIN profiles p ON p.id = b.user_id
+        WHERE b.server_id = $1
+        ORDER BY b.created_at DESC
+        "#,
+    )
+    .bind(server_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let bans = rows
+        .into_iter()
+        .map(|r| BanWithUser {
+            user: ProfileSummary {
+                id: r.user_id,
+                username: r.username,
+                display_name: r.display_name,
+                avatar_url: r.avatar_url,
+            },
+            reason: r.reason,
+            banned_by: r.banned_by,
+            created_at: r.created_at,
+        })
+        .collect();
+
+    Ok(Json(bans))
+}
+
+#[derive(sqlx::FromRow)]
+struct BanRow {
+    user_id: Uuid,
+    username: Option<String>,
+    display_name: String,
+    avatar_url: Option<String>,
+    reason: Option<String>,
+    banned_by: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+/// PUT /api/v1/servers/:id/bans/:user_id — requires `BAN_MEMBERS`
+///
+/// Works for users who already left, so they can't come back. The reason
+/// comes from the body, falling back to `X-Audit-Log-Reason`.
+pub async fn ban_member(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id)): Path<(Uuid, Uuid)>,
+    AuditReason(header_reason): AuditReason,
+    Json(body): Json<BanRequest>,
+) -> AppResult<Json<ServerBan>> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::BAN_MEMBERS).await?;
+    if let Some(target) = load_target(&state, server_id, user_id).await? {
+        check_moderatable(&perms, auth.user_id, user_id, &target)?;
+    } else if user_id == auth.user_id {
+        return Err(AppError::BadRequest("You cannot moderate yourself".into()));
+    }
+
+    if !(0..=MAX_DELETE_MESSAGE_DAYS).contains(&body.delete_message_days) {
+        return Err(AppError::BadRequest(format!(
+            "delete_message_days must be between 0 and {MAX_DELETE_MESSAGE_DAYS}"
+        )));
+    }
+    let reason = audit::normalize_reason(body.reason.as_deref())?.or(header_reason);
+
+    let user_exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM profiles WHERE id = $1)")
+        .bind(user_id)
+        .fetch_one(&state.pool)
+        .await?;
+    if !user_exists {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let ban = sqlx::query_as::<_, ServerBan>(
+        r#"
+        INSERT INTO server_bans (server_id, user_id, reason, banned_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (server_id, user_id)
+        DO UPDATE SET reason = EXCLUDED.reason, banned_by = EXCLUDED.banned_by
+        RETURNING *
+        "#,
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .bind(&reason)
+    .bind(auth.user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM server_members WHERE server_id = $1 AND user_id = $2")
+        .bind(server_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let deleted = if body.delete_message_days > 0 {
+        sqlx::query_as::<_, (Uuid, Uuid)>(
+            r#"
+            UPDATE messages SET content = '', deleted_at = now()
+            WHERE author_id = $1
+              AND deleted_at IS NULL
+              AND created_at > now() - make_interval(days => $2)
+              AND channel_id IN (SELECT id FROM channels WHERE server_id = $3)
+            RETURNING id, channel_id
+            "#,
+        )
+        .bind(user_id)
+        .bind(body.delete_message_days as i32)
+        .bind(server_id)
+        .fetch_all(&mut *tx)
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::MemberBanAdd)
+        .target(user_id)
+        .created("delete_message_days", body.delete_message_days)
+        .created("deleted_messages", deleted.len())
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    let mut by_channel: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (id, channel_id) in deleted {
+        by_channel.entry(channel_id).or_default().push(id);
+    }
+    for (channel_id, ids) in by_channel {
+        state.ws_state.broadcast_to_channel(&channel_id, WsEvent::MessageDeleteBulk { ids, channel_id });
+    }
+
+    state.ws_state.send_to_user(&user_id, &WsEvent::ServerRemove {
+        server_id,
+        kind: RemovalKind::Ban,
+        reason: ban.reason.clone(),
+    });
+    voice::remove_from_server(&state, server_id, user_id).await;
+
+    Ok(Json(ban))
+}
+
+/// DELETE /api/v1/servers/:id/bans/:user_id — requires `BAN_MEMBERS`
+pub async fn unban_member(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::BAN_MEMBERS).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let ban = sqlx::query_as::<_, ServerBan>("DELETE FROM server_bans WHERE server_id = $1 AND user_id = $2 RETURNING *")
+        .bind(server_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Ban not found".into()))?;
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::MemberBanRemove)
+        .target(user_id)
+        .removed("ban_reason", ban.reason)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// PUT /api/v1/servers/:id/members/:user_id/timeout — requires `MODERATE_MEMBERS`
+pub async fn timeout_member(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+    Json(body): Json<TimeoutRequest>,
+) -> AppResult<Json<ServerMember>> {
+    if !(1..=MAX_TIMEOUT_SECS).contains(&body.duration_secs) {
+        return Err(AppError::BadRequest(format!(
+            "duration_secs must be between 1 and {MAX_TIMEOUT_SECS}"
+        )));
+    }
+
+    let until = Utc::now() + Duration::seconds(body.duration_secs);
+    set_timeout(&state, auth.user_id, server_id, user_id, Some(until), reason)
+        .await
+        .map(Json)
+}
+
+/// DELETE /api/v1/servers/:id/members/:user_id/timeout — requires `MODERATE_MEMBERS`
+pub async fn remove_timeout(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<Json<ServerMember>> {
+    set_timeout(&state, auth.user_id, server_id, user_id, None, reason).await.map(Json)
+}
+
+/// Set or clear a member's timeout and tell them about it.
+async fn set_timeout(
+    state: &AppState,
+    moderator_id: Uuid,
+    server_id: Uuid,
+    user_id: Uuid,
+    until: Option<DateTime<Utc>>,
+    reason: Option<String>,
+) -> AppResult<ServerMember> {
+    let perms = access::require_server_permission(&state.pool, server_id, moderator_id, Permissions::MODERATE_MEMBERS).await?;
+    let target = load_target(state, server_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Member not found".into()))?;
+    check_moderatable(&perms, moderator_id, user_id, &target)?;
+
+    // Administrators bypass timeouts, so refuse rather than pretend it worked
+    if until.is_some() && target.has(Permissions::ADMINISTRATOR) {
+        return Err(AppError::BadRequest("Administrators cannot be timed out".into()));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let previous = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "SELECT timed_out_until FROM server_members WHERE server_id = $1 AND user_id = $2 FOR UPDATE"
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let member = sqlx::query_as::<_, ServerMember>(
+        r#"
+        UPDATE server_members SET timed_out_until = $3
+        WHERE server_id = $1 AND user_id = $2
+        RETURNING *
+        "#,
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .bind(until)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    AuditEntry::new(server_id, moderator_id, AuditAction::MemberUpdate)
+        .target(user_id)
+        .change("timed_out_until", previous, until)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    state.ws_state.send_to_user(&user_id, &WsEvent::MemberTimeout {
+        server_id,
+        user_id,
+        timed_out_until: until.map(|t| t.to_rfc3339()),
+    });
+    // Timed-out members lose CONNECT
+    if until.is_some() {
+        voice::remove_from_server(state, server_id, user_id).await;
+    }
+
+    Ok(member)
+}
+
+/// The target's permissions in the server, or `None` if they aren't a member.
+async fn load_target(state: &AppState, server_id: Uuid, user_id: Uuid) -> AppResult<Option<MemberPermissions>> {
+    match permissions::server_permissions(&state.pool, server_id, user_id).await {
+        Ok(perms) => Ok(Some(perms)),
+        Err(AppError::Forbidden(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn check_moderatable(
+    perms: &MemberPermissions,
+    moderator_id: Uuid,
+    target_id: Uuid,
+    target: &MemberPermissions,
+) -> AppResult<()> {
+    if moderator_id == target_id {
+        return Err(AppError::BadRequest("You cannot moderate yourself".into()));
+    }
+    if target.is_owner {
+        return Err(AppError::Forbidden("The server owner cannot be moderated".into()));
+    }
+    if !perms.outranks(target.top_role_position) {
+        return Err(AppError::Forbidden("You can only moderate members below your highest role".into()));
+    }
+    Ok(())
+}
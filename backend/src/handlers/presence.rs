@@ -0,0 +1,171 @@
+//! Presence over REST: looking up a user's presence, and the custom status
+//! shown next to it.
+//!
+//! Statuses themselves are set per session over the gateway (see
+//! `ws::presence`). A custom status can expire; `run_custom_status_expiry`
+//! clears expired ones and tells everyone who could see them.
+
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::AuthUser;
+use crate::error::{AppError, AppResult};
+use crate::models::{CustomStatus, Presence, SetCustomStatusRequest, UserStatus};
+use crate::ws::presence;
+
+/// Longest custom status text, in characters.
+const MAX_CUSTOM_STATUS_TEXT_CHARS: usize = 128;
+
+/// Longest custom status emoji, in characters.
+const MAX_CUSTOM_STATUS_EMOJI_CHARS: usize = 32;
+
+/// How often expired custom statuses are cleared.
+const EXPIRY_SWEEP: Duration = Duration::from_secs(30);
+
+/// GET /api/v1/users/:id/presence — only for users sharing a server or DM
+/// with the caller
+pub async fn get_presence(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<Json<Presence>> {
+    if user_id != auth.user_id {
+        let shared = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM server_members sm1
+                JOIN server_members sm2 ON sm1.server_id = sm2.server_id
+                WHERE sm1.user_id = $1 AND sm2.user_id = $2
+            ) OR EXISTS(
+                SELECT 1 FROM dm_members dm1
+                JOIN dm_members dm2 ON dm1.dm_channel_id = dm2.dm_channel_id
+                WHERE dm1.user_id = $1 AND dm2.user_id = $2
+            )
+            "#,
+        )
+        .bind(auth.user_id)
+        .bind(user_id)
+        .fetch_one(&state.pool)
+        .await?;
+
+        if !shared {
+            return Err(AppError::NotFound("User not found".into()));
+        }
+    }
+
+    Ok(Json(presence::presence(&state, user_id).await?))
+}
+
+/// PUT /api/v1/users/@me/custom-status
+pub async fn set_custom_status(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(body): Json<SetCustomStatusRequest>,
+) -> AppResult<Json<CustomStatus>> {
+    let text = body.text.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    let emoji = body.emoji.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+
+    if text.is_none() && emoji.is_none() {
+        return Err(AppError::BadRequest("Custom status needs text or an emoji".into()));
+    }
+    if text.as_ref().is_some_and(|t| t.chars().count() > MAX_CUSTOM_STATUS_TEXT_CHARS) {
+        return Err(AppError::BadRequest(format!(
+            "Custom status text is limited to {MAX_CUSTOM_STATUS_TEXT_CHARS} characters"
+        )));
+    }
+    if emoji.as_ref().is_some_and(|e| e.chars().count() > MAX_CUSTOM_STATUS_EMOJI_CHARS || e.contains(char::is_whitespace)) {
+        return Err(AppError::BadRequest("Invalid custom status emoji".into()));
+    }
+    if body.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(AppError::BadRequest("expires_at must be in the future".into()));
+    }
+
+    let custom_status = sqlx::query_as::<_, CustomStatus>(
+        r#"
+        UPDATE profiles
+        SET custom_status_text = $2,
+            custom_status_emoji = $3,
+            custom_status_expires_at = $4,
+            updated_at = now()
+        WHERE id = $1
+        RETURNING custom_status_text, custom_status_emoji, custom_status_expires_at
+        "#,
+    )
+    .bind(auth.user_id)
+    .bind(text)
+    .bind(emoji)
+    .bind(body.expires_at)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Profile not found".into()))?;
+
+    presence::broadcast(&state, auth.user_id).await;
+    Ok(Json(custom_status))
+}
+
+/// DELETE /api/v1/users/@me/custom-status
+pub async fn clear_custom_status(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> AppResult<StatusCode> {
+    let cleared = sqlx::query(
+        r#"
+        UPDATE profiles
+        SET custom_status_text = NULL,
+            custom_status_emoji = NULL,
+            custom_status_expires_at = NULL,
+            updated_at = now()
+        WHERE id = $1 AND (custom_status_text IS NOT NULL OR custom_status_emoji IS NOT NULL)
+        "#,
+    )
+    .bind(auth.user_id)
+    .execute(&state.pool)
+    .await?;
+
+    if cleared.rows_affected() > 0 {
+        presence::broadcast(&state, auth.user_id).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Background task: clear custom statuses past their `expires_at`,
+/// broadcasting each user's presence without it.
+pub async fn run_custom_status_expiry(state: AppState) {
+    let mut interval = tokio::time::interval(EXPIRY_SWEEP);
+    loop {
+        interval.tick().await;
+        if let Err(e) = clear_expired(&state).await {
+            tracing::error!("Custom status expiry failed: {e}");
+        }
+    }
+}
+
+async fn clear_expired(state: &AppState) -> AppResult<()> {
+    let cleared = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE profiles
+        SET custom_status_text = NULL,
+            custom_status_emoji = NULL,
+            custom_status_expires_at = NULL
+        WHERE custom_status_expires_at <= now()
+        RETURNING id
+        "#,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    for user_id in cleared {
+        // Offline users showed no custom status to begin with
+        if state.ws_state.presence_status(&user_id) != UserStatus::Offline {
+            presence::broadcast(state, user_id).await;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,399 @@
+//! Reaction REST handlers: add / remove / list emoji reactions on channel and DM messages
+//!
+//! An emoji is either a unicode emoji (`👍`) or a custom emoji written as
+//! `name:id`. Message listings embed per-emoji counts via `load_counts`.
+
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{MessageKind, ProfileSummary, ReactionCount, ReactionUsersQuery};
+use crate::permissions::Permissions;
+use crate::ws::events::WsEvent;
+
+/// Distinct emoji allowed on a single message.
+const MAX_EMOJI_PER_MESSAGE: i64 = 20;
+
+/// Longest unicode emoji accepted, in bytes (ZWJ sequences get long).
+const MAX_UNICODE_EMOJI_BYTES: usize = 64;
+
+impl MessageKind {
+    fn messages_table(self) -> &'static str {
+        match self {
+            MessageKind::Channel => "messages",
+            MessageKind::Dm => "dm_messages",
+        }
+    }
+
+    fn reactions_table(self) -> &'static str {
+        match self {
+            MessageKind::Channel => "message_reactions",
+            MessageKind::Dm => "dm_message_reactions",
+        }
+    }
+
+    fn channel_column(self) -> &'static str {
+        match self {
+            MessageKind::Channel => "channel_id",
+            MessageKind::Dm => "dm_channel_id",
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ReactionCountRow {
+    message_id: Uuid,
+    emoji: String,
+    count: i64,
+    me: bool,
+}
+
+/// Aggregate reactions for a page of messages, as seen by `viewer`.
+///
+/// Emoji are ordered by when they were first added to each message.
+pub async fn load_counts(
+    pool: &PgPool,
+    kind: MessageKind,
+    message_ids: &[Uuid],
+    viewer: Uuid,
+) -> AppResult<HashMap<Uuid, Vec<ReactionCount>>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows = sqlx::query_as::<_, ReactionCountRow>(&format!(
+        r#"
+        SELECT message_id, emoji, COUNT(*) as count, bool_or(user_id = $2) as me
+        FROM {}
+        WHERE message_id = ANY($1)
+        GROUP BY message_id, emoji
+        ORDER BY MIN(created_at)
+        "#,
+        kind.reactions_table()
+    ))
+    .bind(message_ids)
+    .bind(viewer)
+    .fetch_all(pool)
+    .await?;
+
+    let mut counts: HashMap<Uuid, Vec<ReactionCount>> = HashMap::new();
+    for r in rows {
+        counts.entry(r.message_id).or_default().push(ReactionCount {
+            emoji: r.emoji,
+            count: r.count,
+            me: r.me,
+        });
+    }
+
+    Ok(counts)
+}
+
+/// PUT /api/v1/channels/:id/messages/:msg_id/reactions/:emoji/@me
+pub async fn add_reaction(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+) -> AppResult<StatusCode> {
+    // SEND_MESSAGES keeps timed-out members from reacting
+    access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY | Permissions::SEND_MESSAGES,
+    )
+    .await?;
+
+    add(&state, MessageKind::Channel, channel_id, message_id, auth.user_id, &emoji).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /api/v1/channels/:id/messages/:msg_id/reactions/:emoji/@me
+pub async fn remove_own_reaction(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+) -> AppResult<StatusCode> {
+    access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+
+    remove(&state, MessageKind::Channel, channel_id, message_id, auth.user_id, &emoji).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /api/v1/channels/:id/messages/:msg_id/reactions/:emoji/:user_id — requires `MANAGE_MESSAGES`
+pub async fn remove_user_reaction(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id, emoji, user_id)): Path<(Uuid, Uuid, String, Uuid)>,
+) -> AppResult<StatusCode> {
+    access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::MANAGE_MESSAGES,
+    )
+    .await?;
+
+    remove(&state, MessageKind::Channel, channel_id, message_id, user_id, &emoji).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/channels/:id/messages/:msg_id/reactions/:emoji?limit=
+pub async fn list_reaction_users(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+    Query(q): Query<ReactionUsersQuery>,
+) -> AppResult<Json<Vec<ProfileSummary>>> {
+    access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY,
+    )
+    .await?;
+
+    let users = list_users(&state.pool, MessageKind::Channel, channel_id, message_id, &emoji, q.limit).await?;
+    Ok(Json(users))
+}
+
+/// PUT /api/v1/dms/:id/messages/:msg_id/reactions/:emoji/@me
+pub async fn add_dm_reaction(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((dm_channel_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+) -> AppResult<StatusCode> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    add(&state, MessageKind::Dm, dm_channel_id, message_id, auth.user_id, &emoji).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /api/v1/dms/:id/messages/:msg_id/reactions/:emoji/@me
+pub async fn remove_own_dm_reaction(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((dm_channel_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+) -> AppResult<StatusCode> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    remove(&state, MessageKind::Dm, dm_channel_id, message_id, auth.user_id, &emoji).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/dms/:id/messages/:msg_id/reactions/:emoji?limit=
+pub async fn list_dm_reaction_users(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((dm_channel_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+    Query(q): Query<ReactionUsersQuery>,
+) -> AppResult<Json<Vec<ProfileSummary>>> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    let users = list_users(&state.pool, MessageKind::Dm, dm_channel_id, message_id, &emoji, q.limit).await?;
+    Ok(Json(users))
+}
+
+/// Add `user_id`'s reaction and broadcast `ReactionAdd`; reacting twice is a no-op.
+async fn add(
+    state: &AppState,
+    kind: MessageKind,
+    channel_id: Uuid,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: &str,
+) -> AppResult<()> {
+    let emoji = validate_emoji(emoji)?;
+    require_message(&state.pool, kind, channel_id, message_id).await?;
+
+    let table = kind.reactions_table();
+    let messages = kind.messages_table();
+
+    // Lock the message so concurrent reactions can't each see room for one
+    // more emoji and together go over the limit
+    let mut tx = state.pool.begin().await?;
+    sqlx::query(&format!("SELECT 1 FROM {messages} WHERE id = $1 FOR NO KEY UPDATE"))
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let is_new_emoji = !sqlx::query_scalar::<_, bool>(&format!(
+        "SELECT EXISTS(SELECT 1 FROM {table} WHERE message_id = $1 AND emoji = $2)"
+    ))
+    .bind(message_id)
+    .bind(&emoji)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if is_new_emoji {
+        let distinct = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(DISTINCT emoji) FROM {table} WHERE message_id = $1"
+        ))
+        .bind(message_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if distinct >= MAX_EMOJI_PER_MESSAGE {
+            return Err(AppError::BadRequest(format!(
+                "A message can have at most {MAX_EMOJI_PER_MESSAGE} different reactions"
+            )));
+        }
+    }
+
+    let inserted = sqlx::query(&format!(
+        "INSERT INTO {table} (message_id, user_id, emoji) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"
+    ))
+    .bind(message_id)
+    .bind(user_id)
+    .bind(&emoji)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    if inserted > 0 {
+        let (server_channel_id, dm_channel_id) = event_target(kind, channel_id);
+        state.ws_state.broadcast_to_channel(&channel_id, WsEvent::ReactionAdd {
+            channel_id: server_channel_id,
+            dm_channel_id,
+            message_id,
+            user_id,
+            emoji,
+        });
+    }
+
+    Ok(())
+}
+
+/// Remove `user_id`'s reaction and broadcast `ReactionRemove`.
+async fn remove(
+    state: &AppState,
+    kind: MessageKind,
+    channel_id: Uuid,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: &str,
+) -> AppResult<()> {
+    let emoji = validate_emoji(emoji)?;
+    require_message(&state.pool, kind, channel_id, message_id).await?;
+
+    let removed = sqlx::query(&format!(
+        "DELETE FROM {} WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+        kind.reactions_table()
+    ))
+    .bind(message_id)
+    .bind(user_id)
+    .bind(&emoji)
+    .execute(&state.pool)
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Err(AppError::NotFound("Reaction not found".into()));
+    }
+
+    let (server_channel_id, dm_channel_id) = event_target(kind, channel_id);
+    state.ws_state.broadcast_to_channel(&channel_id, WsEvent::ReactionRemove {
+        channel_id: server_channel_id,
+        dm_channel_id,
+        message_id,
+        user_id,
+        emoji,
+    });
+
+    Ok(())
+}
+
+/// Users who reacted with `emoji`, in the order they reacted.
+async fn list_users(
+    pool: &PgPool,
+    kind: MessageKind,
+    channel_id: Uuid,
+    message_id: Uuid,
+    emoji: &str,
+    limit: Option<i64>,
+) -> AppResult<Vec<ProfileSummary>> {
+    let emoji = validate_emoji(emoji)?;
+    require_message(pool, kind, channel_id, message_id).await?;
+
+    let limit = limit.unwrap_or(25).clamp(1, 100);
+
+    let users = sqlx::query_as::<_, ProfileSummary>(&format!(
+        r#"
+        SELECT p.id, p.username, p.display_name, p.avatar_url
+        FROM {} r
+        INNER JOIN profiles p ON p.id = r.user_id
+        WHERE r.message_id = $1 AND r.emoji = $2
+        ORDER BY r.created_at ASC
+        LIMIT $3
+        "#,
+        kind.reactions_table()
+    ))
+    .bind(message_id)
+    .bind(&emoji)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users)
+}
+
+/// Ensure the message exists in the channel and hasn't been deleted.
+async fn require_message(pool: &PgPool, kind: MessageKind, channel_id: Uuid, message_id: Uuid) -> AppResult<()> {
+    let exists = sqlx::query_scalar::<_, bool>(&format!(
+        "SELECT EXISTS(SELECT 1 FROM {} WHERE id = $1 AND {} = $2 AND deleted_at IS NULL)",
+        kind.messages_table(),
+        kind.channel_column()
+    ))
+    .bind(message_id)
+    .bind(channel_id)
+    .fetch_one(pool)
+    .await?;
+
+    if !exists {
+        return Err(AppError::NotFound("Message not found".into()));
+    }
+    Ok(())
+}
+
+/// Split a channel id into the `(channel_id, dm_channel_id)` pair carried by reaction events.
+fn event_target(kind: MessageKind, channel_id: Uuid) -> (Option<Uuid>, Option<Uuid>) {
+    match kind {
+        MessageKind::Channel => (Some(channel_id), None),
+        MessageKind::Dm => (None, Some(channel_id)),
+    }
+}
+
+/// Accept a custom emoji (`name:id`) or a single unicode emoji sequence.
+fn validate_emoji(emoji: &str) -> AppResult<String> {
+    let invalid = || AppError::BadRequest("Invalid emoji".into());
+
+    if let Some((name, id)) = emoji.split_once(':') {
+        let name_ok = (2..=32).contains(&name.len())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+        if !name_ok {
+            return Err(invalid());
+        }
+        return Ok(format!("{name}:{id}"));
+    }
+
+    // Unicode emoji always contain a non-ASCII code point (keycaps like 1️⃣
+    // start with an ASCII digit), and never whitespace or control characters
+    let ok = !emoji.is_empty()
+        && emoji.len() <= MAX_UNICODE_EMOJI_BYTES
+        && !emoji.is_ascii()
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control());
+    if !ok {
+        return Err(invalid());
+    }
+
+    Ok(emoji.to_owned())
+}
@@ -0,0 +1,347 @@
+//! Read state handlers: acknowledging messages, per-channel unread counts and
+//! server unread badges
+//!
+//! A read state is the last message a user acknowledged in a channel or DM
+//! (see `013_read_states.sql`). Unread and mention counts are worked out from
+//! the messages after it, so they never drift from what's actually there.
+//! Every change is pushed to all of the user's devices as `ReadStateUpdate`.
+
+use std::collections::HashMap;
+
+use axum::extract::{Path, State};
+use axum::Json;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{MessageKind, ReadState};
+use crate::permissions::{self, MemberPermissions, Permissions};
+use crate::ws::events::WsEvent;
+
+/// Read-state table, message table and channel column for `kind`.
+fn tables(kind: MessageKind) -> (&'static str, &'static str, &'static str) {
+    match kind {
+        MessageKind::Channel => ("read_states", "messages", "channel_id"),
+        MessageKind::Dm => ("dm_read_states", "dm_messages", "dm_channel_id"),
+    }
+}
+
+fn read_state(kind: MessageKind, channel_id: Uuid, last_read_message_id: Option<Uuid>, unread_count: i64, mention_count: i64) -> ReadState {
+    let (channel_id, dm_channel_id) = match kind {
+        MessageKind::Channel => (Some(channel_id), None),
+        MessageKind::Dm => (None, Some(channel_id)),
+    };
+    ReadState { channel_id, dm_channel_id, last_read_message_id, unread_count, mention_count }
+}
+
+/// Unread totals of a server's visible channels, for its badge.
+#[derive(Debug, Default)]
+pub struct ServerUnread {
+    pub unread: bool,
+    pub mention_count: i64,
+}
+
+/// POST /api/v1/channels/:id/messages/:msg_id/ack
+pub async fn ack_message(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<ReadState>> {
+    access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+
+    let read_state = ack(&state, auth.user_id, MessageKind::Channel, channel_id, message_id).await?;
+    Ok(Json(read_state))
+}
+
+/// POST /api/v1/dms/:id/messages/:msg_id/ack
+pub async fn ack_dm_message(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((dm_channel_id, message_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<ReadState>> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    let read_state = ack(&state, auth.user_id, MessageKind::Dm, dm_channel_id, message_id).await?;
+    Ok(Json(read_state))
+}
+
+/// GET /api/v1/servers/:id/read-states — the caller's read state in every
+/// visible text channel and every thread they've joined
+pub async fn list_server_read_states(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+) -> AppResult<Json<Vec<ReadState>>> {
+    let perms = access::require_server_member(&state.pool, server_id, auth.user_id).await?;
+
+    let read_states = server_read_states(&state.pool, server_id, auth.user_id, &perms).await?;
+    Ok(Json(read_states))
+}
+
+/// The user's read state in each text channel of `server_id` they can view
+/// (with `perms` there) and each thread they've joined.
+pub async fn server_read_states(
+    pool: &PgPool,
+    server_id: Uuid,
+    user_id: Uuid,
+    perms: &MemberPermissions,
+) -> AppResult<Vec<ReadState>> {
+    let rows = channel_rows(pool, user_id, Some(&[server_id]), None).await?;
+    let read_states = retain_visible(pool, server_id, user_id, perms, rows)
+        .await?
+        .into_iter()
+        .map(ChannelReadRow::into_read_state)
+        .collect();
+
+    Ok(read_states)
+}
+
+/// Set the user's read position to `message_id` and sync it to their devices.
+///
+/// The position may move backwards, which marks later messages unread again.
+/// Callers must already have verified access to the channel or DM.
+pub async fn ack(
+    state: &AppState,
+    user_id: Uuid,
+    kind: MessageKind,
+    channel_id: Uuid,
+    message_id: Uuid,
+) -> AppResult<ReadState> {
+    let (read_table, message_table, channel_column) = tables(kind);
+    let acked = sqlx::query(&format!(
+        r#"
+        INSERT INTO {read_table} (user_id, {channel_column}, last_read_message_id, last_read_at)
+        SELECT $1, m.{channel_column}, m.id, m.created_at
+        FROM {message_table} m
+        WHERE m.id = $3 AND m.{channel_column} = $2 AND m.deleted_at IS NULL
+        ON CONFLICT (user_id, {channel_column}) DO UPDATE
+        SET last_read_message_id = EXCLUDED.last_read_message_id,
+            last_read_at = EXCLUDED.last_read_at,
+            updated_at = now()
+        "#
+    ))
+    .bind(user_id)
+    .bind(channel_id)
+    .bind(message_id)
+    .execute(&state.pool)
+    .await?
+    .rows_affected();
+
+    if acked == 0 {
+        return Err(AppError::NotFound("Message not found".into()));
+    }
+
+    let read_state = load(&state.pool, user_id, kind, channel_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Channel not found".into()))?;
+
+    state.ws_state.send_to_user(&user_id, &WsEvent::ReadStateUpdate { read_state: read_state.clone() });
+    Ok(read_state)
+}
+
+/// Move the author's read position up to the message they just sent (never
+/// backwards). Run it in the transaction that inserts the message, then call
+/// `sync_own_message` once it's committed.
+pub async fn advance<'e>(
+    executor: impl PgExecutor<'e>,
+    kind: MessageKind,
+    user_id: Uuid,
+    channel_id: Uuid,
+    message_id: Uuid,
+) -> AppResult<()> {
+    let (read_table, message_table, channel_column) = tables(kind);
+    sqlx::query(&format!(
+        r#"
+        INSERT INTO {read_table} (user_id, {channel_column}, last_read_message_id, last_read_at)
+        SELECT $1, m.{channel_column}, m.id, m.created_at
+        FROM {message_table} m
+        WHERE m.id = $3 AND m.{channel_column} = $2
+        ON CONFLICT (user_id, {channel_column}) DO UPDATE
+        SET last_read_message_id = EXCLUDED.last_read_message_id,
+            last_read_at = EXCLUDED.last_read_at,
+            updated_at = now()
+        WHERE {read_table}.last_read_at <= EXCLUDED.last_read_at
+        "#
+    ))
+    .bind(user_id)
+    .bind(channel_id)
+    .bind(message_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Tell the author's other devices where they've read up to after sending
+/// a message. Counts are loaded rather than assumed zero, since others'
+/// messages may have landed just before the author's committed.
+pub async fn sync_own_message(state: &AppState, kind: MessageKind, user_id: Uuid, channel_id: Uuid) {
+    match load(&state.pool, user_id, kind, channel_id).await {
+        Ok(Some(read_state)) => state.ws_state.send_to_user(&user_id, &WsEvent::ReadStateUpdate { read_state }),
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to load read state of user {user_id} in {channel_id}: {e}"),
+    }
+}
+
+/// The user's read state in one channel or DM, if they're still in it.
+async fn load(pool: &PgPool, user_id: Uuid, kind: MessageKind, channel_id: Uuid) -> AppResult<Option<ReadState>> {
+    Ok(match kind {
+        MessageKind::Channel => channel_rows(pool, user_id, None, Some(channel_id))
+            .await?
+            .pop()
+            .map(ChannelReadRow::into_read_state),
+        MessageKind::Dm => dm_read_states(pool, user_id, Some(channel_id)).await?.remove(&channel_id),
+    })
+}
+
+/// The user's read state in each of their DMs (or just `dm_channel_id`),
+/// keyed by DM id.
+pub async fn dm_read_states(
+    pool: &PgPool,
+    user_id: Uuid,
+    dm_channel_id: Option<Uuid>,
+) -> AppResult<HashMap<Uuid, ReadState>> {
+    let rows = sqlx::query_as::<_, DmReadRow>(
+        r#"
+        SELECT dm.dm_channel_id, rs.last_read_message_id, u.unread_count, u.mention_count
+        FROM dm_members dm
+        LEFT JOIN dm_read_states rs ON rs.dm_channel_id = dm.dm_channel_id AND rs.user_id = dm.user_id
+        CROSS JOIN LATERAL (
+            SELECT
+                count(*) as unread_count,
+                count(*) FILTER (WHERE strpos(m.content, '<@' || $1::TEXT || '>') > 0) as mention_count
+            FROM dm_messages m
+            WHERE m.dm_channel_id = dm.dm_channel_id AND m.deleted_at IS NULL AND m.author_id <> $1
+              AND (rs.last_read_at IS NULL OR m.created_at > rs.last_read_at)
+        ) u
+        WHERE dm.user_id = $1 AND ($2::UUID IS NULL OR dm.dm_channel_id = $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(dm_channel_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let read_state = read_state(MessageKind::Dm, r.dm_channel_id, r.last_read_message_id, r.unread_count, r.mention_count);
+            (r.dm_channel_id, read_state)
+        })
+        .collect())
+}
+
+/// Unread badge of every server the user belongs to, counting only channels
+/// they can see and threads they've joined.
+pub async fn server_unreads(pool: &PgPool, user_id: Uuid) -> AppResult<HashMap<Uuid, ServerUnread>> {
+    let mut unread_by_server: HashMap<Uuid, Vec<ChannelReadRow>> = HashMap::new();
+    for row in channel_rows(pool, user_id, None, None).await? {
+        if row.unread_count > 0 {
+            unread_by_server.entry(row.server_id).or_default().push(row);
+        }
+    }
+
+    // Permissions only matter for servers with something unread
+    let mut badges = HashMap::new();
+    for (server_id, rows) in unread_by_server {
+        let perms = permissions::server_permissions(pool, server_id, user_id).await?;
+        let visible = retain_visible(pool, server_id, user_id, &perms, rows).await?;
+        if !visible.is_empty() {
+            badges.insert(server_id, ServerUnread {
+                unread: true,
+                mention_count: visible.iter().map(|r| r.mention_count).sum(),
+            });
+        }
+    }
+
+    Ok(badges)
+}
+
+#[derive(sqlx::FromRow)]
+struct DmReadRow {
+    dm_channel_id: Uuid,
+    last_read_message_id: Option<Uuid>,
+    unread_count: i64,
+    mention_count: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct ChannelReadRow {
+    server_id: Uuid,
+    channel_id: Uuid,
+    parent_id: Option<Uuid>,
+    last_read_message_id: Option<Uuid>,
+    unread_count: i64,
+    mention_count: i64,
+}
+
+impl ChannelReadRow {
+    fn into_read_state(self) -> ReadState {
+        read_state(MessageKind::Channel, self.channel_id, self.last_read_message_id, self.unread_count, self.mention_count)
+    }
+}
+
+/// Read state rows for the user's text channels, optionally limited to some
+/// servers or a single channel. Threads are included only when the user has
+/// joined them, unless asked for by id. Visibility is not checked here.
+async fn channel_rows(
+    pool: &PgPool,
+    user_id: Uuid,
+    server_ids: Option<&[Uuid]>,
+    channel_id: Option<Uuid>,
+) -> AppResult<Vec<ChannelReadRow>> {
+    let rows = sqlx::query_as::<_, ChannelReadRow>(
+        r#"
+        SELECT c.server_id, c.id as channel_id, c.parent_id, rs.last_read_message_id,
+               u.unread_count, u.mention_count
+        FROM channels c
+        INNER JOIN server_members sm ON sm.server_id = c.server_id AND sm.user_id = $1
+        LEFT JOIN read_states rs ON rs.channel_id = c.id AND rs.user_id = $1
+        CROSS JOIN LATERAL (
+            SELECT
+                count(*) as unread_count,
+                count(*) FILTER (WHERE EXISTS (
+                    SELECT 1 FROM message_mentions mm WHERE mm.message_id = m.id AND mm.user_id = $1
+                )) as mention_count
+            FROM messages m
+            WHERE m.channel_id = c.id AND m.deleted_at IS NULL AND m.author_id <> $1
+              AND m.created_at > COALESCE(rs.last_read_at, sm.joined_at)
+        ) u
+        WHERE c.kind = 'text'
+          AND ($2::UUID[] IS NULL OR c.server_id = ANY($2))
+          AND ($3::UUID IS NULL OR c.id = $3)
+          AND ($3::UUID IS NOT NULL OR c.parent_id IS NULL OR EXISTS (
+              SELECT 1 FROM thread_members tm WHERE tm.thread_id = c.id AND tm.user_id = $1
+          ))
+        "#,
+    )
+    .bind(user_id)
+    .bind(server_ids)
+    .bind(channel_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Keep the rows of channels in `server_id` the user can view.
+async fn retain_visible(
+    pool: &PgPool,
+    server_id: Uuid,
+    user_id: Uuid,
+    perms: &MemberPermissions,
+    mut rows: Vec<ChannelReadRow>,
+) -> AppResult<Vec<ChannelReadRow>> {
+    // Threads share their parent channel's overwrites
+    let overwrites = permissions::member_overwrites_by_channel(pool, server_id, user_id).await?;
+    rows.retain(|r| {
+        let channel_overwrites = overwrites
+            .get(&r.parent_id.unwrap_or(r.channel_id))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        perms.in_channel(channel_overwrites).has(Permissions::VIEW_CHANNEL)
+    });
+    Ok(rows)
+}
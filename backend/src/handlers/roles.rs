@@ -0,0 +1,346 @@
+//! Role REST handlers: list / create / update / delete roles, assign roles to members
+//!
+//! Managing a role requires `MANAGE_ROLES` and sitting above the role in the
+//! hierarchy; members can never grant permissions they don't hold themselves.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::audit::{AuditEntry, AuditReason};
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{AuditAction, CreateRoleRequest, Role, UpdateRoleRequest};
+use crate::permissions::{MemberPermissions, Permissions};
+
+/// GET /api/v1/servers/:id/roles
+pub async fn list_roles(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+) -> AppResult<Json<Vec<Role>>> {
+    access::require_server_member(&state.pool, server_id, auth.user_id).await?;
+
+    let roles = sqlx::query_as::<_, Role>(
+        "SELECT * FROM roles WHERE server_id = $1 ORDER BY position DESC, created_at"
+    )
+    .bind(server_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(roles))
+}
+
+/// POST /api/v1/servers/:id/roles
+pub async fn create_role(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    AuditReason(reason): AuditReason,
+    Json(body): Json<CreateRoleRequest>,
+) -> AppResult<Json<Role>> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_ROLES).await?;
+
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Role name cannot be empty".into()));
+    }
+
+    let position = body.position.unwrap_or(1);
+    check_position(&perms, position)?;
+
+    let permissions = body.permissions.unwrap_or_default().truncate();
+    check_grantable(&perms, permissions)?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let role = sqlx::query_as::<_, Role>(
+        r#"
+        INSERT INTO roles (server_id, name, color, position, permissions)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+    )
+    .bind(server_id)
+    .bind(name)
+    .bind(&body.color)
+    .bind(position)
+    .bind(permissions)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::RoleCreate)
+        .target(role.id)
+        .created("name", &role.name)
+        .created("color", &role.color)
+        .created("position", role.position)
+        .created("permissions", role.permissions)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Json(role))
+}
+
+/// PATCH /api/v1/servers/:id/roles/:role_id
+pub async fn update_role(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, role_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+    Json(body): Json<UpdateRoleRequest>,
+) -> AppResult<Json<Role>> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_ROLES).await?;
+    let role = fetch_role(&state.pool, server_id, role_id).await?;
+    check_manageable(&perms, &role)?;
+
+    if role.is_default && (body.name.is_some() || body.position.is_some()) {
+        return Err(AppError::BadRequest("The @everyone role cannot be renamed or moved".into()));
+    }
+    if let Some(name) = &body.name {
+        if name.trim().is_empty() {
+            return Err(AppError::BadRequest("Role name cannot be empty".into()));
+        }
+    }
+    if let Some(position) = body.position {
+        check_position(&perms, position)?;
+    }
+    let permissions = body.permissions.map(Permissions::truncate);
+    if let Some(permissions) = permissions {
+        check_grantable(&perms, permissions)?;
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let updated = sqlx::query_as::<_, Role>(
+        r#"
+        UPDATE roles
+        SET
+            name        = COALESCE($2, name),
+            color       = COALESCE($3, color),
+            position    = COALESCE($4, position),
+            permissions = COALESCE($5, permissions)
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(role_id)
+    .bind(body.name.as_deref().map(str::trim))
+    .bind(&body.color)
+    .bind(body.position)
+    .bind(permissions)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::RoleUpdate)
+        .target(role_id)
+        .change("name", &role.name, &updated.name)
+        .change("color", &role.color, &updated.color)
+        .change("position", role.position, updated.position)
+        .change("permissions", role.permissions, updated.permissions)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    state.ws_state.permissions_changed(server_id);
+    Ok(Json(updated))
+}
+
+/// DELETE /api/v1/servers/:id/roles/:role_id
+pub async fn delete_role(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, role_id)): Path<(Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_ROLES).await?;
+    let role = fetch_role(&state.pool, server_id, role_id).await?;
+    check_manageable(&perms, &role)?;
+
+    if role.is_default {
+        return Err(AppError::BadRequest("The @everyone role cannot be deleted".into()));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    sqlx::query("DELETE FROM channel_overwrites WHERE target_id = $1 AND kind = 'role'")
+        .bind(role_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM roles WHERE id = $1")
+        .bind(role_id)
+        .execute(&mut *tx)
+        .await?;
+
+    AuditEntry::new(server_id, auth.user_id, AuditAction::RoleDelete)
+        .target(role_id)
+        .removed("name", &role.name)
+        .removed("color", &role.color)
+        .removed("position", role.position)
+        .removed("permissions", role.permissions)
+        .reason(reason)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    state.ws_state.permissions_changed(server_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// PUT /api/v1/servers/:id/members/:user_id/roles/:role_id
+pub async fn add_member_role(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id, role_id)): Path<(Uuid, Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_ROLES).await?;
+    let role = fetch_assignable_role(&state.pool, &perms, server_id, role_id).await?;
+    require_target_member(&state.pool, server_id, user_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let added = sqlx::query(
+        r#"
+        INSERT INTO member_roles (server_id, user_id, role_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .bind(role.id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if added > 0 {
+        AuditEntry::new(server_id, auth.user_id, AuditAction::MemberRoleAdd)
+            .target(user_id)
+            .created("role_id", role.id)
+            .reason(reason)
+            .record(&mut *tx)
+            .await?;
+    }
+
+    // Getting a role turns a temporary membership into a permanent one
+    sqlx::query("UPDATE server_members SET temporary = false WHERE server_id = $1 AND user_id = $2")
+        .bind(server_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    state.ws_state.permissions_changed(server_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /api/v1/servers/:id/members/:user_id/roles/:role_id
+pub async fn remove_member_role(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((server_id, user_id, role_id)): Path<(Uuid, Uuid, Uuid)>,
+    AuditReason(reason): AuditReason,
+) -> AppResult<StatusCode> {
+    let perms = access::require_server_permission(&state.pool, server_id, auth.user_id, Permissions::MANAGE_ROLES).await?;
+    let role = fetch_assignable_role(&state.pool, &perms, server_id, role_id).await?;
+
+    let mut tx = state.pool.begin().await?;
+
+    let removed = sqlx::query("DELETE FROM member_roles WHERE server_id = $1 AND user_id = $2 AND role_id = $3")
+        .bind(server_id)
+        .bind(user_id)
+        .bind(role.id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    if removed > 0 {
+        AuditEntry::new(server_id, auth.user_id, AuditAction::MemberRoleRemove)
+            .target(user_id)
+            .removed("role_id", role.id)
+            .reason(reason)
+            .record(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    state.ws_state.permissions_changed(server_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Load a role, making sure it belongs to `server_id`.
+async fn fetch_role(pool: &PgPool, server_id: Uuid, role_id: Uuid) -> AppResult<Role> {
+    sqlx::query_as::<_, Role>("SELECT * FROM roles WHERE id = $1 AND server_id = $2")
+        .bind(role_id)
+        .bind(server_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Role not found".into()))
+}
+
+/// Load a role that `perms` may add to / remove from members.
+async fn fetch_assignable_role(
+    pool: &PgPool,
+    perms: &MemberPermissions,
+    server_id: Uuid,
+    role_id: Uuid,
+) -> AppResult<Role> {
+    let role = fetch_role(pool, server_id, role_id).await?;
+    check_manageable(perms, &role)?;
+
+    if role.is_default {
+        return Err(AppError::BadRequest("The @everyone role cannot be assigned".into()));
+    }
+
+    Ok(role)
+}
+
+async fn require_target_member(pool: &PgPool, server_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    let is_member = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)"
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    if !is_member {
+        return Err(AppError::NotFound("Member not found".into()));
+    }
+
+    Ok(())
+}
+
+fn check_manageable(perms: &MemberPermissions, role: &Role) -> AppResult<()> {
+    // @everyone sits at position 0, so anyone with MANAGE_ROLES may edit it
+    if !role.is_default && !perms.outranks(role.position) {
+        return Err(AppError::Forbidden("You can only manage roles below your highest role".into()));
+    }
+    Ok(())
+}
+
+fn check_position(perms: &MemberPermissions, position: i32) -> AppResult<()> {
+    if position < 1 {
+        return Err(AppError::BadRequest("Role position must be at least 1".into()));
+    }
+    if !perms.outranks(position) {
+        return Err(AppError::Forbidden("You can only place roles below your highest role".into()));
+    }
+    Ok(())
+}
+
+fn check_grantable(perms: &MemberPermissions, requested: Permissions) -> AppResult<()> {
+    if !perms.permissions.contains(requested) {
+        return Err(AppError::Forbidden("You cannot grant permissions you do not have".into()));
+    }
+    Ok(())
+}
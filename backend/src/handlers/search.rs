@@ -0,0 +1,393 @@
+//! Full-text message search in a server's channels or a DM
+//!
+//! Matching uses the `content_tsv` columns (see `012_search.sql`) with
+//! `websearch_to_tsquery`, so clients can send quoted phrases, `or` and
+//! `-term`. Results are newest first and paged with `cursor`. A server search
+//! only ever looks at channels and threads the caller can read.
+
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::handlers::channels;
+use crate::handlers::dms;
+use crate::models::{
+    Channel, DmMessageWithAuthor, MessageKind, MessageWithAuthor, SearchHit, SearchQuery, SearchResults,
+};
+use crate::permissions::{self, Permissions};
+
+/// Longest accepted search string, in characters.
+const MAX_QUERY_CHARS: usize = 512;
+
+/// Characters of content used as the snippet when there are no search terms.
+const PLAIN_SNIPPET_CHARS: i32 = 200;
+
+/// Text search configuration the `content_tsv` columns are built with.
+const TS_CONFIG: &str = "english";
+
+/// GET /api/v1/servers/:id/messages/search?q=&author_id=&channel_id=&after=&before=&has_attachment=&mentions=&cursor=&limit=
+pub async fn search_server_messages(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+    Query(q): Query<SearchQuery>,
+) -> AppResult<Json<SearchResults<MessageWithAuthor>>> {
+    let filters = Filters::parse(q)?;
+
+    let mut scope = readable_channels(&state.pool, server_id, auth.user_id).await?;
+    if let Some(channel_id) = filters.channel_id {
+        if !scope.contains(&channel_id) {
+            return Err(AppError::Forbidden("You cannot read this channel".into()));
+        }
+        scope = vec![channel_id];
+    }
+
+    let (hits, next_cursor) = find(&state.pool, MessageKind::Channel, &scope, &filters).await?;
+    let ids: Vec<Uuid> = hits.iter().map(|h| h.id).collect();
+    let messages = channels::load_messages(&state, &ids, auth.user_id).await?;
+
+    Ok(Json(SearchResults { results: attach_snippets(messages, hits, |m| m.id), next_cursor }))
+}
+
+/// GET /api/v1/dms/:id/messages/search?q=&author_id=&after=&before=&has_attachment=&mentions=&cursor=&limit=
+pub async fn search_dm_messages(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(dm_channel_id): Path<Uuid>,
+    Query(q): Query<SearchQuery>,
+) -> AppResult<Json<SearchResults<DmMessageWithAuthor>>> {
+    access::require_dm_member(&state.pool, dm_channel_id, auth.user_id).await?;
+
+    let filters = Filters::parse(q)?;
+    if filters.channel_id.is_some() {
+        return Err(AppError::BadRequest("channel_id only applies to server searches".into()));
+    }
+
+    let (hits, next_cursor) = find(&state.pool, MessageKind::Dm, &[dm_channel_id], &filters).await?;
+    let ids: Vec<Uuid> = hits.iter().map(|h| h.id).collect();
+    let messages = dms::load_dm_messages(&state, &ids, auth.user_id).await?;
+
+    Ok(Json(SearchResults { results: attach_snippets(messages, hits, |m| m.id), next_cursor }))
+}
+
+/// Validated search parameters.
+struct Filters {
+    terms: Option<String>,
+    author_id: Option<Uuid>,
+    channel_id: Option<Uuid>,
+    after: Option<chrono::DateTime<chrono::Utc>>,
+    before: Option<chrono::DateTime<chrono::Utc>>,
+    has_attachment: Option<bool>,
+    mentions: Option<Uuid>,
+    cursor: Option<Uuid>,
+    limit: i64,
+}
+
+impl Filters {
+    fn parse(q: SearchQuery) -> AppResult<Self> {
+        let terms = q.q.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        if terms.as_ref().is_some_and(|t| t.chars().count() > MAX_QUERY_CHARS) {
+            return Err(AppError::BadRequest(format!(
+                "Search query cannot exceed {MAX_QUERY_CHARS} characters"
+            )));
+        }
+        if terms.is_none()
+            && q.author_id.is_none()
+            && q.channel_id.is_none()
+            && q.after.is_none()
+            && q.before.is_none()
+            && q.has_attachment.is_none()
+            && q.mentions.is_none()
+        {
+            return Err(AppError::BadRequest("Give a search query or at least one filter".into()));
+        }
+        if let (Some(after), Some(before)) = (q.after, q.before) {
+            if after >= before {
+                return Err(AppError::BadRequest("`after` must be earlier than `before`".into()));
+            }
+        }
+
+        Ok(Filters {
+            terms,
+            author_id: q.author_id,
+            channel_id: q.channel_id,
+            after: q.after,
+            before: q.before,
+            has_attachment: q.has_attachment,
+            mentions: q.mentions,
+            cursor: q.cursor,
+            limit: q.limit.unwrap_or(25).clamp(1, 50),
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct HitRow {
+    id: Uuid,
+    snippet: String,
+}
+
+/// Channels and threads in `server_id` where `user_id` can read history.
+async fn readable_channels(pool: &PgPool, server_id: Uuid, user_id: Uuid) -> AppResult<Vec<Uuid>> {
+    let perms = access::require_server_member(pool, server_id, user_id).await?;
+
+    let channels = sqlx::query_as::<_, Channel>("SELECT * FROM channels WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_all(pool)
+        .await?;
+
+    // Threads share their parent channel's overwrites
+    let overwrites = permissions::member_overwrites_by_channel(pool, server_id, user_id).await?;
+    let readable = Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY;
+    Ok(channels
+        .into_iter()
+        .filter(|c| {
+            let channel_overwrites = overwrites
+                .get(&c.parent_id.unwrap_or(c.id))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            perms.in_channel(channel_overwrites).has(readable)
+        })
+        .map(|c| c.id)
+        .collect())
+}
+
+/// Ids and snippets of one page of matches in `scope` (channel or DM ids),
+/// plus the cursor for the next page.
+async fn find(
+    pool: &PgPool,
+    kind: MessageKind,
+    scope: &[Uuid],
+    filters: &Filters,
+) -> AppResult<(Vec<HitRow>, Option<Uuid>)> {
+    if scope.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+
+    let (table, channel_column, attachment_column) = match kind {
+        MessageKind::Channel => ("messages", "channel_id", "message_id"),
+        MessageKind::Dm => ("dm_messages", "dm_channel_id", "dm_message_id"),
+    };
+
+    // Channel mentions are stored when sent; DMs only have the content
+    let mentions_clause = match kind {
+        MessageKind::Channel => {
+            "EXISTS (SELECT 1 FROM message_mentions mm WHERE mm.message_id = m.id AND mm.user_id = $7 AND mm.direct)"
+        }
+        MessageKind::Dm => "strpos(m.content, '<@' || $7::TEXT || '>') > 0",
+    };
+
+    // Content is HTML-escaped before highlighting so the only markup in a
+    // snippet is our own <mark> tags
+    let escaped = "replace(replace(replace(m.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;')";
+
+    let mut rows = sqlx::query_as::<_, HitRow>(&format!(
+        r#"
+        SELECT m.id,
+            CASE WHEN $2::TEXT IS NULL THEN left({escaped}, $10)
+                 ELSE ts_headline('{TS_CONFIG}', {escaped}, websearch_to_tsquery('{TS_CONFIG}', $2),
+                                  'StartSel=<mark>, StopSel=</mark>, MinWords=10, MaxWords=30, MaxFragments=2')
+            END as snippet
+        FROM {table} m
+        WHERE m.{channel_column} = ANY($1) AND m.deleted_at IS NULL
+          AND ($2::TEXT IS NULL OR m.content_tsv @@ websearch_to_tsquery('{TS_CONFIG}', $2))
+          AND ($3::UUID IS NULL OR m.author_id = $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR m.created_at >= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR m.created_at < $5)
+          AND ($6::BOOLEAN IS NULL OR EXISTS (
+                  SELECT 1 FROM attachments a WHERE a.{attachment_column} = m.id
+              ) = $6)
+          AND ($7::UUID IS NULL OR {mentions_clause})
+          AND ($8::UUID IS NULL OR (m.created_at, m.id) < (
+                  SELECT created_at, id FROM {table} WHERE id = $8
+              ))
+        ORDER BY m.created_at DESC, m.id DESC
+        LIMIT $9 + 1
+        "#
+    ))
+    .bind(scope)
+    .bind(&filters.terms)
+    .bind(filters.author_id)
+    .bind(filters.after)
+    .bind(filters.before)
+    .bind(filters.has_attachment)
+    .bind(filters.mentions)
+    .bind(filters.cursor)
+    .bind(filters.limit)
+    .bind(PLAIN_SNIPPET_CHARS)
+    .fetch_all(pool)
+    .await?;
+
+    // The extra row only tells us whether there's another page
+    let next_cursor = if rows.len() as i64 > filters.limit {
+        rows.truncate(filters.limit as usize);
+        rows.last().map(|r| r.id)
+    } else {
+        None
+    };
+
+    Ok((rows, next_cursor))
+}
+
+/// Pair loaded messages with their snippets, keeping the search order.
+fn attach_snippets<T>(messages: Vec<T>, hits: Vec<HitRow>, id_of: impl Fn(&T) -> Uuid) -> Vec<SearchHit<T>> {
+    let mut snippets: HashMap<Uuid, String> = hits.into_iter().map(|h| (h.id, h.snippet)).collect();
+    messages
+        .into_iter()
+        .map(|message| {
+            let snippet = snippets.remove(&id_of(&message)).unwrap_or_default();
+            SearchHit { message, snippet }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+    use crate::test_util::{self, TestServer};
+
+    fn query(q: &str) -> SearchQuery {
+        SearchQuery {
+            q: Some(q.into()),
+            author_id: None,
+            channel_id: None,
+            after: None,
+            before: None,
+            has_attachment: None,
+            mentions: None,
+            cursor: None,
+            limit: None,
+        }
+    }
+
+    async fn search(state: &AppState, server_id: Uuid, user_id: Uuid, q: SearchQuery) -> SearchResults<MessageWithAuthor> {
+        let Json(results) = search_server_messages(AuthUser { user_id }, State(state.clone()), Path(server_id), Query(q))
+            .await
+            .unwrap();
+        results
+    }
+
+    async fn post(pool: &PgPool, channel_id: Uuid, author_id: Uuid, content: &str, created_at: DateTime<Utc>) -> Uuid {
+        sqlx::query_scalar(
+            "INSERT INTO messages (channel_id, author_id, content, created_at) VALUES ($1, $2, $3, $4) RETURNING id"
+        )
+        .bind(channel_id)
+        .bind(author_id)
+        .bind(content)
+        .bind(created_at)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn channel(pool: &PgPool, server_id: Uuid, parent_id: Option<Uuid>) -> Uuid {
+        sqlx::query_scalar(
+            "INSERT INTO channels (server_id, name, kind, parent_id) VALUES ($1, 'more', 'text', $2) RETURNING id"
+        )
+        .bind(server_id)
+        .bind(parent_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    /// A server owned by the first user, with the second as a member.
+    async fn setup() -> (PgPool, AppState, TestServer, Uuid, Uuid) {
+        let pool = test_util::database().await;
+        let owner = test_util::user(&pool, "Owner").await;
+        let member = test_util::user(&pool, "Member").await;
+        let server = test_util::server(&pool, owner).await;
+        test_util::join(&pool, server.id, member).await;
+        let state = test_util::app_state(pool.clone());
+        (pool, state, server, owner, member)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn only_searches_readable_channels_and_threads() {
+        let (pool, state, server, owner, member) = setup().await;
+        let hidden = channel(&pool, server.id, None).await;
+        let hidden_thread = channel(&pool, server.id, Some(hidden)).await;
+        let visible_thread = channel(&pool, server.id, Some(server.text_channel)).await;
+        sqlx::query("INSERT INTO channel_overwrites (channel_id, target_id, kind, deny) VALUES ($1, $2, 'member', $3)")
+            .bind(hidden)
+            .bind(member)
+            .bind(Permissions::VIEW_CHANNEL)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        let public = post(&pool, server.text_channel, owner, "banana bread", now).await;
+        let in_thread = post(&pool, visible_thread, owner, "banana split", now).await;
+        post(&pool, hidden, owner, "banana secret", now).await;
+        post(&pool, hidden_thread, owner, "banana plans", now).await;
+
+        let results = search(&state, server.id, member, query("banana")).await;
+        let mut found: Vec<Uuid> = results.results.iter().map(|hit| hit.message.id).collect();
+        found.sort_unstable();
+        let mut expected = vec![public, in_thread];
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+        assert_eq!(search(&state, server.id, owner, query("banana")).await.results.len(), 4);
+
+        // Asking for a hidden thread by id doesn't get around it
+        let q = SearchQuery { channel_id: Some(hidden_thread), ..query("banana") };
+        let denied = search_server_messages(AuthUser { user_id: member }, State(state), Path(server.id), Query(q)).await;
+        assert!(matches!(denied, Err(AppError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn pages_through_messages_with_the_same_timestamp() {
+        let (pool, state, server, owner, member) = setup().await;
+        let now = Utc::now();
+        let mut expected = Vec::new();
+        for _ in 0..5 {
+            expected.push(post(&pool, server.text_channel, owner, "same moment", now).await);
+        }
+        // Newest first, ties broken by id
+        expected.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let q = SearchQuery { cursor, limit: Some(2), ..query("moment") };
+            let page = search(&state, server.id, member, q).await;
+            assert!(page.results.len() <= 2);
+            seen.extend(page.results.iter().map(|hit| hit.message.id));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(seen, expected);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn escapes_html_in_snippets() {
+        let (pool, state, server, owner, member) = setup().await;
+        post(&pool, server.text_channel, owner, "<script>alert(1)</script> banana & <b>co</b>", Utc::now()).await;
+
+        // Fragments may start or end mid-entity, but the only markup is ours
+        let highlighted = search(&state, server.id, member, query("banana")).await;
+        let snippet = &highlighted.results[0].snippet;
+        assert!(snippet.contains("alert(1)&lt;/script&gt; <mark>banana</mark>"), "{snippet}");
+        let unmarked = snippet.replace("<mark>", "").replace("</mark>", "");
+        assert!(!unmarked.contains(['<', '>']), "{snippet}");
+
+        // Without search terms the snippet is the start of the content
+        let q = SearchQuery { author_id: Some(owner), ..query("") };
+        let plain = search(&state, server.id, member, q).await;
+        assert_eq!(plain.results[0].snippet, "&lt;script&gt;alert(1)&lt;/script&gt; banana &amp; &lt;b&gt;co&lt;/b&gt;");
+    }
+}
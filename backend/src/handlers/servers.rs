@@ -1,4 +1,4 @@
-/// Server REST handlers: list, create, discover, get, join, leave
+//! Server REST handlers: list, create, discover, get, join, leave, members
 
 use axum::extract::{Path, State, Query};
 use axum::Json;
@@ -6,15 +6,22 @@ use serde::Deserialize;
 use uuid::Uuid;
 
 use crate::AppState;
-use crate::auth::AuthUser;
+use crate::audit::AuditEntry;
+use crate::auth::{access, AuthUser};
 use crate::error::{AppError, AppResult};
-use crate::models::{Server, ServerMember, CreateServerRequest};
+use crate::handlers::{read_states, voice};
+use crate::models::{
+    AuditAction, Server, ServerMember, ServerWithUnread, CreateServerRequest, MemberWithRoles, ProfileSummary,
+    RemovalKind,
+};
+use crate::permissions::Permissions;
+use crate::ws::events::WsEvent;
 
-/// GET /api/v1/servers — user's joined servers
+/// GET /api/v1/servers — user's joined servers, with their unread badges
 pub async fn list_servers(
     auth: AuthUser,
     State(state): State<AppState>,
-) -> AppResult<Json<Vec<Server>>> {
+) -> AppResult<Json<Vec<ServerWithUnread>>> {
     let servers = sqlx::query_as::<_, Server>(
         r#"
         SELECT s.* FROM servers s
@@ -27,6 +34,15 @@ pub async fn list_servers(
     .fetch_all(&state.pool)
     .await?;
 
+    let mut unreads = read_states::server_unreads(&state.pool, auth.user_id).await?;
+    let servers = servers
+        .into_iter()
+        .map(|server| {
+            let badge = unreads.remove(&server.id).unwrap_or_default();
+            ServerWithUnread { server, unread: badge.unread, mention_count: badge.mention_count }
+        })
+        .collect();
+
     Ok(Json(servers))
 }
 
@@ -55,15 +71,24 @@ pub async fn create_server(
     .fetch_one(&mut *tx)
     .await?;
 
-    // Add creator as owner member
+    // Add creator as a member (ownership comes from servers.owner_id)
     sqlx::query(
-        "INSERT INTO server_members (server_id, user_id, role) VALUES ($1, $2, 'owner')"
+        "INSERT INTO server_members (server_id, user_id) VALUES ($1, $2)"
     )
     .bind(server.id)
     .bind(auth.user_id)
     .execute(&mut *tx)
     .await?;
 
+    // Create the @everyone role
+    sqlx::query(
+        "INSERT INTO roles (server_id, name, position, permissions, is_default) VALUES ($1, '@everyone', 0, $2, true)"
+    )
+    .bind(server.id)
+    .bind(Permissions::DEFAULT)
+    .execute(&mut *tx)
+    .await?;
+
     // Create default channels
     sqlx::query(
         "INSERT INTO channels (server_id, name, kind, position) VALUES ($1, 'general', 'text', 0), ($1, 'Lounge', 'voice', 0)"
@@ -131,18 +156,7 @@ pub async fn get_server(
     State(state): State<AppState>,
     Path(server_id): Path<Uuid>,
 ) -> AppResult<Json<Server>> {
-    // Check membership
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)"
-    )
-    .bind(server_id)
-    .bind(auth.user_id)
-    .fetch_one(&state.pool)
-    .await?;
-
-    if !is_member {
-        return Err(AppError::Forbidden("You are not a member of this server".into()));
-    }
+    access::require_server_member(&state.pool, server_id, auth.user_id).await?;
 
     let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
         .bind(server_id)
@@ -169,21 +183,30 @@ pub async fn join_server(
     if !server.is_public {
         return Err(AppError::Forbidden("Server is not public".into()));
     }
+    access::require_not_banned(&state.pool, server_id, auth.user_id).await?;
+
+    let mut tx = state.pool.begin().await?;
 
     let member = sqlx::query_as::<_, ServerMember>(
         r#"
-        INSERT INTO server_members (server_id, user_id, role)
-        VALUES ($1, $2, 'member')
+        INSERT INTO server_members (server_id, user_id)
+        VALUES ($1, $2)
         ON CONFLICT (server_id, user_id) DO NOTHING
         RETURNING *
         "#,
     )
     .bind(server_id)
     .bind(auth.user_id)
-    .fetch_optional(&state.pool)
+    .fetch_optional(&mut *tx)
     .await?
     .ok_or_else(|| AppError::BadRequest("Already a member".into()))?;
 
+    AuditEntry::new(server_id, auth.user_id, AuditAction::MemberJoin)
+        .target(auth.user_id)
+        .record(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
     Ok(Json(member))
 }
 
@@ -204,11 +227,122 @@ pub async fn leave_server(
         return Err(AppError::BadRequest("Owner cannot leave the server".into()));
     }
 
-    sqlx::query("DELETE FROM server_members WHERE server_id = $1 AND user_id = $2")
-        .bind(server_id)
-        .bind(auth.user_id)
-        .execute(&state.pool)
+    leave(&state, server_id, auth.user_id, false).await?;
+    Ok(axum::http::StatusCode::NO_CONTENT)
+}
+
+/// Take `user_id` out of the server: delete the membership and record the
+/// leave, then tell the remaining members (`MemberLeave`) and the user's
+/// sessions (`ServerRemove`) and take the user out of voice there. With
+/// `only_temporary`, only a temporary membership without roles is removed.
+/// Returns false if nothing was removed.
+pub async fn leave(state: &AppState, server_id: Uuid, user_id: Uuid, only_temporary: bool) -> AppResult<bool> {
+    let mut tx = state.pool.begin().await?;
+
+    let left = sqlx::query(
+        r#"
+        DELETE FROM server_members sm
+        WHERE sm.server_id = $1 AND sm.user_id = $2
+          AND (NOT $3 OR (sm.temporary AND NOT EXISTS (
+              SELECT 1 FROM member_roles mr
+              WHERE mr.server_id = sm.server_id AND mr.user_id = sm.user_id
+          )))
+        "#,
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .bind(only_temporary)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if left == 0 {
+        return Ok(false);
+    }
+
+    AuditEntry::new(server_id, user_id, AuditAction::MemberLeave)
+        .target(user_id)
+        .record(&mut *tx)
         .await?;
 
-    Ok(axum::http::StatusCode::NO_CONTENT)
+    tx.commit().await?;
+
+    // The membership is gone either way; failures from here on are logged
+    let member_ids = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM server_members WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_all(&state.pool)
+        .await;
+    match member_ids {
+        Ok(member_ids) => {
+            let event = WsEvent::MemberLeave { server_id, user_id };
+            for member_id in member_ids {
+                state.ws_state.send_to_user(&member_id, &event);
+            }
+        }
+        Err(e) => tracing::error!("Failed to load members of server {server_id}: {e}"),
+    }
+    state.ws_state.send_to_user(&user_id, &WsEvent::ServerRemove {
+        server_id,
+        kind: RemovalKind::Leave,
+        reason: None,
+    });
+    voice::remove_from_server(state, server_id, user_id).await;
+
+    Ok(true)
+}
+
+#[derive(sqlx::FromRow)]
+struct MemberRow {
+    user_id: Uuid,
+    username: Option<String>,
+    display_name: String,
+    avatar_url: Option<String>,
+    joined_at: chrono::DateTime<chrono::Utc>,
+    roles: Vec<Uuid>,
+}
+
+/// GET /api/v1/servers/:id/members — members with their assigned roles
+pub async fn list_members(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(server_id): Path<Uuid>,
+) -> AppResult<Json<Vec<MemberWithRoles>>> {
+    access::require_server_member(&state.pool, server_id, auth.user_id).await?;
+
+    let rows = sqlx::query_as::<_, MemberRow>(
+        r#"
+        SELECT
+            p.id as user_id, p.username, p.display_name, p.avatar_url,
+            sm.joined_at,
+            COALESCE(
+                array_agg(mr.role_id) FILTER (WHERE mr.role_id IS NOT NULL),
+                '{}'
+            ) as roles
+        FROM server_members sm
+        INNER JOIN profiles p ON p.id = sm.user_id
+        LEFT JOIN member_roles mr ON mr.server_id = sm.server_id AND mr.user_id = sm.user_id
+        WHERE sm.server_id = $1
+        GROUP BY p.id, sm.joined_at
+        ORDER BY sm.joined_at
+        "#,
+    )
+    .bind(server_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let members = rows
+        .into_iter()
+        .map(|r| MemberWithRoles {
+            user: ProfileSummary {
+                id: r.user_id,
+                username: r.username,
+                display_name: r.display_name,
+                avatar_url: r.avatar_url,
+            },
+            roles: r.roles,
+            joined_at: r.joined_at,
+        })
+        .collect();
+
+    Ok(Json(members))
 }
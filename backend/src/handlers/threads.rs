@@ -0,0 +1,402 @@
+//! Thread REST handlers: start threads from messages, list / update them, join / leave
+//!
+//! A thread is a text channel with `parent_id` set plus a row in `threads`, so
+//! its messages go through the regular channel endpoints and gateway events.
+//! Threads inherit the parent's permissions, are archived automatically after
+//! `auto_archive_minutes` without a message, and come back when someone posts.
+
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ChannelType, CreateThreadRequest, ProfileSummary, Thread, ThreadMember, ThreadQuery,
+    UpdateThreadRequest,
+};
+use crate::permissions::{self, Permissions};
+use crate::ws::events::WsEvent;
+
+/// Inactivity periods (minutes) a thread may auto-archive after: 1h, 1d, 3d, 1w.
+const AUTO_ARCHIVE_OPTIONS: [i32; 4] = [60, 1440, 4320, 10080];
+
+/// Longest thread name (matches `channels.name`).
+const MAX_THREAD_NAME_LEN: usize = 64;
+
+/// How often inactive threads are swept into the archive.
+const AUTO_ARCHIVE_SWEEP: Duration = Duration::from_secs(60);
+
+/// Select list producing a `Thread`; callers append WHERE / ORDER BY.
+const THREAD_SELECT: &str = r#"
+    SELECT
+        c.id, c.server_id, c.parent_id, c.name, c.created_at,
+        t.source_message_id, t.owner_id, t.archived, t.archived_at,
+        t.auto_archive_minutes, t.last_message_at,
+        (SELECT COUNT(*) FROM thread_members tm WHERE tm.thread_id = t.channel_id) as member_count
+    FROM threads t
+    INNER JOIN channels c ON c.id = t.channel_id
+"#;
+
+/// POST /api/v1/channels/:id/messages/:msg_id/threads
+pub async fn create_thread(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((channel_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(body): Json<CreateThreadRequest>,
+) -> AppResult<Json<Thread>> {
+    let parent = access::require_channel_permission(
+        &state.pool,
+        channel_id,
+        auth.user_id,
+        Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+    )
+    .await?;
+
+    if parent.kind != ChannelType::Text || parent.parent_id.is_some() {
+        return Err(AppError::BadRequest("Threads can only be started in text channels".into()));
+    }
+
+    let name = validate_name(&body.name)?;
+    let auto_archive_minutes = body.auto_archive_minutes.unwrap_or(AUTO_ARCHIVE_OPTIONS[1]);
+    validate_auto_archive(auto_archive_minutes)?;
+
+    let message_exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM messages WHERE id = $1 AND channel_id = $2 AND deleted_at IS NULL)"
+    )
+    .bind(message_id)
+    .bind(channel_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    if !message_exists {
+        return Err(AppError::NotFound("Message not found".into()));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let thread_id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO channels (server_id, name, kind, parent_id) VALUES ($1, $2, 'text', $3) RETURNING id"
+    )
+    .bind(parent.server_id)
+    .bind(name)
+    .bind(channel_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let created = sqlx::query(
+        r#"
+        INSERT INTO threads (channel_id, source_message_id, owner_id, auto_archive_minutes)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (source_message_id) DO NOTHING
+        "#,
+    )
+    .bind(thread_id)
+    .bind(message_id)
+    .bind(auth.user_id)
+    .bind(auto_archive_minutes)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if created == 0 {
+        return Err(AppError::BadRequest("This message already has a thread".into()));
+    }
+
+    sqlx::query("INSERT INTO thread_members (thread_id, user_id) VALUES ($1, $2)")
+        .bind(thread_id)
+        .bind(auth.user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    let thread = fetch_thread(&state.pool, thread_id).await?;
+    state.ws_state.broadcast_to_channel(&channel_id, WsEvent::ThreadCreate { thread: thread.clone() });
+
+    Ok(Json(thread))
+}
+
+/// GET /api/v1/channels/:id/threads?archived=
+pub async fn list_threads(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(channel_id): Path<Uuid>,
+    Query(q): Query<ThreadQuery>,
+) -> AppResult<Json<Vec<Thread>>> {
+    access::require_channel_permission(&state.pool, channel_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+
+    let threads = sqlx::query_as::<_, Thread>(&format!(
+        r#"{THREAD_SELECT}
+        WHERE c.parent_id = $1 AND ($2::BOOLEAN IS NULL OR t.archived = $2)
+        ORDER BY t.last_message_at DESC
+        "#
+    ))
+    .bind(channel_id)
+    .bind(q.archived)
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(threads))
+}
+
+/// GET /api/v1/threads/:id
+pub async fn get_thread(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(thread_id): Path<Uuid>,
+) -> AppResult<Json<Thread>> {
+    access::require_channel_permission(&state.pool, thread_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+
+    let thread = fetch_thread(&state.pool, thread_id).await?;
+    Ok(Json(thread))
+}
+
+/// PATCH /api/v1/threads/:id — the thread's owner or anyone with `MANAGE_CHANNELS`
+pub async fn update_thread(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(thread_id): Path<Uuid>,
+    Json(body): Json<UpdateThreadRequest>,
+) -> AppResult<Json<Thread>> {
+    let channel = access::require_channel_permission(&state.pool, thread_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+    let thread = fetch_thread(&state.pool, thread_id).await?;
+
+    if thread.owner_id != Some(auth.user_id) {
+        permissions::channel_permissions(&state.pool, &channel, auth.user_id)
+            .await?
+            .require(Permissions::MANAGE_CHANNELS)?;
+    }
+
+    let name = body.name.as_deref().map(validate_name).transpose()?;
+    if let Some(minutes) = body.auto_archive_minutes {
+        validate_auto_archive(minutes)?;
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    if let Some(name) = name {
+        sqlx::query("UPDATE channels SET name = $2 WHERE id = $1")
+            .bind(thread_id)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    // Unarchiving restarts the inactivity clock
+    sqlx::query(
+        r#"
+        UPDATE threads
+        SET
+            auto_archive_minutes = COALESCE($2, auto_archive_minutes),
+            archived             = COALESCE($3, archived),
+            archived_at          = CASE
+                                       WHEN $3 IS NULL OR $3 = archived THEN archived_at
+                                       WHEN $3 THEN now()
+                                   END,
+            last_message_at      = CASE WHEN $3 = false AND archived THEN now() ELSE last_message_at END
+        WHERE channel_id = $1
+        "#,
+    )
+    .bind(thread_id)
+    .bind(body.auto_archive_minutes)
+    .bind(body.archived)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let thread = fetch_thread(&state.pool, thread_id).await?;
+    broadcast_update(&state, &thread);
+
+    Ok(Json(thread))
+}
+
+/// GET /api/v1/threads/:id/members
+pub async fn list_thread_members(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(thread_id): Path<Uuid>,
+) -> AppResult<Json<Vec<ThreadMember>>> {
+    access::require_channel_permission(&state.pool, thread_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+    fetch_thread(&state.pool, thread_id).await?;
+
+    let rows = sqlx::query_as::<_, ThreadMemberRow>(
+        r#"
+        SELECT p.id as user_id, p.username, p.display_name, p.avatar_url, tm.joined_at
+        FROM thread_members tm
+        INNER JOIN profiles p ON p.id = tm.user_id
+        WHERE tm.thread_id = $1
+        ORDER BY tm.joined_at
+        "#,
+    )
+    .bind(thread_id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let members = rows
+        .into_iter()
+        .map(|r| ThreadMember {
+            user: ProfileSummary {
+                id: r.user_id,
+                username: r.username,
+                display_name: r.display_name,
+                avatar_url: r.avatar_url,
+            },
+            joined_at: r.joined_at,
+        })
+        .collect();
+
+    Ok(Json(members))
+}
+
+#[derive(sqlx::FromRow)]
+struct ThreadMemberRow {
+    user_id: Uuid,
+    username: Option<String>,
+    display_name: String,
+    avatar_url: Option<String>,
+    joined_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// PUT /api/v1/threads/:id/members/@me
+pub async fn join_thread(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(thread_id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+    access::require_channel_permission(&state.pool, thread_id, auth.user_id, Permissions::VIEW_CHANNEL).await?;
+    fetch_thread(&state.pool, thread_id).await?;
+
+    sqlx::query("INSERT INTO thread_members (thread_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(thread_id)
+        .bind(auth.user_id)
+        .execute(&state.pool)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /api/v1/threads/:id/members/@me
+pub async fn leave_thread(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(thread_id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+    sqlx::query("DELETE FROM thread_members WHERE thread_id = $1 AND user_id = $2")
+        .bind(thread_id)
+        .bind(auth.user_id)
+        .execute(&state.pool)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Note a new message in `channel_id` if it is a thread, in the message's
+/// transaction: bump its activity, add the author as a participant and
+/// unarchive it. Returns true if it was archived, for the caller to
+/// `broadcast_unarchived` once committed. No-op for other channels.
+pub async fn record_activity<'e>(executor: impl PgExecutor<'e>, channel_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+    let was_archived = sqlx::query_scalar::<_, bool>(
+        r#"
+        WITH bumped AS (
+            UPDATE threads t
+            SET last_message_at = now(), archived = false, archived_at = NULL
+            FROM threads old
+            WHERE t.channel_id = $1 AND old.channel_id = t.channel_id
+            RETURNING old.archived
+        ), joined AS (
+            INSERT INTO thread_members (thread_id, user_id)
+            SELECT $1, $2 WHERE EXISTS (SELECT 1 FROM bumped)
+            ON CONFLICT DO NOTHING
+        )
+        SELECT archived FROM bumped
+        "#,
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(was_archived.unwrap_or(false))
+}
+
+/// Tell clients a thread was unarchived by a new message. The message is
+/// already saved by then, so a failure is only logged.
+pub async fn broadcast_unarchived(state: &AppState, thread_id: Uuid) {
+    match fetch_thread(&state.pool, thread_id).await {
+        Ok(thread) => broadcast_update(state, &thread),
+        Err(e) => tracing::error!("Failed to load unarchived thread {thread_id}: {e}"),
+    }
+}
+
+/// Background task: archive threads that have been quiet for longer than
+/// their `auto_archive_minutes`, announcing each with `ThreadUpdate`.
+pub async fn run_auto_archive(state: AppState) {
+    let mut interval = tokio::time::interval(AUTO_ARCHIVE_SWEEP);
+    loop {
+        interval.tick().await;
+        if let Err(e) = archive_inactive(&state).await {
+            tracing::error!("Thread auto-archive failed: {e}");
+        }
+    }
+}
+
+async fn archive_inactive(state: &AppState) -> AppResult<()> {
+    let archived = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE threads SET archived = true, archived_at = now()
+        WHERE NOT archived
+          AND last_message_at < now() - make_interval(mins => auto_archive_minutes)
+        RETURNING channel_id
+        "#,
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    for thread_id in archived {
+        let thread = fetch_thread(&state.pool, thread_id).await?;
+        broadcast_update(state, &thread);
+    }
+
+    Ok(())
+}
+
+/// Send `ThreadUpdate` to both the parent channel and the thread itself.
+fn broadcast_update(state: &AppState, thread: &Thread) {
+    let event = WsEvent::ThreadUpdate { thread: thread.clone() };
+    state.ws_state.broadcast_to_channel(&thread.parent_id, event.clone());
+    state.ws_state.broadcast_to_channel(&thread.id, event);
+}
+
+async fn fetch_thread(pool: &PgPool, thread_id: Uuid) -> AppResult<Thread> {
+    sqlx::query_as::<_, Thread>(&format!("{THREAD_SELECT} WHERE t.channel_id = $1"))
+        .bind(thread_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Thread not found".into()))
+}
+
+fn validate_name(name: &str) -> AppResult<&str> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_THREAD_NAME_LEN {
+        return Err(AppError::BadRequest(format!(
+            "Thread name must be between 1 and {MAX_THREAD_NAME_LEN} characters"
+        )));
+    }
+    Ok(name)
+}
+
+fn validate_auto_archive(minutes: i32) -> AppResult<()> {
+    if !AUTO_ARCHIVE_OPTIONS.contains(&minutes) {
+        return Err(AppError::BadRequest(format!(
+            "auto_archive_minutes must be one of {AUTO_ARCHIVE_OPTIONS:?}"
+        )));
+    }
+    Ok(())
+}
@@ -1,15 +1,56 @@
-/// Voice/LiveKit REST handlers: token generation, voice state query
+//! Voice/LiveKit: token generation, and voice states set through the gateway
+//!
+//! A user is in at most one voice channel. Their voice state belongs to the
+//! gateway session that joined and is removed when that session ends; rows
+//! left behind by a backend node that died are swept by `remove_orphaned`.
+//!
+//! LiveKit has the final say: its webhooks (`POST /voice/webhook`) add,
+//! move and remove voice states to match who is actually in each room, and
+//! set video and screen share from the tracks they publish. Redelivered
+//! events are skipped, and so are events created before the user's voice
+//! state last changed, since they arrive out of order. Members who are
+//! kicked, banned or timed out are also removed from the room through
+//! LiveKit's server API, since their access token stays valid.
 
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::body::Bytes;
 use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::Json;
-use chrono::Utc;
-use jsonwebtoken::{encode, EncodingKey, Header, Algorithm};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Algorithm, Validation};
+use sha2::{Digest, Sha256};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::AppState;
-use crate::auth::AuthUser;
-use crate::error::AppResult;
+use crate::auth::{access, AuthUser};
+use crate::error::{AppError, AppResult};
+use crate::models::{Channel, ChannelType, ProfileSummary, VoiceState};
+use crate::permissions::{self, Permissions};
+use crate::ws::events::WsEvent;
+
+/// LiveKit rooms are named after their channel: `channel:<channel id>`.
+const ROOM_PREFIX: &str = "channel:";
+
+/// How long a participant's access token is valid.
+const TOKEN_TTL_SECS: usize = 6 * 60 * 60;
+
+/// How long a server API token is valid.
+const API_TOKEN_TTL_SECS: usize = 60;
+
+/// Client for LiveKit's server API.
+static LIVEKIT_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build LiveKit HTTP client")
+});
 
 /// Request body for POST /api/v1/voice/token
 #[derive(Debug, Deserialize)]
@@ -30,7 +71,8 @@ struct LiveKitClaims {
     exp: usize,
     iss: String,        // API key
     nbf: usize,
-    sub: String,        // participant identity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<String>, // participant identity
     video: LiveKitVideoGrants,
 }
 
@@ -41,6 +83,7 @@ struct LiveKitVideoGrants {
     room: String,
     can_publish: bool,
     can_subscribe: bool,
+    room_admin: bool,
 }
 
 /// POST /api/v1/voice/token — generate a LiveKit access token
@@ -49,33 +92,760 @@ pub async fn generate_voice_token(
     State(state): State<AppState>,
     Json(body): Json<VoiceTokenRequest>,
 ) -> AppResult<Json<VoiceTokenResponse>> {
-    let room_name = format!("channel:{}", body.channel_id);
-    let identity = auth.user_id.to_string();
+    access::require_channel_permission(&state.pool, body.channel_id, auth.user_id, Permissions::CONNECT).await?;
 
-    let now = Utc::now().timestamp() as usize;
-    let exp = now + (6 * 60 * 60); // 6 hours
+    let grants = LiveKitVideoGrants {
+        room_join: true,
+        room: format!("{ROOM_PREFIX}{}", body.channel_id),
+        can_publish: true,
+        can_subscribe: true,
+        room_admin: false,
+    };
+    let token = sign_token(&state, Some(auth.user_id.to_string()), grants, TOKEN_TTL_SECS)?;
 
+    Ok(Json(VoiceTokenResponse {
+        token,
+        url: state.config.livekit_url.clone(),
+    }))
+}
+
+/// Sign a LiveKit token with `grants`, for a participant (`identity`) or for
+/// the server API.
+fn sign_token(state: &AppState, identity: Option<String>, grants: LiveKitVideoGrants, ttl_secs: usize) -> AppResult<String> {
+    let now = Utc::now().timestamp() as usize;
     let claims = LiveKitClaims {
-        exp,
+        exp: now + ttl_secs,
         iss: state.config.livekit_api_key.clone(),
         nbf: 0,
         sub: identity,
-        video: LiveKitVideoGrants {
-            room_join: true,
-            room: room_name,
-            can_publish: true,
-            can_subscribe: true,
-        },
+        video: grants,
     };
 
     let header = Header::new(Algorithm::HS256);
     let key = EncodingKey::from_secret(state.config.livekit_api_secret.as_bytes());
 
-    let token = encode(&header, &claims, &key)
-        .map_err(|e| crate::error::AppError::Internal(format!("Failed to generate token: {e}")))?;
+    encode(&header, &claims, &key).map_err(|e| AppError::Internal(format!("Failed to generate token: {e}")))
+}
 
-    Ok(Json(VoiceTokenResponse {
-        token,
-        url: state.config.livekit_url.clone(),
-    }))
+/// Mute / deafen / video / screen share state sent with a voice state update.
+#[derive(Debug, Clone, Copy)]
+pub struct VoiceFlags {
+    pub muted: bool,
+    pub deafened: bool,
+    pub video_on: bool,
+    pub screen_sharing: bool,
+}
+
+/// Put the user in a voice channel with `flags`, moving them out of any
+/// other, or take them out of voice (`channel_id: None`), broadcasting
+/// `VoiceStateUpdate` for each channel affected. The voice state is handed
+/// to `session_id`, even if another of the user's sessions joined.
+///
+/// Used by the gateway; callers must already have verified `VIEW_CHANNEL`
+/// and `CONNECT` in the channel.
+pub async fn update_voice_state(
+    state: &AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+    channel_id: Option<Uuid>,
+    flags: VoiceFlags,
+) -> AppResult<()> {
+    let Some(channel_id) = channel_id else {
+        let left = sqlx::query_as::<_, VoiceState>("DELETE FROM voice_states WHERE user_id = $1 RETURNING *")
+            .bind(user_id)
+            .fetch_optional(&state.pool)
+            .await?;
+        if let Some(left) = left {
+            broadcast_left(state, &left).await?;
+        }
+        return Ok(());
+    };
+
+    let channel = access::load_channel(&state.pool, channel_id).await?;
+    if channel.kind != ChannelType::Voice {
+        return Err(AppError::BadRequest("Not a voice channel".into()));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let previous = sqlx::query_as::<_, VoiceState>("SELECT * FROM voice_states WHERE user_id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let voice_state = sqlx::query_as::<_, VoiceState>(
+        r#"
+        INSERT INTO voice_states (channel_id, user_id, muted, deafened, video_on, screen_sharing, session_id, node_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (user_id) DO UPDATE SET
+            channel_id     = EXCLUDED.channel_id,
+            muted          = EXCLUDED.muted,
+            deafened       = EXCLUDED.deafened,
+            video_on       = EXCLUDED.video_on,
+            screen_sharing = EXCLUDED.screen_sharing,
+            session_id     = EXCLUDED.session_id,
+            node_id        = EXCLUDED.node_id,
+            joined_at      = CASE WHEN voice_states.channel_id = EXCLUDED.channel_id
+                                  THEN voice_states.joined_at ELSE now() END
+        RETURNING *
+        "#,
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    // Deafened users can't be heard either
+    .bind(flags.muted || flags.deafened)
+    .bind(flags.deafened)
+    .bind(flags.video_on)
+    .bind(flags.screen_sharing)
+    .bind(session_id)
+    .bind(state.ws_state.node_id())
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if let Some(previous) = previous.filter(|p| p.channel_id != channel_id) {
+        broadcast_left(state, &previous).await?;
+    }
+    broadcast(state, &channel, user_id, Some(voice_state)).await
+}
+
+/// Take whoever joined voice through `session_id` out of it; the session
+/// has ended.
+pub async fn disconnect_session(state: &AppState, session_id: Uuid) -> AppResult<()> {
+    let left = sqlx::query_as::<_, VoiceState>("DELETE FROM voice_states WHERE session_id = $1 RETURNING *")
+        .bind(session_id)
+        .fetch_all(&state.pool)
+        .await?;
+
+    for voice_state in left {
+        broadcast_left(state, &voice_state).await?;
+    }
+    Ok(())
+}
+
+/// Remove voice states held by nodes other than `live_nodes`, whose
+/// sessions are gone with them.
+pub async fn remove_orphaned(state: &AppState, live_nodes: &[Uuid]) -> AppResult<()> {
+    let left = sqlx::query_as::<_, VoiceState>(
+        "DELETE FROM voice_states WHERE node_id IS NULL OR node_id <> ALL($1) RETURNING *"
+    )
+    .bind(live_nodes)
+    .fetch_all(&state.pool)
+    .await?;
+
+    for voice_state in left {
+        tracing::info!("Removed orphaned voice state of user {} in {}", voice_state.user_id, voice_state.channel_id);
+        broadcast_left(state, &voice_state).await?;
+    }
+    Ok(())
+}
+
+/// Take the user out of voice in `server_id`, both here and in LiveKit;
+/// they left, were kicked, banned or timed out. Failures are logged, since
+/// the leave or moderation action itself has already gone through.
+pub async fn remove_from_server(state: &AppState, server_id: Uuid, user_id: Uuid) {
+    let left = sqlx::query_as::<_, VoiceState>(
+        r#"
+        DELETE FROM voice_states
+        WHERE user_id = $1 AND channel_id IN (SELECT id FROM channels WHERE server_id = $2)
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(server_id)
+    .fetch_all(&state.pool)
+    .await;
+
+    let left = match left {
+        Ok(left) => left,
+        Err(e) => {
+            tracing::error!("Failed to remove voice state of user {user_id} in server {server_id}: {e}");
+            return;
+        }
+    };
+    for voice_state in left {
+        if let Err(e) = broadcast_left(state, &voice_state).await {
+            tracing::error!("Failed to announce user {user_id} leaving {}: {e}", voice_state.channel_id);
+        }
+        remove_participant(state, voice_state.channel_id, user_id).await;
+    }
+}
+
+/// Disconnect the user from the channel's LiveKit room. Failures are only
+/// logged: LiveKit may be unreachable, or the user may have left already.
+async fn remove_participant(state: &AppState, channel_id: Uuid, user_id: Uuid) {
+    let room = format!("{ROOM_PREFIX}{channel_id}");
+    let grants = LiveKitVideoGrants {
+        room_join: false,
+        room: room.clone(),
+        can_publish: false,
+        can_subscribe: false,
+        room_admin: true,
+    };
+    let token = match sign_token(state, None, grants, API_TOKEN_TTL_SECS) {
+        Ok(token) => token,
+        Err(e) => {
+            tracing::error!("{e}");
+            return;
+        }
+    };
+
+    let response = LIVEKIT_CLIENT
+        .post(livekit_api_url(state, "RoomService/RemoveParticipant"))
+        .bearer_auth(token)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(serde_json::json!({ "room": room, "identity": user_id.to_string() }).to_string())
+        .send()
+        .await;
+
+    match response {
+        // Not found: not (or no longer) in the room
+        Ok(r) if r.status().is_success() || r.status() == StatusCode::NOT_FOUND => {}
+        Ok(r) => tracing::error!("LiveKit refused to remove user {user_id} from {room}: {}", r.status()),
+        Err(e) => tracing::error!("Failed to remove user {user_id} from {room}: {e}"),
+    }
+}
+
+/// URL of a LiveKit server API (Twirp) method, on the same host clients
+/// connect to.
+fn livekit_api_url(state: &AppState, method: &str) -> String {
+    let base = state.config.livekit_url.trim_end_matches('/');
+    // ws:// → http://, wss:// → https://
+    let base = match base.strip_prefix("ws") {
+        Some(rest) => format!("http{rest}"),
+        None => base.to_string(),
+    };
+    format!("{base}/twirp/livekit.{method}")
+}
+
+/// Announce that the user of a removed voice state left its channel.
+async fn broadcast_left(state: &AppState, voice_state: &VoiceState) -> AppResult<()> {
+    let channel = access::load_channel(&state.pool, voice_state.channel_id).await?;
+    broadcast(state, &channel, voice_state.user_id, None).await
+}
+
+/// Send `VoiceStateUpdate` to the server members who can see `channel`.
+async fn broadcast(state: &AppState, channel: &Channel, user_id: Uuid, voice_state: Option<VoiceState>) -> AppResult<()> {
+    let user = sqlx::query_as::<_, ProfileSummary>(
+        "SELECT id, username, display_name, avatar_url FROM profiles WHERE id = $1"
+    )
+    .bind(user_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    let member_ids = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM server_members WHERE server_id = $1")
+        .bind(channel.server_id)
+        .fetch_all(&state.pool)
+        .await?;
+    let viewers =
+        permissions::members_with_channel_permission(&state.pool, channel, &member_ids, Permissions::VIEW_CHANNEL)
+            .await?;
+
+    let event = WsEvent::VoiceStateUpdate {
+        channel_id: channel.id,
+        server_id: channel.server_id,
+        user,
+        voice_state,
+    };
+    for viewer_id in viewers {
+        state.ws_state.send_to_user(&viewer_id, &event);
+    }
+    Ok(())
+}
+
+/// Claims of the token LiveKit signs each webhook with.
+#[derive(Debug, Deserialize)]
+struct WebhookClaims {
+    /// Base64 SHA-256 of the request body
+    sha256: String,
+}
+
+/// A LiveKit webhook (the fields used here).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookEvent {
+    event: String,
+    /// The same on every delivery of an event
+    id: Option<String>,
+    /// When LiveKit created the event (whole seconds)
+    #[serde(default, deserialize_with = "unix_seconds")]
+    created_at: Option<DateTime<Utc>>,
+    room: Option<WebhookRoom>,
+    participant: Option<WebhookParticipant>,
+    track: Option<WebhookTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookRoom {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookParticipant {
+    identity: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookTrack {
+    #[serde(default)]
+    source: String,
+}
+
+/// LiveKit sends Unix timestamps as strings (protobuf JSON's int64), but
+/// accept numbers too.
+fn unix_seconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Seconds {
+        Number(i64),
+        String(String),
+    }
+
+    let secs = match Option::<Seconds>::deserialize(deserializer)? {
+        Some(Seconds::Number(secs)) => secs,
+        Some(Seconds::String(secs)) => secs.parse().map_err(serde::de::Error::custom)?,
+        None => return Ok(None),
+    };
+    DateTime::from_timestamp(secs, 0)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom("timestamp out of range"))
+}
+
+/// POST /api/v1/voice/webhook — LiveKit room and participant events,
+/// authenticated with a token signed with the LiveKit API secret
+pub async fn livekit_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AppResult<StatusCode> {
+    let config = &state.config;
+    verify_webhook(&config.livekit_api_key, &config.livekit_api_secret, &headers, &body)?;
+
+    let event: WebhookEvent = serde_json::from_slice(&body)
+        .map_err(|e| AppError::BadRequest(format!("Invalid webhook: {e}")))?;
+
+    // LiveKit redelivers events it isn't sure arrived
+    if let Some(id) = &event.id {
+        if !claim_event(&state.pool, id).await? {
+            tracing::debug!("LiveKit webhook {id} already handled");
+            return Ok(StatusCode::OK);
+        }
+    }
+
+    if let Err(e) = handle_event(&state, &event).await {
+        // Let the redelivery try again
+        if let Some(id) = &event.id {
+            sqlx::query("DELETE FROM livekit_webhook_events WHERE id = $1")
+                .bind(id)
+                .execute(&state.pool)
+                .await?;
+        }
+        return Err(e);
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Record that the event with this id is being handled. False if it
+/// already was; ids older than a day are forgotten.
+async fn claim_event(pool: &PgPool, id: &str) -> AppResult<bool> {
+    sqlx::query("DELETE FROM livekit_webhook_events WHERE received_at < now() - interval '1 day'")
+        .execute(pool)
+        .await?;
+
+    let claimed = sqlx::query("INSERT INTO livekit_webhook_events (id) VALUES ($1) ON CONFLICT (id) DO NOTHING")
+        .bind(id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(claimed == 1)
+}
+
+async fn handle_event(state: &AppState, event: &WebhookEvent) -> AppResult<()> {
+    // Rooms this backend didn't name, and participants that aren't users, aren't ours
+    let Some(channel_id) = event
+        .room
+        .as_ref()
+        .and_then(|room| room.name.strip_prefix(ROOM_PREFIX))
+        .and_then(|id| Uuid::parse_str(id).ok())
+    else {
+        return Ok(());
+    };
+    let user_id = event.participant.as_ref().and_then(|p| Uuid::parse_str(&p.identity).ok());
+
+    // Voice states changed after the event (by the gateway, moderation or a
+    // later webhook) are newer than what it describes
+    if let Some(user_id) = user_id {
+        if changed_since(&state.pool, user_id, event.created_at).await? {
+            tracing::debug!("Ignoring stale LiveKit webhook: {} in {channel_id}", event.event);
+            return Ok(());
+        }
+    }
+
+    tracing::debug!("LiveKit webhook: {} in {channel_id}", event.event);
+    match (event.event.as_str(), user_id) {
+        ("participant_joined", Some(user_id)) => participant_joined(state, channel_id, user_id).await,
+        ("participant_left", Some(user_id)) => participant_left(state, channel_id, user_id).await,
+        ("track_published", Some(user_id)) | ("track_unpublished", Some(user_id)) => {
+            let column = match event.track.as_ref().map(|t| t.source.as_str()) {
+                Some("CAMERA") => "video_on",
+                Some("SCREEN_SHARE") => "screen_sharing",
+                _ => return Ok(()),
+            };
+            let on = event.event == "track_published";
+            set_track(state, channel_id, user_id, column, on).await
+        }
+        ("room_finished", _) => room_finished(state, channel_id, event.created_at).await,
+        _ => Ok(()),
+    }
+}
+
+/// Whether the user's voice state changed after `created_at`. Event times
+/// are whole seconds, so only changes from a later second count.
+async fn changed_since(pool: &PgPool, user_id: Uuid, created_at: Option<DateTime<Utc>>) -> AppResult<bool> {
+    let Some(created_at) = created_at else {
+        return Ok(false);
+    };
+    let changed = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM voice_state_changes WHERE user_id = $1 AND changed_at >= $2 + interval '1 second')"
+    )
+    .bind(user_id)
+    .bind(created_at)
+    .fetch_one(pool)
+    .await?;
+    Ok(changed)
+}
+
+/// Check the webhook's token: signed with our API secret, issued for our
+/// API key, and carrying the hash of this body.
+fn verify_webhook(api_key: &str, api_secret: &str, headers: &HeaderMap, body: &[u8]) -> AppResult<()> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.strip_prefix("Bearer ").unwrap_or(v))
+        .ok_or_else(|| AppError::Unauthorized("Missing webhook signature".into()))?;
+
+    let key = DecodingKey::from_secret(api_secret.as_bytes());
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[api_key]);
+
+    let claims = decode::<WebhookClaims>(token, &key, &validation)
+        .map_err(|e| AppError::Unauthorized(format!("Invalid webhook signature: {e}")))?
+        .claims;
+
+    if claims.sha256 != BASE64.encode(Sha256::digest(body)) {
+        return Err(AppError::Unauthorized("Webhook body doesn't match its signature".into()));
+    }
+    Ok(())
+}
+
+/// The user is in the channel's room: put them in it, moving them out of
+/// any other voice channel.
+async fn participant_joined(state: &AppState, channel_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    let perms = Permissions::VIEW_CHANNEL | Permissions::CONNECT;
+    let channel = match access::require_channel_permission(&state.pool, channel_id, user_id, perms).await {
+        Ok(channel) => channel,
+        // Deleted since the room started
+        Err(AppError::NotFound(_)) => return Ok(()),
+        // Kicked, banned or timed out, but still holding a token
+        Err(AppError::Forbidden(_)) => {
+            remove_participant(state, channel_id, user_id).await;
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut tx = state.pool.begin().await?;
+
+    let previous = sqlx::query_as::<_, VoiceState>("SELECT * FROM voice_states WHERE user_id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if previous.as_ref().is_some_and(|p| p.channel_id == channel_id) {
+        return Ok(());
+    }
+
+    // Keeps the session that joined through the gateway, and mute / deafen
+    let voice_state = sqlx::query_as::<_, VoiceState>(
+        r#"
+        INSERT INTO voice_states (channel_id, user_id, node_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO UPDATE SET
+            channel_id     = EXCLUDED.channel_id,
+            video_on       = false,
+            screen_sharing = false,
+            joined_at      = now()
+        RETURNING *
+        "#,
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .bind(state.ws_state.node_id())
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if let Some(previous) = previous {
+        broadcast_left(state, &previous).await?;
+    }
+    broadcast(state, &channel, user_id, Some(voice_state)).await
+}
+
+/// The user left the channel's room.
+async fn participant_left(state: &AppState, channel_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    // Already somewhere else if they moved
+    let left = sqlx::query_as::<_, VoiceState>(
+        "DELETE FROM voice_states WHERE user_id = $1 AND channel_id = $2 RETURNING *"
+    )
+    .bind(user_id)
+    .bind(channel_id)
+    .fetch_optional(&state.pool)
+    .await?;
+
+    if let Some(left) = left {
+        broadcast_left(state, &left).await?;
+    }
+    Ok(())
+}
+
+/// The user started or stopped publishing video (`video_on`) or their
+/// screen (`screen_sharing`) in the channel's room.
+async fn set_track(state: &AppState, channel_id: Uuid, user_id: Uuid, column: &str, on: bool) -> AppResult<()> {
+    let updated = sqlx::query_as::<_, VoiceState>(&format!(
+        "UPDATE voice_states SET {column} = $3 WHERE user_id = $1 AND channel_id = $2 AND {column} <> $3 RETURNING *"
+    ))
+    .bind(user_id)
+    .bind(channel_id)
+    .bind(on)
+    .fetch_optional(&state.pool)
+    .await?;
+
+    if let Some(voice_state) = updated {
+        let channel = access::load_channel(&state.pool, channel_id).await?;
+        broadcast(state, &channel, user_id, Some(voice_state)).await?;
+    }
+    Ok(())
+}
+
+/// The channel's room closed: everyone in it has left, apart from anyone
+/// who (re)joined after `created_at`.
+async fn room_finished(state: &AppState, channel_id: Uuid, created_at: Option<DateTime<Utc>>) -> AppResult<()> {
+    let left = sqlx::query_as::<_, VoiceState>(
+        r#"
+        DELETE FROM voice_states v
+        WHERE channel_id = $1
+          AND NOT EXISTS (
+              SELECT 1 FROM voice_state_changes c
+              WHERE c.user_id = v.user_id AND c.changed_at >= $2 + interval '1 second'
+          )
+        RETURNING *
+        "#,
+    )
+    .bind(channel_id)
+    .bind(created_at)
+    .fetch_all(&state.pool)
+    .await?;
+
+    for voice_state in left {
+        broadcast_left(state, &voice_state).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_util::{self, LIVEKIT_API_KEY, LIVEKIT_API_SECRET};
+
+    /// Room and participant in the recorded fixtures.
+    const FIXTURE_ROOM: &str = "channel:5e0d6b7a-2f4c-4a8e-9d3b-0c6a1f2e7b91";
+    const FIXTURE_IDENTITY: &str = "9b2f6c1e-4d7a-4e3b-8f50-2a1c9d6e3b74";
+
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/fixtures/livekit/{name}.json", env!("CARGO_MANIFEST_DIR"));
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{path}: {e}"))
+    }
+
+    /// Headers LiveKit sends with `body`, signed with `secret`.
+    fn signed_headers(body: &str, secret: &str) -> HeaderMap {
+        #[derive(Serialize)]
+        struct Claims {
+            iss: &'static str,
+            nbf: i64,
+            exp: i64,
+            sha256: String,
+        }
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            iss: LIVEKIT_API_KEY,
+            nbf: now,
+            exp: now + 300,
+            sha256: BASE64.encode(Sha256::digest(body.as_bytes())),
+        };
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, token.parse().unwrap());
+        headers
+    }
+
+    fn verify(headers: &HeaderMap, body: &str) -> AppResult<()> {
+        verify_webhook(LIVEKIT_API_KEY, LIVEKIT_API_SECRET, headers, body.as_bytes())
+    }
+
+    /// Deliver a recorded event about `user_id` in `channel_id`, after `edit`.
+    async fn deliver(
+        state: &AppState,
+        name: &str,
+        channel_id: Uuid,
+        user_id: Uuid,
+        edit: impl FnOnce(&mut Value),
+    ) -> AppResult<StatusCode> {
+        let body = fixture(name)
+            .replace(FIXTURE_ROOM, &format!("{ROOM_PREFIX}{channel_id}"))
+            .replace(FIXTURE_IDENTITY, &user_id.to_string());
+        let mut event: Value = serde_json::from_str(&body).unwrap();
+        edit(&mut event);
+        let body = event.to_string();
+
+        let headers = signed_headers(&body, LIVEKIT_API_SECRET);
+        livekit_webhook(State(state.clone()), headers, Bytes::from(body)).await
+    }
+
+    /// Give the event a new id, created now (or `offset_secs` from now).
+    fn fresh(offset_secs: i64) -> impl FnOnce(&mut Value) {
+        move |event| {
+            event["id"] = format!("EV_{}", Uuid::new_v4().simple()).into();
+            event["createdAt"] = (Utc::now().timestamp() + offset_secs).to_string().into();
+        }
+    }
+
+    async fn voice_channel_of(pool: &PgPool, user_id: Uuid) -> Option<Uuid> {
+        sqlx::query_scalar("SELECT channel_id FROM voice_states WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_recorded_events() {
+        let joined: WebhookEvent = serde_json::from_str(&fixture("participant_joined")).unwrap();
+        assert_eq!(joined.event, "participant_joined");
+        assert_eq!(joined.id.as_deref(), Some("EV_gxXtXXWrE6xy"));
+        assert_eq!(joined.created_at, DateTime::from_timestamp(1729260413, 0));
+        assert_eq!(joined.room.unwrap().name, FIXTURE_ROOM);
+        assert_eq!(joined.participant.unwrap().identity, FIXTURE_IDENTITY);
+
+        let left: WebhookEvent = serde_json::from_str(&fixture("participant_left")).unwrap();
+        assert_eq!(left.event, "participant_left");
+        assert_eq!(left.created_at, DateTime::from_timestamp(1729260502, 0));
+
+        let finished: WebhookEvent = serde_json::from_str(&fixture("room_finished")).unwrap();
+        assert_eq!(finished.event, "room_finished");
+        assert!(finished.participant.is_none());
+    }
+
+    #[test]
+    fn reads_created_at_as_string_or_number() {
+        let parse = |json: &str| serde_json::from_str::<WebhookEvent>(json).map(|e| e.created_at);
+        assert_eq!(parse(r#"{"event":"x","createdAt":"60"}"#).unwrap(), DateTime::from_timestamp(60, 0));
+        assert_eq!(parse(r#"{"event":"x","createdAt":60}"#).unwrap(), DateTime::from_timestamp(60, 0));
+        assert_eq!(parse(r#"{"event":"x"}"#).unwrap(), None);
+        assert!(parse(r#"{"event":"x","createdAt":"soon"}"#).is_err());
+    }
+
+    #[test]
+    fn accepts_signed_webhooks() {
+        for name in ["participant_joined", "participant_left", "room_finished"] {
+            let body = fixture(name);
+            verify(&signed_headers(&body, LIVEKIT_API_SECRET), &body).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tokens() {
+        let body = fixture("participant_joined");
+
+        let wrong_secret = signed_headers(&body, "not-the-secret");
+        assert!(matches!(verify(&wrong_secret, &body), Err(AppError::Unauthorized(_))));
+
+        let mut garbage = HeaderMap::new();
+        garbage.insert(header::AUTHORIZATION, "Bearer not.a.jwt".parse().unwrap());
+        assert!(matches!(verify(&garbage, &body), Err(AppError::Unauthorized(_))));
+
+        assert!(matches!(verify(&HeaderMap::new(), &body), Err(AppError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn rejects_bodies_that_do_not_match_their_token() {
+        let body = fixture("participant_joined");
+        let headers = signed_headers(&body, LIVEKIT_API_SECRET);
+        let tampered = body.replace("participant_joined", "participant_left");
+        assert!(matches!(verify(&headers, &tampered), Err(AppError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn joins_and_leaves_from_recorded_events() {
+        let state = test_util::app_state(test_util::database().await);
+        let alice = test_util::user(&state.pool, "Alice").await;
+        let server = test_util::server(&state.pool, alice).await;
+        let channel = server.voice_channel;
+
+        deliver(&state, "participant_joined", channel, alice, |_| {}).await.unwrap();
+        assert_eq!(voice_channel_of(&state.pool, alice).await, Some(channel));
+
+        deliver(&state, "participant_left", channel, alice, fresh(0)).await.unwrap();
+        assert_eq!(voice_channel_of(&state.pool, alice).await, None);
+
+        deliver(&state, "participant_joined", channel, alice, fresh(0)).await.unwrap();
+        deliver(&state, "room_finished", channel, alice, fresh(0)).await.unwrap();
+        assert_eq!(voice_channel_of(&state.pool, alice).await, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn skips_redelivered_events() {
+        let state = test_util::app_state(test_util::database().await);
+        let alice = test_util::user(&state.pool, "Alice").await;
+        let channel = test_util::server(&state.pool, alice).await.voice_channel;
+
+        deliver(&state, "participant_joined", channel, alice, |_| {}).await.unwrap();
+        // Forget the change, so only the event id can tell it's a redelivery
+        sqlx::query("DELETE FROM voice_states").execute(&state.pool).await.unwrap();
+        sqlx::query("DELETE FROM voice_state_changes").execute(&state.pool).await.unwrap();
+
+        let status = deliver(&state, "participant_joined", channel, alice, |_| {}).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(voice_channel_of(&state.pool, alice).await, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn ignores_events_older_than_the_last_change() {
+        let state = test_util::app_state(test_util::database().await);
+        let alice = test_util::user(&state.pool, "Alice").await;
+        let channel = test_util::server(&state.pool, alice).await.voice_channel;
+
+        // Joined through the gateway; a leave from an earlier visit arrives late
+        let session_id = Uuid::new_v4();
+        let flags = VoiceFlags { muted: false, deafened: false, video_on: false, screen_sharing: false };
+        update_voice_state(&state, alice, session_id, Some(channel), flags).await.unwrap();
+        deliver(&state, "participant_left", channel, alice, fresh(-5)).await.unwrap();
+        assert_eq!(voice_channel_of(&state.pool, alice).await, Some(channel));
+
+        // Left through the gateway; the join arrives late
+        update_voice_state(&state, alice, session_id, None, flags).await.unwrap();
+        deliver(&state, "participant_joined", channel, alice, fresh(-5)).await.unwrap();
+        assert_eq!(voice_channel_of(&state.pool, alice).await, None);
+
+        // Kept in a room that finished before they joined
+        deliver(&state, "participant_joined", channel, alice, fresh(0)).await.unwrap();
+        deliver(&state, "room_finished", channel, alice, fresh(-5)).await.unwrap();
+        assert_eq!(voice_channel_of(&state.pool, alice).await, Some(channel));
+    }
 }
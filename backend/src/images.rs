@@ -0,0 +1,285 @@
+//! Avatar, banner and server icon processing.
+//!
+//! Uploads are decoded (PNG, JPEG, GIF, WebP), rotated upright per their EXIF
+//! orientation and re-encoded, which drops EXIF and any other metadata. Each
+//! image is stored as a set of resized variants under a content-addressed
+//! prefix, `{kind}/{hash}/{size}.{ext}`, where `size` is one of the kind's
+//! `sizes()`. Animated GIFs keep their animation in `.gif` variants and also
+//! get a still `.png` of the first frame at every size; their hash starts
+//! with `a_`.
+
+use std::io::Cursor;
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageFormat, ImageReader, Limits, RgbaImage};
+use sha2::{Digest, Sha256};
+
+use crate::AppState;
+use crate::error::{AppError, AppResult};
+
+/// Largest upload accepted for any image kind.
+pub const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Largest source dimension decoded, in pixels.
+const MAX_SOURCE_DIMENSION: u32 = 4096;
+
+/// Most GIF frames kept; longer animations are rejected.
+const MAX_GIF_FRAMES: usize = 300;
+
+/// Most memory all of a GIF's decoded RGBA frames may take together;
+/// larger animations are rejected.
+const MAX_GIF_DECODED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Hex characters of the SHA-256 used in an image's hash.
+const HASH_CHARS: usize = 32;
+
+/// What an uploaded image is used for, which decides its shape and sizes.
+#[derive(Debug, Clone, Copy)]
+pub enum ImageKind {
+    Avatar,
+    ProfileBanner,
+    ServerIcon,
+    ServerBanner,
+}
+
+impl ImageKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            ImageKind::Avatar => "avatars",
+            ImageKind::ProfileBanner => "profile-banners",
+            ImageKind::ServerIcon => "icons",
+            ImageKind::ServerBanner => "banners",
+        }
+    }
+
+    /// Variant widths, smallest first.
+    pub fn sizes(self) -> &'static [u32] {
+        match self {
+            ImageKind::Avatar | ImageKind::ServerIcon => &[64, 128, 256, 512],
+            ImageKind::ProfileBanner | ImageKind::ServerBanner => &[480, 960, 1920],
+        }
+    }
+
+    /// The variant stored in `avatar_url` / `icon_url` / `banner_url`.
+    fn default_size(self) -> u32 {
+        match self {
+            ImageKind::Avatar | ImageKind::ServerIcon => 256,
+            ImageKind::ProfileBanner | ImageKind::ServerBanner => 960,
+        }
+    }
+
+    /// Height for a variant `width` wide: square icons, 3:1 banners.
+    fn height(self, width: u32) -> u32 {
+        match self {
+            ImageKind::Avatar | ImageKind::ServerIcon => width,
+            ImageKind::ProfileBanner | ImageKind::ServerBanner => width / 3,
+        }
+    }
+}
+
+/// One encoded variant, before it's written out.
+struct Variant {
+    name: String,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+/// Validate and process an uploaded image, store all its variants and return
+/// the URL of the default one.
+pub async fn store(state: &AppState, kind: ImageKind, body: Vec<u8>) -> AppResult<String> {
+    if body.len() > MAX_IMAGE_BYTES {
+        return Err(AppError::BadRequest(format!(
+            "Image cannot exceed {} MiB",
+            MAX_IMAGE_BYTES / (1024 * 1024)
+        )));
+    }
+
+    // Decoding and resizing are CPU-bound
+    let (hash, variants) = tokio::task::spawn_blocking(move || process(kind, &body))
+        .await
+        .map_err(|e| AppError::Internal(format!("Image processing failed: {e}")))??;
+
+    let prefix = format!("{}/{hash}", kind.prefix());
+    let default_ext = if hash.starts_with("a_") { "gif" } else { "png" };
+    let url = state.storage.url(&format!("{prefix}/{}.{default_ext}", kind.default_size()));
+
+    for variant in variants {
+        state
+            .storage
+            .put(&format!("{prefix}/{}", variant.name), variant.body, variant.content_type)
+            .await?;
+    }
+
+    Ok(url)
+}
+
+/// Decode `body` and encode every variant for `kind`.
+fn process(kind: ImageKind, body: &[u8]) -> AppResult<(String, Vec<Variant>)> {
+    let format = image::guess_format(body).map_err(|_| unsupported())?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP) {
+        return Err(unsupported());
+    }
+
+    let digest = hex::encode(Sha256::digest(body));
+    let digest = &digest[..HASH_CHARS];
+
+    if format == ImageFormat::Gif {
+        let frames = decode_gif(body)?;
+        if frames.len() > 1 {
+            let variants = animated_variants(kind, frames)?;
+            return Ok((format!("a_{digest}"), variants));
+        }
+    }
+
+    let image = decode_still(body)?;
+    let variants = kind
+        .sizes()
+        .iter()
+        .map(|&size| encode_png(format!("{size}.png"), &fit(kind, &image, size)))
+        .collect::<AppResult<Vec<_>>>()?;
+
+    Ok((digest.to_string(), variants))
+}
+
+/// Decode a still image, applying its EXIF orientation.
+fn decode_still(body: &[u8]) -> AppResult<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(body))
+        .with_guessed_format()
+        .map_err(|_| unsupported())?;
+    reader.limits(limits());
+
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+
+    Ok(image)
+}
+
+/// Decode every frame of a GIF, composited onto the full canvas.
+fn decode_gif(body: &[u8]) -> AppResult<Vec<Frame>> {
+    let mut decoder = GifDecoder::new(Cursor::new(body)).map_err(invalid)?;
+    decoder.set_limits(limits()).map_err(invalid)?;
+
+    // Every frame is composited onto the full canvas, so each one costs the
+    // same; stop as soon as the total would go over the cap
+    let (width, height) = decoder.dimensions();
+    let frame_bytes = u64::from(width) * u64::from(height) * 4;
+
+    let mut frames = Vec::new();
+    let mut decoded_bytes = 0;
+    for frame in decoder.into_frames() {
+        if frames.len() == MAX_GIF_FRAMES {
+            return Err(AppError::BadRequest(format!("GIFs can have at most {MAX_GIF_FRAMES} frames")));
+        }
+        decoded_bytes += frame_bytes;
+        if decoded_bytes > MAX_GIF_DECODED_BYTES {
+            return Err(AppError::BadRequest("GIF is too large to decode".into()));
+        }
+        frames.push(frame.map_err(invalid)?);
+    }
+    Ok(frames)
+}
+
+/// Animated `.gif` variants plus a still `.png` of the first frame per size.
+fn animated_variants(kind: ImageKind, frames: Vec<Frame>) -> AppResult<Vec<Variant>> {
+    let mut variants = Vec::new();
+
+    for &size in kind.sizes() {
+        let resized: Vec<Frame> = frames
+            .iter()
+            .map(|frame| {
+                let image = fit(kind, &DynamicImage::ImageRgba8(frame.buffer().clone()), size);
+                Frame::from_parts(image.to_rgba8(), 0, 0, frame.delay())
+            })
+            .collect();
+
+        let first = DynamicImage::ImageRgba8(resized[0].buffer().clone());
+        variants.push(encode_png(format!("{size}.png"), &first)?);
+
+        let mut body = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut body, 10);
+            encoder.set_repeat(Repeat::Infinite).map_err(encode_failed)?;
+            encoder.encode_frames(resized).map_err(encode_failed)?;
+        }
+        variants.push(Variant { name: format!("{size}.gif"), content_type: "image/gif", body });
+    }
+
+    Ok(variants)
+}
+
+/// Center-crop to the kind's aspect ratio and resize to `width`.
+fn fit(kind: ImageKind, image: &DynamicImage, width: u32) -> DynamicImage {
+    image.resize_to_fill(width, kind.height(width), FilterType::Triangle)
+}
+
+fn encode_png(name: String, image: &DynamicImage) -> AppResult<Variant> {
+    let rgba: RgbaImage = image.to_rgba8();
+    let mut body = Vec::new();
+    rgba.write_to(&mut Cursor::new(&mut body), ImageFormat::Png)
+        .map_err(encode_failed)?;
+    Ok(Variant { name, content_type: "image/png", body })
+}
+
+fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits
+}
+
+fn unsupported() -> AppError {
+    AppError::BadRequest("Image must be a PNG, JPEG, GIF or WebP".into())
+}
+
+fn invalid(e: image::ImageError) -> AppError {
+    AppError::BadRequest(format!("Invalid image: {e}"))
+}
+
+fn encode_failed(e: image::ImageError) -> AppError {
+    AppError::Internal(format!("Failed to encode image: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GIF with a `width`×`height` canvas and `frames` 1×1 frames.
+    fn gif(width: u16, height: u16, frames: usize) -> Vec<u8> {
+        let mut body = b"GIF89a".to_vec();
+        body.extend(width.to_le_bytes());
+        body.extend(height.to_le_bytes());
+        // Two-colour global palette: black, white
+        body.extend([0x80, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff]);
+        for _ in 0..frames {
+            body.extend([0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+            body.extend([0x02, 0x02, 0x44, 0x01, 0x00]);
+        }
+        body.push(0x3b);
+        body
+    }
+
+    #[test]
+    fn decodes_small_animations() {
+        let frames = decode_gif(&gif(16, 16, 3)).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (16, 16));
+    }
+
+    #[test]
+    fn rejects_too_many_frames() {
+        assert!(decode_gif(&gif(1, 1, MAX_GIF_FRAMES)).is_ok());
+        assert!(decode_gif(&gif(1, 1, MAX_GIF_FRAMES + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_animations_too_large_to_decode() {
+        // A few hundred bytes that would expand to 300 × 64 MiB
+        let body = gif(4096, 4096, MAX_GIF_FRAMES);
+        assert!(body.len() < 5000);
+        let result = decode_gif(&body);
+        assert!(matches!(result, Err(AppError::BadRequest(msg)) if msg.contains("too large")));
+    }
+}
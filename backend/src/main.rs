@@ -2,7 +2,10 @@
 //!
 //! Powered by Axum, Tokio, SQLx (Supabase PostgreSQL), and LiveKit.
 
-use axum::routing::{delete, get, patch, post};
+use std::sync::Arc;
+
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{delete, get, patch, post, put};
 use axum::Router;
 use sqlx::PgPool;
 use tower_http::cors::CorsLayer;
@@ -12,16 +15,24 @@ mod config;
 mod db;
 mod error;
 mod auth;
+mod audit;
+mod images;
 mod models;
 mod handlers;
+mod permissions;
+mod storage;
 mod ws;
 
+#[cfg(test)]
+mod test_util;
+
 /// Shared application state available to all handlers.
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
     pub config: config::AppConfig,
     pub ws_state: ws::WsState,
+    pub storage: Arc<dyn storage::Storage>,
 }
 
 /// Build the `/api/v1` router with all REST + WS routes.
@@ -29,25 +40,77 @@ fn api_router() -> Router<AppState> {
     Router::new()
         // Auth (combined on same path)
         .route("/auth/me", get(auth::handlers::get_me).patch(auth::handlers::update_me))
+        .route("/auth/me/avatar", put(handlers::images::upload_avatar).delete(handlers::images::delete_avatar).layer(upload_limit(images::MAX_IMAGE_BYTES)))
+        .route("/auth/me/banner", put(handlers::images::upload_profile_banner).delete(handlers::images::delete_profile_banner).layer(upload_limit(images::MAX_IMAGE_BYTES)))
+        // Users
+        .route("/users/@me/mentions", get(handlers::mentions::list_mentions))
+        .route("/users/@me/custom-status", put(handlers::presence::set_custom_status).delete(handlers::presence::clear_custom_status))
+        .route("/users/:id/presence", get(handlers::presence::get_presence))
         // Servers
         .route("/servers", get(handlers::servers::list_servers).post(handlers::servers::create_server))
         .route("/servers/discover", get(handlers::servers::discover_servers))
         .route("/servers/:id", get(handlers::servers::get_server))
         .route("/servers/:id/join", post(handlers::servers::join_server))
         .route("/servers/:id/leave", delete(handlers::servers::leave_server))
+        .route("/servers/:id/members", get(handlers::servers::list_members))
+        .route("/servers/:id/icon", put(handlers::images::upload_server_icon).delete(handlers::images::delete_server_icon).layer(upload_limit(images::MAX_IMAGE_BYTES)))
+        .route("/servers/:id/banner", put(handlers::images::upload_server_banner).delete(handlers::images::delete_server_banner).layer(upload_limit(images::MAX_IMAGE_BYTES)))
+        .route("/servers/:id/invites", get(handlers::invites::list_invites).post(handlers::invites::create_invite))
+        // Moderation
+        .route("/servers/:id/members/:user_id", delete(handlers::moderation::kick_member))
+        .route("/servers/:id/members/:user_id/timeout", put(handlers::moderation::timeout_member).delete(handlers::moderation::remove_timeout))
+        .route("/servers/:id/bans", get(handlers::moderation::list_bans))
+        .route("/servers/:id/bans/:user_id", put(handlers::moderation::ban_member).delete(handlers::moderation::unban_member))
+        .route("/servers/:id/audit-log", get(handlers::audit_log::get_audit_log))
+        .route("/servers/:id/messages/search", get(handlers::search::search_server_messages))
+        .route("/servers/:id/read-states", get(handlers::read_states::list_server_read_states))
+        // Roles
+        .route("/servers/:id/roles", get(handlers::roles::list_roles).post(handlers::roles::create_role))
+        .route("/servers/:id/roles/:role_id", patch(handlers::roles::update_role).delete(handlers::roles::delete_role))
+        .route("/servers/:id/members/:user_id/roles/:role_id", put(handlers::roles::add_member_role).delete(handlers::roles::remove_member_role))
         // Channels
         .route("/servers/:id/channels", get(handlers::channels::list_channels).post(handlers::channels::create_channel))
+        .route("/channels/:id/overwrites", get(handlers::channels::list_overwrites))
+        .route("/channels/:id/overwrites/:target_id", put(handlers::channels::put_overwrite).delete(handlers::channels::delete_overwrite))
         .route("/channels/:id/messages", get(handlers::channels::get_messages))
+        .route("/channels/:id/messages/:msg_id", patch(handlers::channels::update_message).delete(handlers::channels::delete_message))
+        .route("/channels/:id/attachments", post(handlers::attachments::upload_attachment).layer(upload_limit(handlers::attachments::MAX_ATTACHMENT_BYTES)))
+        .route("/channels/:id/messages/bulk-delete", post(handlers::channels::bulk_delete_messages))
+        .route("/channels/:id/messages/:msg_id/ack", post(handlers::read_states::ack_message))
+        .route("/channels/:id/messages/:msg_id/reactions/:emoji", get(handlers::reactions::list_reaction_users))
+        .route("/channels/:id/messages/:msg_id/reactions/:emoji/@me", put(handlers::reactions::add_reaction).delete(handlers::reactions::remove_own_reaction))
+        .route("/channels/:id/messages/:msg_id/reactions/:emoji/:user_id", delete(handlers::reactions::remove_user_reaction))
         .route("/channels/:id/voice-state", get(handlers::channels::get_voice_state))
+        // Threads
+        .route("/channels/:id/messages/:msg_id/threads", post(handlers::threads::create_thread))
+        .route("/channels/:id/threads", get(handlers::threads::list_threads))
+        .route("/threads/:id", get(handlers::threads::get_thread).patch(handlers::threads::update_thread))
+        .route("/threads/:id/members", get(handlers::threads::list_thread_members))
+        .route("/threads/:id/members/@me", put(handlers::threads::join_thread).delete(handlers::threads::leave_thread))
+        // Invites
+        .route("/invites/:code", get(handlers::invites::get_invite).delete(handlers::invites::revoke_invite))
+        .route("/invites/:code/accept", post(handlers::invites::accept_invite))
         // DMs
         .route("/dms", get(handlers::dms::list_dms).post(handlers::dms::create_dm))
         .route("/dms/:id/messages", get(handlers::dms::get_dm_messages))
+        .route("/dms/:id/messages/search", get(handlers::search::search_dm_messages))
+        .route("/dms/:id/attachments", post(handlers::attachments::upload_dm_attachment).layer(upload_limit(handlers::attachments::MAX_ATTACHMENT_BYTES)))
+        .route("/dms/:id/messages/:msg_id", patch(handlers::dms::update_dm_message).delete(handlers::dms::delete_dm_message))
+        .route("/dms/:id/messages/:msg_id/ack", post(handlers::read_states::ack_dm_message))
+        .route("/dms/:id/messages/:msg_id/reactions/:emoji", get(handlers::reactions::list_dm_reaction_users))
+        .route("/dms/:id/messages/:msg_id/reactions/:emoji/@me", put(handlers::reactions::add_dm_reaction).delete(handlers::reactions::remove_own_dm_reaction))
         // Voice / LiveKit
         .route("/voice/token", post(handlers::voice::generate_voice_token))
+        .route("/voice/webhook", post(handlers::voice::livekit_webhook))
         // WebSocket
         .route("/ws", get(ws::router::ws_handler))
 }
 
+/// Body limit for upload routes: the largest file plus multipart overhead.
+fn upload_limit(max_file_bytes: usize) -> DefaultBodyLimit {
+    DefaultBodyLimit::max(max_file_bytes + 64 * 1024)
+}
+
 #[tokio::main]
 async fn main() {
     // Load .env file (ignoring errors if not present)
@@ -72,14 +135,34 @@ async fn main() {
 
     // Build shared state
     let state = AppState {
+        storage: storage::from_config(&config.storage),
+        ws_state: ws::WsState::new(ws::bus::from_config(config.event_bus, &pool)),
         pool,
         config,
-        ws_state: ws::WsState::new(),
     };
 
+    // Exchange gateway events and presence with other nodes
+    state.ws_state.start_bus();
+    tokio::spawn(ws::bus::run_node_heartbeat(state.clone()));
+
+    // Archive threads that have gone quiet
+    tokio::spawn(handlers::threads::run_auto_archive(state.clone()));
+
+    // Sweep unsent uploads and files of deleted messages
+    tokio::spawn(handlers::attachments::run_cleanup(state.clone()));
+
+    // Clear custom statuses that have expired
+    tokio::spawn(handlers::presence::run_custom_status_expiry(state.clone()));
+
     // Build application
-    let app = Router::new()
-        .nest("/api/v1", api_router())
+    let mut app = Router::new().nest("/api/v1", api_router());
+
+    // Local storage is served by the backend itself
+    if let config::StorageConfig::Local { dir, url_secret, .. } = &state.config.storage {
+        app = app.nest("/uploads", storage::local::service(dir, url_secret));
+    }
+
+    let app = app
         .layer(CorsLayer::permissive())
         .with_state(state);
 
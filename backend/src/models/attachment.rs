@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An uploaded file, as embedded in messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    /// Size in bytes
+    pub size: i64,
+    /// Pixel dimensions, for images
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub url: String,
+}
@@ -0,0 +1,63 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Kind of change recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "audit_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    MemberJoin,
+    MemberLeave,
+    MemberKick,
+    MemberBanAdd,
+    MemberBanRemove,
+    MemberUpdate,
+    MemberRoleAdd,
+    MemberRoleRemove,
+    ChannelCreate,
+    ChannelOverwriteUpdate,
+    ChannelOverwriteDelete,
+    RoleCreate,
+    RoleUpdate,
+    RoleDelete,
+    InviteCreate,
+    InviteDelete,
+    MessageDelete,
+    MessageBulkDelete,
+    ServerUpdate,
+}
+
+/// One field changed by an audited action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditChange {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<Value>,
+}
+
+/// Mirrors public.audit_log_entries table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub action: AuditAction,
+    pub target_id: Option<Uuid>,
+    pub changes: sqlx::types::Json<Vec<AuditChange>>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Query params for the audit log
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    /// Only entries older than this one (pass the last entry's `id`)
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+}
@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::permissions::Permissions;
+
 /// PostgreSQL enum: channel_type
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "channel_type", rename_all = "lowercase")]
@@ -19,6 +21,8 @@ pub struct Channel {
     pub kind: ChannelType,
     pub position: i32,
     pub created_at: DateTime<Utc>,
+    /// Set for threads: the channel the thread was started in
+    pub parent_id: Option<Uuid>,
 }
 
 /// Request body for creating a channel
@@ -26,6 +30,39 @@ pub struct Channel {
 pub struct CreateChannelRequest {
     pub name: String,
     pub kind: ChannelType,
+    /// Hide the channel from @everyone; only the creator is let in
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// PostgreSQL enum: overwrite_kind
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "overwrite_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OverwriteKind {
+    Role,
+    Member,
+}
+
+/// Mirrors public.channel_overwrites table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ChannelOverwrite {
+    pub channel_id: Uuid,
+    /// A role id (kind = role) or user id (kind = member)
+    pub target_id: Uuid,
+    pub kind: OverwriteKind,
+    pub allow: Permissions,
+    pub deny: Permissions,
+}
+
+/// Request body for creating / replacing a channel overwrite
+#[derive(Debug, Deserialize)]
+pub struct PutOverwriteRequest {
+    pub kind: OverwriteKind,
+    #[serde(default)]
+    pub allow: Permissions,
+    #[serde(default)]
+    pub deny: Permissions,
 }
 
 /// Mirrors public.voice_states table
@@ -34,6 +71,9 @@ pub struct VoiceState {
     pub channel_id: Uuid,
     pub user_id: Uuid,
     pub muted: bool,
+    pub deafened: bool,
     pub video_on: bool,
+    pub screen_sharing: bool,
+    /// When the user joined this channel (moving channels resets it)
     pub joined_at: DateTime<Utc>,
 }
@@ -2,7 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::ProfileSummary;
+use super::{Attachment, ProfileSummary, ReactionCount};
 
 /// Mirrors public.dm_channels table
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -12,6 +12,7 @@ pub struct DmChannel {
 }
 
 /// Mirrors public.dm_members table
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct DmMember {
     pub dm_channel_id: Uuid,
@@ -27,6 +28,7 @@ pub struct DmMessage {
     pub content: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 /// DM channel summary for sidebar (with other participant + last message)
@@ -37,6 +39,7 @@ pub struct DmChannelSummary {
     pub last_message: Option<String>,
     pub last_message_at: Option<DateTime<Utc>>,
     pub unread_count: i64,
+    pub mention_count: i64,
 }
 
 /// DM message with embedded author (for API responses)
@@ -47,6 +50,10 @@ pub struct DmMessageWithAuthor {
     pub author: ProfileSummary,
     pub content: String,
     pub created_at: DateTime<Utc>,
+    /// Set when the message has been edited (backed by `updated_at`)
+    pub edited_at: Option<DateTime<Utc>>,
+    pub attachments: Vec<Attachment>,
+    pub reactions: Vec<ReactionCount>,
 }
 
 /// Request body for creating / finding a DM channel
@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{ProfileSummary, ServerWithMemberCount};
+
+/// Mirrors public.invites table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Invite {
+    pub code: String,
+    pub server_id: Uuid,
+    pub channel_id: Option<Uuid>,
+    pub inviter_id: Option<Uuid>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub temporary: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request body for creating an invite
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteRequest {
+    /// Land the invitee in this channel (must belong to the server)
+    pub channel_id: Option<Uuid>,
+    /// Omit or 0 for unlimited uses
+    pub max_uses: Option<i32>,
+    /// Lifetime in seconds; omit or 0 for an invite that never expires
+    pub max_age_secs: Option<i64>,
+    #[serde(default)]
+    pub temporary: bool,
+}
+
+/// Public preview shown before accepting an invite
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvitePreview {
+    pub code: String,
+    pub server: ServerWithMemberCount,
+    pub channel_id: Option<Uuid>,
+    pub channel_name: Option<String>,
+    pub inviter: Option<ProfileSummary>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub temporary: bool,
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::MessageWithAuthor;
+
+/// Query parameters for the recent-mentions inbox
+#[derive(Debug, Deserialize)]
+pub struct MentionQuery {
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+    /// Only mentions from this server
+    pub server_id: Option<Uuid>,
+}
+
+/// A message that mentioned the user, for the recent-mentions inbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentMention {
+    pub server_id: Uuid,
+    #[serde(flatten)]
+    pub message: MessageWithAuthor,
+}
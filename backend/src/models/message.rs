@@ -2,7 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::ProfileSummary;
+use super::{Attachment, ProfileSummary, ReactionCount};
 
 /// Mirrors public.messages table
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -13,6 +13,16 @@ pub struct Message {
     pub content: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub reply_to_id: Option<Uuid>,
+}
+
+/// Which kind of message something refers to: a channel `Message` or a
+/// `DmMessage`
+#[derive(Debug, Clone, Copy)]
+pub enum MessageKind {
+    Channel,
+    Dm,
 }
 
 /// Message with embedded author profile (for API responses)
@@ -23,6 +33,30 @@ pub struct MessageWithAuthor {
     pub author: ProfileSummary,
     pub content: String,
     pub created_at: DateTime<Utc>,
+    /// Set when the message has been edited (backed by `updated_at`)
+    pub edited_at: Option<DateTime<Utc>>,
+    /// The message this one replies to
+    pub reply_to: Option<MessageReference>,
+    /// Thread started from this message, if any
+    pub thread_id: Option<Uuid>,
+    pub attachments: Vec<Attachment>,
+    pub reactions: Vec<ReactionCount>,
+    /// Users named with `<@id>` (only those who can see the channel)
+    pub mentions: Vec<ProfileSummary>,
+    /// Roles named with `<@&id>`
+    pub mention_roles: Vec<Uuid>,
+    /// Set when `@everyone` or `@here` notified the channel
+    pub mention_everyone: bool,
+}
+
+/// Preview of a replied-to message embedded in the reply
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReference {
+    pub id: Uuid,
+    pub author: ProfileSummary,
+    /// Start of the referenced message's content (empty once deleted)
+    pub content: String,
+    pub deleted: bool,
 }
 
 /// Query parameters for paginated message fetching
@@ -31,3 +65,15 @@ pub struct MessageQuery {
     pub before: Option<Uuid>,
     pub limit: Option<i64>,
 }
+
+/// Request body for editing a channel or DM message
+#[derive(Debug, Deserialize)]
+pub struct UpdateMessageRequest {
+    pub content: String,
+}
+
+/// Request body for moderator bulk deletion
+#[derive(Debug, Deserialize)]
+pub struct BulkDeleteMessagesRequest {
+    pub message_ids: Vec<Uuid>,
+}
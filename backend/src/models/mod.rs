@@ -3,9 +3,29 @@ pub mod server;
 pub mod channel;
 pub mod message;
 pub mod dm;
+pub mod role;
+pub mod invite;
+pub mod moderation;
+pub mod audit_log;
+pub mod thread;
+pub mod reaction;
+pub mod attachment;
+pub mod search;
+pub mod read_state;
+pub mod mention;
 
 pub use profile::*;
 pub use server::*;
 pub use channel::*;
 pub use message::*;
 pub use dm::*;
+pub use role::*;
+pub use invite::*;
+pub use moderation::*;
+pub use audit_log::*;
+pub use thread::*;
+pub use reaction::*;
+pub use attachment::*;
+pub use search::*;
+pub use read_state::*;
+pub use mention::*;
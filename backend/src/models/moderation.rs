@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::ProfileSummary;
+
+/// Mirrors public.server_bans table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ServerBan {
+    pub server_id: Uuid,
+    pub user_id: Uuid,
+    pub reason: Option<String>,
+    pub banned_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Ban list entry with the banned user's profile
+#[derive(Debug, Clone, Serialize)]
+pub struct BanWithUser {
+    pub user: ProfileSummary,
+    pub reason: Option<String>,
+    pub banned_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request body for banning a member
+#[derive(Debug, Default, Deserialize)]
+pub struct BanRequest {
+    pub reason: Option<String>,
+    /// Also delete the user's messages from the last N days (0–7)
+    #[serde(default)]
+    pub delete_message_days: i64,
+}
+
+/// Request body for timing out a member
+#[derive(Debug, Deserialize)]
+pub struct TimeoutRequest {
+    /// How long the member can't send messages or join voice, in seconds
+    pub duration_secs: i64,
+}
+
+/// How a member left a server: on their own, or removed by a moderator
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalKind {
+    Leave,
+    Kick,
+    Ban,
+}
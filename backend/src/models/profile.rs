@@ -3,13 +3,16 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 /// PostgreSQL enum: user_status
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "user_status", rename_all = "lowercase")]
 pub enum UserStatus {
     Online,
     Idle,
     Dnd,
     Offline,
+    /// Connected, but shown to others as offline
+    Invisible,
 }
 
 /// Mirrors public.profiles table (linked to Supabase auth.users)
@@ -18,8 +21,14 @@ pub struct Profile {
     pub id: Uuid,
     pub username: Option<String>,
     pub display_name: String,
+    /// Uploaded avatar image, or an emoji avatar
     pub avatar_url: Option<String>,
+    pub banner_url: Option<String>,
+    /// The status the user last picked (see `ws::presence`)
     pub status: UserStatus,
+    pub custom_status_text: Option<String>,
+    pub custom_status_emoji: Option<String>,
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -32,3 +41,33 @@ pub struct ProfileSummary {
     pub display_name: String,
     pub avatar_url: Option<String>,
 }
+
+/// A user's current presence, as seen by others
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub user_id: Uuid,
+    /// Never `invisible`; invisible users are `offline`
+    pub status: UserStatus,
+    /// Only shown while the user appears online
+    pub custom_status: Option<CustomStatus>,
+}
+
+/// Text and/or emoji shown next to a user's status
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CustomStatus {
+    #[sqlx(rename = "custom_status_text")]
+    pub text: Option<String>,
+    #[sqlx(rename = "custom_status_emoji")]
+    pub emoji: Option<String>,
+    /// Cleared automatically after this
+    #[sqlx(rename = "custom_status_expires_at")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Request body for PUT /users/@me/custom-status
+#[derive(Debug, Deserialize)]
+pub struct SetCustomStatusRequest {
+    pub text: Option<String>,
+    pub emoji: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+/// Aggregated reactions with one emoji on a message
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the requesting user is among the reactors
+    pub me: bool,
+}
+
+/// Query parameters for listing who reacted with an emoji
+#[derive(Debug, Deserialize)]
+pub struct ReactionUsersQuery {
+    pub limit: Option<i64>,
+}
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A user's read position in a channel (`channel_id`) or DM (`dm_channel_id`)
+/// and what has arrived since
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dm_channel_id: Option<Uuid>,
+    pub last_read_message_id: Option<Uuid>,
+    /// Messages from other users after the read position
+    pub unread_count: i64,
+    /// Unread messages that mention the user
+    pub mention_count: i64,
+}
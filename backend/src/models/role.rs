@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::ProfileSummary;
+use crate::permissions::Permissions;
+
+/// Mirrors public.roles table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Role {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub name: String,
+    pub color: Option<String>,
+    pub position: i32,
+    pub permissions: Permissions,
+    /// The server's implicit @everyone role
+    pub is_default: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request body for creating a role
+#[derive(Debug, Deserialize)]
+pub struct CreateRoleRequest {
+    pub name: String,
+    pub color: Option<String>,
+    pub permissions: Option<Permissions>,
+    pub position: Option<i32>,
+}
+
+/// Request body for updating a role (all fields optional)
+#[derive(Debug, Deserialize)]
+pub struct UpdateRoleRequest {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub permissions: Option<Permissions>,
+    pub position: Option<i32>,
+}
+
+/// Server member with profile and assigned role ids (for member lists)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberWithRoles {
+    pub user: ProfileSummary,
+    pub roles: Vec<Uuid>,
+    pub joined_at: DateTime<Utc>,
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Query parameters for message search; every filter is optional but at
+/// least one of them (or `q`) must be given
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// Search terms, in web-search syntax (`"exact phrase"`, `or`, `-exclude`)
+    pub q: Option<String>,
+    pub author_id: Option<Uuid>,
+    /// Restrict a server search to one channel or thread
+    pub channel_id: Option<Uuid>,
+    /// Only messages sent at or after this time
+    pub after: Option<DateTime<Utc>>,
+    /// Only messages sent before this time
+    pub before: Option<DateTime<Utc>>,
+    pub has_attachment: Option<bool>,
+    /// Only messages mentioning this user
+    pub mentions: Option<Uuid>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+/// A page of search results, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResults<T> {
+    pub results: Vec<SearchHit<T>>,
+    /// Pass as `cursor` to fetch the next page; `None` on the last page
+    pub next_cursor: Option<Uuid>,
+}
+
+/// One matching message with a highlighted excerpt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit<T> {
+    #[serde(flatten)]
+    pub message: T,
+    /// HTML-escaped excerpt with matches wrapped in `<mark>…</mark>`
+    pub snippet: String,
+}
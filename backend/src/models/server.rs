@@ -2,21 +2,17 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-/// PostgreSQL enum: member_role
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
-#[sqlx(type_name = "member_role", rename_all = "lowercase")]
-pub enum MemberRole {
-    Owner,
-    Admin,
-    Member,
-}
+use super::{Channel, Presence, ReadState};
 
 /// Mirrors public.servers table
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Server {
     pub id: Uuid,
     pub name: String,
+    /// Emoji shown when no icon image is uploaded
     pub icon: Option<String>,
+    /// Uploaded icon image (see `PUT /servers/:id/icon`)
+    pub icon_url: Option<String>,
     pub banner_url: Option<String>,
     pub color: Option<String>,
     pub description: Option<String>,
@@ -41,14 +37,43 @@ pub struct CreateServerRequest {
 pub struct ServerMember {
     pub server_id: Uuid,
     pub user_id: Uuid,
-    pub role: MemberRole,
     pub joined_at: DateTime<Utc>,
+    /// Joined through a temporary invite (removed on disconnect unless given a role)
+    pub temporary: bool,
+    /// Set while the member is timed out
+    pub timed_out_until: Option<DateTime<Utc>>,
 }
 
-/// Server with member count (for discovery)
+/// Server with member count (for discovery and invite previews)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerWithMemberCount {
     #[serde(flatten)]
     pub server: Server,
     pub member_count: i64,
 }
+
+/// Server with the caller's unread badge (for the server list)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerWithUnread {
+    #[serde(flatten)]
+    pub server: Server,
+    /// Some channel the caller can see has unread messages
+    pub unread: bool,
+    /// Unread mentions across those channels
+    pub mention_count: i64,
+}
+
+/// Everything a gateway client needs about one of its servers (in `Ready`,
+/// or streamed as `ServerCreate` for large servers)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayServer {
+    #[serde(flatten)]
+    pub server: Server,
+    pub member_count: i64,
+    /// Top-level channels the user can view
+    pub channels: Vec<Channel>,
+    /// The user's read states in those channels and their joined threads
+    pub read_states: Vec<ReadState>,
+    /// Members currently connected
+    pub presences: Vec<Presence>,
+}
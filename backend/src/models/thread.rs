@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::ProfileSummary;
+
+/// A thread: a child channel of `parent_id` joined with public.threads
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Thread {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub parent_id: Uuid,
+    pub name: String,
+    pub source_message_id: Option<Uuid>,
+    pub owner_id: Option<Uuid>,
+    pub archived: bool,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub auto_archive_minutes: i32,
+    pub last_message_at: DateTime<Utc>,
+    pub member_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request body for starting a thread from a message
+#[derive(Debug, Deserialize)]
+pub struct CreateThreadRequest {
+    pub name: String,
+    pub auto_archive_minutes: Option<i32>,
+}
+
+/// Request body for updating a thread (all fields optional)
+#[derive(Debug, Deserialize)]
+pub struct UpdateThreadRequest {
+    pub name: Option<String>,
+    pub archived: Option<bool>,
+    pub auto_archive_minutes: Option<i32>,
+}
+
+/// Query parameters for listing a channel's threads
+#[derive(Debug, Deserialize)]
+pub struct ThreadQuery {
+    /// Only archived (`true`) or only active (`false`) threads; omit for both
+    pub archived: Option<bool>,
+}
+
+/// Thread participant with profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadMember {
+    pub user: ProfileSummary,
+    pub joined_at: DateTime<Utc>,
+}
@@ -0,0 +1,523 @@
+//! Permission bitfield and the central per-member permission computation.
+//!
+//! A member's server permissions are the union of the server's @everyone role
+//! and every role assigned to them. The server owner and anyone holding
+//! `ADMINISTRATOR` implicitly have every permission. Anyone else who is timed
+//! out is cut down to `Permissions::TIMED_OUT`, in every channel.
+//!
+//! Channel permissions then layer the channel's overwrites on top, in order:
+//! @everyone, the member's roles (allows and denies combined), the member.
+//! Threads use their parent channel's overwrites.
+
+use std::collections::HashMap;
+use std::ops::{BitOr, BitOrAssign};
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::models::{Channel, OverwriteKind};
+
+/// Bitfield of server permissions, stored as BIGINT and sent as a JSON number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct Permissions(i64);
+
+impl Permissions {
+    pub const ADMINISTRATOR: Self = Self(1 << 0);
+    pub const VIEW_CHANNEL: Self = Self(1 << 1);
+    pub const SEND_MESSAGES: Self = Self(1 << 2);
+    pub const READ_MESSAGE_HISTORY: Self = Self(1 << 3);
+    pub const MANAGE_MESSAGES: Self = Self(1 << 4);
+    pub const MANAGE_CHANNELS: Self = Self(1 << 5);
+    pub const MANAGE_ROLES: Self = Self(1 << 6);
+    pub const CONNECT: Self = Self(1 << 7);
+    pub const SPEAK: Self = Self(1 << 8);
+    pub const CREATE_INVITE: Self = Self(1 << 9);
+    pub const MANAGE_SERVER: Self = Self(1 << 10);
+    pub const KICK_MEMBERS: Self = Self(1 << 11);
+    pub const BAN_MEMBERS: Self = Self(1 << 12);
+    pub const MODERATE_MEMBERS: Self = Self(1 << 13);
+    pub const MENTION_EVERYONE: Self = Self(1 << 14);
+
+    /// Every defined permission bit.
+    pub const ALL: Self = Self((1 << 15) - 1);
+
+    /// All a timed-out member keeps: they can read but not talk.
+    pub const TIMED_OUT: Self = Self(Self::VIEW_CHANNEL.0 | Self::READ_MESSAGE_HISTORY.0);
+
+    /// Granted to @everyone on newly created servers.
+    pub const DEFAULT: Self = Self(
+        Self::VIEW_CHANNEL.0
+            | Self::SEND_MESSAGES.0
+            | Self::READ_MESSAGE_HISTORY.0
+            | Self::CONNECT.0
+            | Self::SPEAK.0,
+    );
+
+    /// True if every bit in `other` is set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Drop any bits that don't correspond to a defined permission.
+    pub const fn truncate(self) -> Self {
+        Self(self.0 & Self::ALL.0)
+    }
+
+    /// Clear the `deny` bits, then set the `allow` bits.
+    pub const fn overwrite(self, allow: Self, deny: Self) -> Self {
+        Self((self.0 & !deny.0) | allow.0)
+    }
+
+    /// Keep only the bits also set in `other`.
+    pub const fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Permissions {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// A member's effective permissions within one server.
+#[derive(Debug, Clone, Copy)]
+pub struct MemberPermissions {
+    pub permissions: Permissions,
+    pub is_owner: bool,
+    /// Highest position among the member's roles (0 = only @everyone)
+    pub top_role_position: i32,
+    /// Currently timed out (never set for owners and administrators)
+    pub timed_out: bool,
+}
+
+impl MemberPermissions {
+    /// True if the member holds `perm` (owners and administrators hold everything).
+    pub fn has(&self, perm: Permissions) -> bool {
+        self.permissions.contains(perm)
+    }
+
+    /// Fail with `Forbidden` unless the member holds `perm`.
+    pub fn require(&self, perm: Permissions) -> AppResult<()> {
+        if !self.has(perm) {
+            return Err(AppError::Forbidden("Missing permissions".into()));
+        }
+        Ok(())
+    }
+
+    /// True if the member sits strictly above a role at `position` and may
+    /// therefore manage it (the owner outranks every role).
+    pub fn outranks(&self, position: i32) -> bool {
+        self.is_owner || self.top_role_position > position
+    }
+
+    /// Apply one channel's overwrites that target this member.
+    ///
+    /// Owners and administrators are unaffected by overwrites, and losing
+    /// `VIEW_CHANNEL` revokes everything else in the channel.
+    pub fn in_channel<'a>(&self, overwrites: impl IntoIterator<Item = &'a MemberOverwrite>) -> Self {
+        if self.permissions == Permissions::ALL {
+            return *self;
+        }
+
+        let mut everyone = None;
+        let mut member = None;
+        let (mut role_allow, mut role_deny) = (Permissions::default(), Permissions::default());
+        for o in overwrites {
+            match o.layer {
+                OverwriteLayer::Everyone => everyone = Some(o),
+                OverwriteLayer::Role => {
+                    role_allow |= o.allow;
+                    role_deny |= o.deny;
+                }
+                OverwriteLayer::Member => member = Some(o),
+            }
+        }
+
+        let mut permissions = self.permissions;
+        if let Some(o) = everyone {
+            permissions = permissions.overwrite(o.allow, o.deny);
+        }
+        permissions = permissions.overwrite(role_allow, role_deny);
+        if let Some(o) = member {
+            permissions = permissions.overwrite(o.allow, o.deny);
+        }
+
+        if !permissions.contains(Permissions::VIEW_CHANNEL) {
+            permissions = Permissions::default();
+        }
+        if self.timed_out {
+            permissions = permissions.intersect(Permissions::TIMED_OUT);
+        }
+
+        Self { permissions, ..*self }
+    }
+}
+
+/// Which step of the overwrite layering a `MemberOverwrite` belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwriteLayer {
+    Everyone,
+    Role,
+    Member,
+}
+
+/// A channel overwrite that applies to a particular member.
+#[derive(Debug, Clone)]
+pub struct MemberOverwrite {
+    pub channel_id: Uuid,
+    pub layer: OverwriteLayer,
+    pub allow: Permissions,
+    pub deny: Permissions,
+}
+
+#[derive(sqlx::FromRow)]
+struct MemberOverwriteRow {
+    channel_id: Uuid,
+    kind: OverwriteKind,
+    is_default: Option<bool>,
+    allow: i64,
+    deny: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct UserOverwriteRow {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    overwrite: MemberOverwriteRow,
+}
+
+impl From<MemberOverwriteRow> for MemberOverwrite {
+    fn from(r: MemberOverwriteRow) -> Self {
+        MemberOverwrite {
+            channel_id: r.channel_id,
+            layer: match (r.kind, r.is_default) {
+                (OverwriteKind::Member, _) => OverwriteLayer::Member,
+                (OverwriteKind::Role, Some(true)) => OverwriteLayer::Everyone,
+                (OverwriteKind::Role, _) => OverwriteLayer::Role,
+            },
+            allow: Permissions(r.allow).truncate(),
+            deny: Permissions(r.deny).truncate(),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct MemberPermissionsRow {
+    user_id: Uuid,
+    owner_id: Uuid,
+    permissions: i64,
+    top_role_position: i32,
+    timed_out: bool,
+}
+
+impl MemberPermissionsRow {
+    fn into_permissions(self) -> MemberPermissions {
+        let is_owner = self.owner_id == self.user_id;
+        let mut permissions = Permissions(self.permissions).truncate();
+        let mut timed_out = self.timed_out;
+        if is_owner || permissions.contains(Permissions::ADMINISTRATOR) {
+            permissions = Permissions::ALL;
+            timed_out = false;
+        } else if timed_out {
+            permissions = permissions.intersect(Permissions::TIMED_OUT);
+        }
+
+        MemberPermissions {
+            permissions,
+            is_owner,
+            top_role_position: self.top_role_position,
+            timed_out,
+        }
+    }
+}
+
+/// Server permissions of every member of `$1` listed in `$2`, one row each.
+const MEMBER_PERMISSIONS_SQL: &str = r#"
+    SELECT
+        sm.user_id,
+        s.owner_id,
+        COALESCE(bit_or(r.permissions), 0)::BIGINT as permissions,
+        COALESCE(max(r.position), 0)::INT as top_role_position,
+        COALESCE(sm.timed_out_until > now(), false) as timed_out
+    FROM server_members sm
+    INNER JOIN servers s ON s.id = sm.server_id
+    LEFT JOIN roles r ON r.server_id = sm.server_id AND (
+        r.is_default OR r.id IN (
+            SELECT role_id FROM member_roles
+            WHERE server_id = sm.server_id AND user_id = sm.user_id
+        )
+    )
+    WHERE sm.server_id = $1 AND sm.user_id = ANY($2)
+    GROUP BY sm.user_id, s.owner_id, sm.timed_out_until
+"#;
+
+/// Compute `user_id`'s permissions in `server_id`.
+///
+/// Returns `Forbidden` if the user is not a member of the server.
+pub async fn server_permissions(pool: &PgPool, server_id: Uuid, user_id: Uuid) -> AppResult<MemberPermissions> {
+    let row = sqlx::query_as::<_, MemberPermissionsRow>(MEMBER_PERMISSIONS_SQL)
+        .bind(server_id)
+        .bind([user_id].as_slice())
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::Forbidden("You are not a member of this server".into()))?;
+
+    Ok(row.into_permissions())
+}
+
+/// Compute `user_id`'s permissions in a channel, including its overwrites.
+///
+/// Returns `Forbidden` if the user is not a member of the channel's server.
+pub async fn channel_permissions(pool: &PgPool, channel: &Channel, user_id: Uuid) -> AppResult<MemberPermissions> {
+    let perms = server_permissions(pool, channel.server_id, user_id).await?;
+    if perms.permissions == Permissions::ALL {
+        return Ok(perms);
+    }
+
+    // Threads share their parent channel's overwrites
+    let overwrites_of = channel.parent_id.unwrap_or(channel.id);
+    let overwrites = member_overwrites(pool, channel.server_id, user_id, Some(overwrites_of)).await?;
+    Ok(perms.in_channel(&overwrites))
+}
+
+/// Every overwrite in `server_id` that applies to `user_id`, grouped by
+/// channel. Used to filter channel lists without a query per channel.
+pub async fn member_overwrites_by_channel(
+    pool: &PgPool,
+    server_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<HashMap<Uuid, Vec<MemberOverwrite>>> {
+    let mut by_channel: HashMap<Uuid, Vec<MemberOverwrite>> = HashMap::new();
+    for o in member_overwrites(pool, server_id, user_id, None).await? {
+        by_channel.entry(o.channel_id).or_default().push(o);
+    }
+    Ok(by_channel)
+}
+
+/// Load overwrites targeting @everyone, one of the member's roles, or the
+/// member directly — optionally restricted to a single channel.
+async fn member_overwrites(
+    pool: &PgPool,
+    server_id: Uuid,
+    user_id: Uuid,
+    channel_id: Option<Uuid>,
+) -> AppResult<Vec<MemberOverwrite>> {
+    let rows = sqlx::query_as::<_, MemberOverwriteRow>(
+        r#"
+        SELECT o.channel_id, o.kind, r.is_default, o.allow, o.deny
+        FROM channel_overwrites o
+        INNER JOIN channels c ON c.id = o.channel_id
+        LEFT JOIN roles r ON o.kind = 'role' AND r.id = o.target_id
+        WHERE c.server_id = $1
+          AND ($3::UUID IS NULL OR o.channel_id = $3)
+          AND (
+              (o.kind = 'member' AND o.target_id = $2)
+              OR r.is_default
+              OR o.target_id IN (
+                  SELECT role_id FROM member_roles
+                  WHERE server_id = $1 AND user_id = $2
+              )
+          )
+        "#,
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .bind(channel_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Those of `user_ids` who are members of the channel's server and hold
+/// `perm` in the channel. Works out everyone's permissions in two queries,
+/// for fanning events out to many members.
+pub async fn members_with_channel_permission(
+    pool: &PgPool,
+    channel: &Channel,
+    user_ids: &[Uuid],
+    perm: Permissions,
+) -> AppResult<Vec<Uuid>> {
+    if user_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let members = sqlx::query_as::<_, MemberPermissionsRow>(MEMBER_PERMISSIONS_SQL)
+        .bind(channel.server_id)
+        .bind(user_ids)
+        .fetch_all(pool)
+        .await?;
+
+    // Threads share their parent channel's overwrites
+    let rows = sqlx::query_as::<_, UserOverwriteRow>(
+        r#"
+        SELECT sm.user_id, o.channel_id, o.kind, r.is_default, o.allow, o.deny
+        FROM server_members sm
+        INNER JOIN channel_overwrites o ON o.channel_id = $3
+        LEFT JOIN roles r ON o.kind = 'role' AND r.id = o.target_id
+        WHERE sm.server_id = $1 AND sm.user_id = ANY($2)
+          AND (
+              (o.kind = 'member' AND o.target_id = sm.user_id)
+              OR r.is_default
+              OR o.target_id IN (
+                  SELECT role_id FROM member_roles
+                  WHERE server_id = $1 AND user_id = sm.user_id
+              )
+          )
+        "#,
+    )
+    .bind(channel.server_id)
+    .bind(user_ids)
+    .bind(channel.parent_id.unwrap_or(channel.id))
+    .fetch_all(pool)
+    .await?;
+
+    let mut overwrites: HashMap<Uuid, Vec<MemberOverwrite>> = HashMap::new();
+    for row in rows {
+        overwrites.entry(row.user_id).or_default().push(row.overwrite.into());
+    }
+
+    Ok(members
+        .into_iter()
+        .filter_map(|row| {
+            let user_id = row.user_id;
+            let member_overwrites = overwrites.get(&user_id).map(Vec::as_slice).unwrap_or_default();
+            row.into_permissions().in_channel(member_overwrites).has(perm).then_some(user_id)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(permissions: Permissions, is_owner: bool, timed_out: bool) -> MemberPermissionsRow {
+        let user_id = Uuid::new_v4();
+        MemberPermissionsRow {
+            user_id,
+            owner_id: if is_owner { user_id } else { Uuid::new_v4() },
+            permissions: permissions.0,
+            top_role_position: 3,
+            timed_out,
+        }
+    }
+
+    fn member(permissions: Permissions) -> MemberPermissions {
+        row(permissions, false, false).into_permissions()
+    }
+
+    fn overwrite(layer: OverwriteLayer, allow: Permissions, deny: Permissions) -> MemberOverwrite {
+        MemberOverwrite { channel_id: Uuid::nil(), layer, allow, deny }
+    }
+
+    #[test]
+    fn owners_and_administrators_hold_everything() {
+        let owner = row(Permissions::default(), true, true).into_permissions();
+        assert!(owner.is_owner);
+        assert_eq!(owner.permissions, Permissions::ALL);
+        assert!(!owner.timed_out);
+
+        let admin = row(Permissions::ADMINISTRATOR, false, true).into_permissions();
+        assert!(!admin.is_owner);
+        assert_eq!(admin.permissions, Permissions::ALL);
+        assert!(!admin.timed_out);
+    }
+
+    #[test]
+    fn timed_out_members_keep_only_read_access() {
+        let perms = row(Permissions::DEFAULT | Permissions::KICK_MEMBERS, false, true).into_permissions();
+        assert!(perms.timed_out);
+        assert_eq!(perms.permissions, Permissions::TIMED_OUT);
+        assert!(perms.require(Permissions::SEND_MESSAGES).is_err());
+    }
+
+    #[test]
+    fn unknown_bits_are_dropped() {
+        let perms = member(Permissions(Permissions::SEND_MESSAGES.0 | 1 << 40));
+        assert_eq!(perms.permissions, Permissions::SEND_MESSAGES);
+        assert_eq!(perms.top_role_position, 3);
+    }
+
+    #[test]
+    fn overwrites_layer_everyone_then_roles_then_member() {
+        let perms = member(Permissions::DEFAULT);
+        let none = Permissions::default();
+
+        // @everyone can't talk, but one of the member's roles can
+        let channel = perms.in_channel(&[
+            overwrite(OverwriteLayer::Role, Permissions::SEND_MESSAGES, none),
+            overwrite(OverwriteLayer::Everyone, none, Permissions::SEND_MESSAGES),
+        ]);
+        assert!(channel.has(Permissions::SEND_MESSAGES));
+
+        // ...unless the member themselves is denied
+        let channel = perms.in_channel(&[
+            overwrite(OverwriteLayer::Everyone, none, Permissions::SEND_MESSAGES),
+            overwrite(OverwriteLayer::Role, Permissions::SEND_MESSAGES, none),
+            overwrite(OverwriteLayer::Member, none, Permissions::SEND_MESSAGES),
+        ]);
+        assert!(!channel.has(Permissions::SEND_MESSAGES));
+        assert!(channel.has(Permissions::VIEW_CHANNEL));
+
+        // Role allows win over role denies
+        let channel = perms.in_channel(&[
+            overwrite(OverwriteLayer::Role, none, Permissions::SPEAK),
+            overwrite(OverwriteLayer::Role, Permissions::SPEAK, none),
+        ]);
+        assert!(channel.has(Permissions::SPEAK));
+    }
+
+    #[test]
+    fn losing_view_channel_revokes_everything() {
+        let perms = member(Permissions::DEFAULT | Permissions::MANAGE_MESSAGES);
+        let channel = perms.in_channel(&[overwrite(
+            OverwriteLayer::Everyone,
+            Permissions::default(),
+            Permissions::VIEW_CHANNEL,
+        )]);
+        assert_eq!(channel.permissions, Permissions::default());
+
+        // A member allow brings it back
+        let channel = perms.in_channel(&[
+            overwrite(OverwriteLayer::Everyone, Permissions::default(), Permissions::VIEW_CHANNEL),
+            overwrite(OverwriteLayer::Member, Permissions::VIEW_CHANNEL, Permissions::default()),
+        ]);
+        assert!(channel.has(Permissions::MANAGE_MESSAGES));
+    }
+
+    #[test]
+    fn overwrites_do_not_affect_administrators_or_lift_timeouts() {
+        let deny_all = [overwrite(OverwriteLayer::Member, Permissions::default(), Permissions::ALL)];
+        let admin = member(Permissions::ADMINISTRATOR);
+        assert_eq!(admin.in_channel(&deny_all).permissions, Permissions::ALL);
+
+        let timed_out = row(Permissions::DEFAULT, false, true).into_permissions();
+        let allow_all = [overwrite(OverwriteLayer::Member, Permissions::ALL, Permissions::default())];
+        assert_eq!(timed_out.in_channel(&allow_all).permissions, Permissions::TIMED_OUT);
+    }
+
+    #[test]
+    fn overwrite_rows_map_to_layers() {
+        let layer = |kind, is_default| {
+            MemberOverwrite::from(MemberOverwriteRow { channel_id: Uuid::nil(), kind, is_default, allow: 0, deny: 0 }).layer
+        };
+        assert_eq!(layer(OverwriteKind::Member, None), OverwriteLayer::Member);
+        assert_eq!(layer(OverwriteKind::Role, Some(true)), OverwriteLayer::Everyone);
+        assert_eq!(layer(OverwriteKind::Role, Some(false)), OverwriteLayer::Role);
+        // A role that no longer exists
+        assert_eq!(layer(OverwriteKind::Role, None), OverwriteLayer::Role);
+    }
+}
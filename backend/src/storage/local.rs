@@ -0,0 +1,186 @@
+//! Local-filesystem storage backend.
+//!
+//! The backend serves the files itself under `/uploads` (see `service`).
+//! Signed URLs carry `expires` and `signature` query parameters, an HMAC of
+//! the key and expiry with `STORAGE_URL_SECRET`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tower_http::services::ServeDir;
+
+use super::Storage;
+use crate::error::{AppError, AppResult};
+
+/// Key prefix of files that are only served with a valid signature.
+const SIGNED_PREFIX: &str = "attachments/";
+
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+    url_secret: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str, public_url: &str, url_secret: &str) -> Self {
+        Self {
+            root: PathBuf::from(root),
+            public_url: public_url.trim_end_matches('/').to_string(),
+            url_secret: url_secret.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, body: Vec<u8>, _content_type: &str) -> AppResult<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(storage_error)?;
+        }
+        tokio::fs::write(&path, body).await.map_err(storage_error)
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(storage_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{key}", self.public_url)
+    }
+
+    fn signed_url(&self, key: &str, expires: i64) -> String {
+        let signature = hex::encode(mac(&self.url_secret, key, expires).finalize().into_bytes());
+        format!("{}/{key}?expires={expires}&signature={signature}", self.public_url)
+    }
+}
+
+/// Serve the files in `dir`. Attachments need an unexpired signature from
+/// `signed_url`. Everything is sent as a download with `nosniff`, so no file
+/// can render as a page on the API's origin.
+pub fn service<S: Clone + Send + Sync + 'static>(dir: &str, url_secret: &str) -> Router<S> {
+    Router::new()
+        .fallback_service(ServeDir::new(dir))
+        .layer(middleware::from_fn_with_state(Arc::<str>::from(url_secret), guard))
+}
+
+async fn guard(State(url_secret): State<Arc<str>>, request: Request, next: Next) -> Response {
+    // Keys never need escaping, so refuse anything `ServeDir` would decode
+    // into a different path than the one checked here
+    let path = request.uri().path();
+    if path.contains(['%', '\\']) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let key = path.split('/').filter(|s| !s.is_empty() && *s != ".").collect::<Vec<_>>().join("/");
+
+    if key.starts_with(SIGNED_PREFIX) && !signature_valid(&url_secret, &key, request.uri().query()) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    response
+}
+
+/// Check the `expires` and `signature` query parameters of a request for `key`.
+fn signature_valid(url_secret: &str, key: &str, query: Option<&str>) -> bool {
+    let (mut expires, mut signature) = (None, None);
+    for pair in query.unwrap_or_default().split('&') {
+        match pair.split_once('=') {
+            Some(("expires", value)) => expires = value.parse::<i64>().ok(),
+            Some(("signature", value)) => signature = hex::decode(value).ok(),
+            _ => {}
+        }
+    }
+    let (Some(expires), Some(signature)) = (expires, signature) else {
+        return false;
+    };
+
+    expires > Utc::now().timestamp() && mac(url_secret, key, expires).verify_slice(&signature).is_ok()
+}
+
+fn mac(url_secret: &str, key: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(url_secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{key}\n{expires}").as_bytes());
+    mac
+}
+
+fn storage_error(e: std::io::Error) -> AppError {
+    tracing::error!("Local storage error: {e}");
+    AppError::Internal("Storage error".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    fn query(url: &str) -> Option<&str> {
+        url.split_once('?').map(|(_, q)| q)
+    }
+
+    #[test]
+    fn signed_urls_verify_for_their_key_only() {
+        let storage = LocalStorage::new("/tmp", "http://localhost/uploads/", SECRET);
+        let expires = Utc::now().timestamp() + 60;
+        let url = storage.signed_url("attachments/x/a.txt", expires);
+        assert!(url.starts_with("http://localhost/uploads/attachments/x/a.txt?"));
+
+        assert!(signature_valid(SECRET, "attachments/x/a.txt", query(&url)));
+        assert!(!signature_valid(SECRET, "attachments/x/b.txt", query(&url)));
+        assert!(!signature_valid("other", "attachments/x/a.txt", query(&url)));
+        assert!(!signature_valid(SECRET, "attachments/x/a.txt", None));
+
+        let extended = url.replace(&expires.to_string(), &(expires + 3600).to_string());
+        assert!(!signature_valid(SECRET, "attachments/x/a.txt", query(&extended)));
+
+        let expired = storage.signed_url("attachments/x/a.txt", Utc::now().timestamp() - 1);
+        assert!(!signature_valid(SECRET, "attachments/x/a.txt", query(&expired)));
+    }
+
+    #[tokio::test]
+    async fn serves_files_as_downloads() {
+        let dir = std::env::temp_dir().join(format!("banter-local-{}", uuid::Uuid::new_v4()));
+        let root = dir.to_str().unwrap();
+        let storage = LocalStorage::new(root, "", SECRET);
+        storage.put("avatars/a.png", b"png".to_vec(), "image/png").await.unwrap();
+        storage.put("attachments/x/a.txt", b"<script>".to_vec(), "text/plain").await.unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = service::<()>(root, SECRET);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let get = |path: String| reqwest::get(format!("{base}{path}"));
+
+        let response = get("/avatars/a.png".into()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment");
+        assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let signed = storage.signed_url("attachments/x/a.txt", Utc::now().timestamp() + 60);
+        let response = get(signed.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment");
+
+        assert_eq!(get("/attachments/x/a.txt".into()).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(get(signed.replace("/attachments", "/./attachments")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(get(signed.replace("a.txt", "%61.txt")).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+//! Blob storage for uploaded files.
+//!
+//! Handlers talk to a `Storage` trait object held in `AppState`; which
+//! backend sits behind it is picked from `StorageConfig` at startup. Keys are
+//! generated by the backend and only ever contain `[A-Za-z0-9._/-]`, so
+//! backends can use them in paths and URLs without escaping.
+//!
+//! Public files (avatars, icons) are linked with `url`. Attachments are
+//! linked with `signed_url`, which only works until it expires, so only
+//! people who could load the message ever get a working link.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::config::StorageConfig;
+use crate::error::AppResult;
+
+pub mod local;
+mod s3;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// Signed URLs are valid until the end of the window after the one they're
+/// issued in, so a key's URL stays the same (and cacheable) within a window.
+pub const URL_WINDOW_SECS: i64 = 12 * 60 * 60;
+
+/// When signed URLs issued now expire, in Unix seconds.
+pub fn url_expires() -> i64 {
+    (Utc::now().timestamp() / URL_WINDOW_SECS + 2) * URL_WINDOW_SECS
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Store `body` under `key`, replacing anything already there.
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> AppResult<()>;
+
+    /// Remove the object at `key`; missing objects are not an error.
+    async fn delete(&self, key: &str) -> AppResult<()>;
+
+    /// Public URL clients can fetch the object from.
+    fn url(&self, key: &str) -> String;
+
+    /// URL clients can fetch the object from until `expires` (Unix seconds,
+    /// from `url_expires`). The same key and expiry give the same URL.
+    fn signed_url(&self, key: &str, expires: i64) -> String;
+}
+
+/// Build the storage backend described by `config`.
+pub fn from_config(config: &StorageConfig) -> Arc<dyn Storage> {
+    match config {
+        StorageConfig::Local { dir, public_url, url_secret } => Arc::new(LocalStorage::new(dir, public_url, url_secret)),
+        StorageConfig::S3 { endpoint, bucket, region, access_key, secret_key, public_url } => Arc::new(
+            S3Storage::new(endpoint, bucket, region, access_key, secret_key, public_url),
+        ),
+    }
+}
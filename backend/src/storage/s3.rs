@@ -0,0 +1,448 @@
+//! S3-compatible storage backend.
+//!
+//! Talks to the bucket with path-style URLs (`{endpoint}/{bucket}/{key}`) so
+//! it works against MinIO and other stand-ins as well as AWS. Requests are
+//! signed with AWS Signature Version 4; signed URLs are presigned `GET`s on
+//! the endpoint, so the bucket itself can stay private. Objects are stored
+//! with `Content-Disposition: attachment`.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Url};
+use sha2::{Digest, Sha256};
+
+use super::{Storage, URL_WINDOW_SECS};
+use crate::error::{AppError, AppResult};
+
+/// Payload hash of an empty body, used for `DELETE`.
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Longest a presigned URL can be valid for (7 days).
+const MAX_PRESIGN_SECS: i64 = 7 * 24 * 60 * 60;
+
+pub struct S3Storage {
+    client: Client,
+    endpoint: String,
+    /// `host[:port]` of the endpoint, as signed
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    public_url: String,
+}
+
+impl S3Storage {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+        public_url: &str,
+    ) -> Self {
+        let url = Url::parse(endpoint).expect("S3_ENDPOINT must be a valid URL");
+        Self {
+            client: Client::new(),
+            host: host(&url),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Send a signed request for `key` and fail on any non-2xx response.
+    async fn send(&self, method: Method, key: &str, body: Vec<u8>, content_type: Option<&str>) -> AppResult<()> {
+        let url = Url::parse(&format!("{}/{}/{key}", self.endpoint, self.bucket))
+            .map_err(|e| storage_error(format!("invalid URL: {e}")))?;
+
+        let payload_hash = if body.is_empty() {
+            EMPTY_SHA256.to_string()
+        } else {
+            hex::encode(Sha256::digest(&body))
+        };
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(method.as_str(), &url, &payload_hash, &amz_date);
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-amz-date", &amz_date)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("authorization", authorization)
+            .body(body);
+        if let Some(content_type) = content_type {
+            request = request
+                .header("content-type", content_type)
+                .header("content-disposition", "attachment");
+        }
+
+        let response = request.send().await.map_err(|e| storage_error(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(storage_error(format!("{status}: {text}")));
+        }
+
+        Ok(())
+    }
+
+    /// Build the SigV4 `Authorization` header, signing `host`,
+    /// `x-amz-content-sha256` and `x-amz-date`.
+    fn authorization(&self, method: &str, url: &Url, payload_hash: &str, amz_date: &str) -> String {
+        let date = &amz_date[..8];
+        let host = host(url);
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}",
+            url.path()
+        );
+
+        let scope = self.scope(date);
+        let signature = self.signature(amz_date, &scope, &canonical_request);
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key
+        )
+    }
+
+    /// A presigned `GET` URL for `key`, valid for `expires_in` seconds from
+    /// `now` (at most 7 days).
+    fn presigned_url(&self, key: &str, now: DateTime<Utc>, expires_in: i64) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = self.scope(&amz_date[..8]);
+        let path = format!("/{}/{key}", self.bucket);
+
+        // Already in canonical (sorted) order
+        let query = format!(
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}&X-Amz-Date={amz_date}&X-Amz-Expires={}&X-Amz-SignedHeaders=host",
+            uri_encode(&format!("{}/{scope}", self.access_key)),
+            expires_in.clamp(1, MAX_PRESIGN_SECS),
+        );
+        let canonical_request = format!("GET\n{path}\n{query}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", self.host);
+        let signature = self.signature(&amz_date, &scope, &canonical_request);
+
+        format!("{}{path}?{query}&X-Amz-Signature={signature}", self.endpoint)
+    }
+
+    fn scope(&self, date: &str) -> String {
+        format!("{date}/{}/s3/aws4_request", self.region)
+    }
+
+    /// Sign a canonical request made at `amz_date`.
+    fn signature(&self, amz_date: &str, scope: &str, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, &amz_date[..8], &self.region, "s3");
+        hex::encode(hmac(&key, &string_to_sign))
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> AppResult<()> {
+        self.send(Method::PUT, key, body, Some(content_type)).await
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        // S3 answers 204 for missing keys too
+        self.send(Method::DELETE, key, Vec::new(), None).await
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{key}", self.public_url)
+    }
+
+    fn signed_url(&self, key: &str, expires: i64) -> String {
+        // Signed as of the start of the window it was issued in, so the URL
+        // is the same all through it
+        let valid_for = 2 * URL_WINDOW_SECS;
+        let signed_at = DateTime::from_timestamp(expires - valid_for, 0).unwrap_or_default();
+        self.presigned_url(key, signed_at, valid_for)
+    }
+}
+
+/// `host[:port]` of `url`.
+fn host(url: &Url) -> String {
+    match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    }
+}
+
+/// The SigV4 key for requests to `service` in `region` on `date` (`YYYYMMDD`).
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{secret_key}").as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+/// Percent-encode everything but unreserved characters, as SigV4 expects
+/// in query strings.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn storage_error(detail: String) -> AppError {
+    tracing::error!("S3 storage error: {detail}");
+    AppError::Internal("Storage error".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, Method, StatusCode, Uri};
+    use axum::response::{IntoResponse, Response};
+    use axum::Router;
+    use chrono::{Duration, NaiveDateTime};
+
+    use super::*;
+    use crate::storage::url_expires;
+
+    const ACCESS_KEY: &str = "AKIDEXAMPLE";
+    const SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    const REGION: &str = "eu-west-1";
+    const BUCKET: &str = "banter";
+
+    /// Objects with the headers they were stored with.
+    type Objects = Arc<Mutex<HashMap<String, (Vec<u8>, HeaderMap)>>>;
+
+    /// Start a stand-in for S3 that checks every request's signature from
+    /// scratch and keeps objects in memory. Returns its endpoint.
+    async fn stand_in() -> (String, Objects) {
+        let objects = Objects::default();
+        let app = Router::new().fallback(handle).with_state(objects.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (endpoint, objects)
+    }
+
+    async fn handle(State(objects): State<Objects>, method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+        let Some(key) = uri.path().strip_prefix(&format!("/{BUCKET}/")) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let host = headers["host"].to_str().unwrap();
+        let authorized = match uri.query() {
+            Some(query) => method == Method::GET && presign_valid(uri.path(), query, host),
+            None => header_valid(&method, uri.path(), &headers, &body),
+        };
+        if !authorized {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+
+        let mut objects = objects.lock().unwrap();
+        match method {
+            Method::PUT => {
+                objects.insert(key.to_string(), (body.to_vec(), headers));
+                StatusCode::OK.into_response()
+            }
+            Method::GET => match objects.get(key) {
+                Some((body, _)) => body.clone().into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+            Method::DELETE => {
+                objects.remove(key);
+                StatusCode::NO_CONTENT.into_response()
+            }
+            _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        }
+    }
+
+    /// Check an `Authorization: AWS4-HMAC-SHA256 ...` request.
+    fn header_valid(method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        let Some(fields) = header("authorization").strip_prefix("AWS4-HMAC-SHA256 ") else {
+            return false;
+        };
+        let fields: HashMap<&str, &str> = fields.split(", ").filter_map(|f| f.split_once('=')).collect();
+        let (Some(credential), Some(signed_headers), Some(signature)) =
+            (fields.get("Credential"), fields.get("SignedHeaders"), fields.get("Signature"))
+        else {
+            return false;
+        };
+
+        let payload_hash = header("x-amz-content-sha256");
+        if payload_hash != hex::encode(Sha256::digest(body)) {
+            return false;
+        }
+        let canonical_headers: String = signed_headers
+            .split(';')
+            .map(|name| format!("{name}:{}\n", header(name).trim()))
+            .collect();
+        let canonical_request = format!("{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}");
+
+        expected_signature(credential, header("x-amz-date"), &canonical_request).as_deref() == Some(*signature)
+    }
+
+    /// Check a presigned `GET`, including its expiry.
+    fn presign_valid(path: &str, query: &str, host: &str) -> bool {
+        let mut params: BTreeMap<String, String> = query
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_string(), decode(v)))
+            .collect();
+        let Some(signature) = params.remove("X-Amz-Signature") else {
+            return false;
+        };
+        let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+
+        let Ok(signed_at) = NaiveDateTime::parse_from_str(&param("X-Amz-Date"), "%Y%m%dT%H%M%SZ") else {
+            return false;
+        };
+        let expires_in: i64 = param("X-Amz-Expires").parse().unwrap_or(0);
+        if signed_at.and_utc() + Duration::seconds(expires_in) < Utc::now() || param("X-Amz-SignedHeaders") != "host" {
+            return false;
+        }
+
+        let canonical_query = params
+            .iter()
+            .map(|(k, v)| format!("{k}={}", v.replace('/', "%2F")))
+            .collect::<Vec<_>>()
+            .join("&");
+        let canonical_request = format!("GET\n{path}\n{canonical_query}\nhost:{host}\n\nhost\nUNSIGNED-PAYLOAD");
+
+        expected_signature(&param("X-Amz-Credential"), &param("X-Amz-Date"), &canonical_request) == Some(signature)
+    }
+
+    /// The signature of `canonical_request` under `credential`
+    /// (`{access key}/{date}/{region}/s3/aws4_request`), if the credential is ours.
+    fn expected_signature(credential: &str, amz_date: &str, canonical_request: &str) -> Option<String> {
+        let (access_key, scope) = credential.split_once('/')?;
+        let [date, region, "s3", "aws4_request"] = scope.split('/').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        if access_key != ACCESS_KEY || region != REGION || !amz_date.starts_with(date) {
+            return None;
+        }
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        Some(hex::encode(hmac(&signing_key(SECRET_KEY, date, region, "s3"), &string_to_sign)))
+    }
+
+    fn decode(value: &str) -> String {
+        let mut out = Vec::new();
+        let mut bytes = value.bytes();
+        while let Some(b) = bytes.next() {
+            if b == b'%' {
+                let hex: String = bytes.by_ref().take(2).map(char::from).collect();
+                out.push(u8::from_str_radix(&hex, 16).unwrap());
+            } else {
+                out.push(b);
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn storage(endpoint: &str, secret_key: &str) -> S3Storage {
+        S3Storage::new(endpoint, BUCKET, REGION, ACCESS_KEY, secret_key, "https://cdn.example.com")
+    }
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        // From the AWS SigV4 documentation ("Examples of how to derive a signing key")
+        let key = signing_key(SECRET_KEY, "20120215", "us-east-1", "iam");
+        assert_eq!(hex::encode(key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[tokio::test]
+    async fn stores_serves_and_deletes_objects() {
+        let (endpoint, objects) = stand_in().await;
+        let storage = storage(&endpoint, SECRET_KEY);
+        let key = "attachments/0f8fad5b-d9cb-469f-a165-70867728950e/notes.txt";
+
+        storage.put(key, b"hello".to_vec(), "text/plain").await.unwrap();
+        {
+            let objects = objects.lock().unwrap();
+            let (body, headers) = &objects[key];
+            assert_eq!(body, b"hello");
+            assert_eq!(headers["content-type"], "text/plain");
+            assert_eq!(headers["content-disposition"], "attachment");
+        }
+
+        let url = storage.signed_url(key, Utc::now().timestamp() + 60);
+        assert!(url.starts_with(&format!("{endpoint}/{BUCKET}/{key}?")));
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"hello");
+
+        storage.delete(key).await.unwrap();
+        assert!(objects.lock().unwrap().is_empty());
+        assert_eq!(reqwest::get(&url).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        // Missing keys delete fine
+        storage.delete(key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signatures() {
+        let (endpoint, objects) = stand_in().await;
+        let key = "attachments/x/a.txt";
+        storage(&endpoint, SECRET_KEY).put(key, b"a".to_vec(), "text/plain").await.unwrap();
+
+        let wrong = storage(&endpoint, "not-the-secret");
+        assert!(wrong.put(key, b"b".to_vec(), "text/plain").await.is_err());
+        assert_eq!(objects.lock().unwrap()[key].0, b"a");
+
+        let url = wrong.signed_url(key, Utc::now().timestamp() + 60);
+        assert_eq!(reqwest::get(&url).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        let tampered = storage(&endpoint, SECRET_KEY)
+            .signed_url(key, Utc::now().timestamp() + 60)
+            .replace("a.txt", "b.txt");
+        assert_eq!(reqwest::get(&tampered).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn presigned_urls_expire() {
+        let (endpoint, _) = stand_in().await;
+        let storage = storage(&endpoint, SECRET_KEY);
+        let key = "attachments/x/a.txt";
+        storage.put(key, b"a".to_vec(), "text/plain").await.unwrap();
+
+        let expired = storage.presigned_url(key, Utc::now() - Duration::hours(2), 60 * 60);
+        assert_eq!(reqwest::get(&expired).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        // Capped at the 7 days S3 allows
+        let url = storage.presigned_url(key, Utc::now(), 30 * 24 * 60 * 60);
+        assert!(url.contains(&format!("X-Amz-Expires={MAX_PRESIGN_SECS}&")));
+        assert_eq!(reqwest::get(&url).await.unwrap().status(), StatusCode::OK);
+
+        // Signed as of the window's start, so it's the same all through it
+        let expires = url_expires();
+        let signed = storage.signed_url(key, expires);
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(storage.signed_url(key, expires), signed);
+        let window_start = DateTime::from_timestamp(expires - 2 * URL_WINDOW_SECS, 0).unwrap();
+        assert!(signed.contains(&format!("X-Amz-Date={}&", window_start.format("%Y%m%dT%H%M%SZ"))));
+        assert_eq!(reqwest::get(&signed).await.unwrap().status(), StatusCode::OK);
+    }
+}
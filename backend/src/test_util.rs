@@ -0,0 +1,185 @@
+//! Helpers for tests that need a database.
+//!
+//! Each call to `database` creates a fresh database on the server at
+//! `TEST_DATABASE_URL` (any database there, e.g.
+//! `postgres://postgres@localhost/postgres`) and applies a stand-in for
+//! Supabase's `auth` schema and every migration. Tests using it are
+//! `#[ignore]`d; run them with `cargo test -- --ignored`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, EventBusConfig, SlowConsumerPolicy, StorageConfig};
+use crate::ws::WsState;
+use crate::AppState;
+
+pub const LIVEKIT_API_KEY: &str = "test-key";
+pub const LIVEKIT_API_SECRET: &str = "test-secret";
+
+/// What the migrations need of Supabase's `auth` schema.
+const AUTH_SCHEMA: &str = r#"
+CREATE SCHEMA IF NOT EXISTS auth;
+CREATE TABLE IF NOT EXISTS auth.users (id UUID PRIMARY KEY, raw_user_meta_data JSONB DEFAULT '{}');
+CREATE OR REPLACE FUNCTION auth.uid() RETURNS UUID LANGUAGE sql AS $$ SELECT NULL::uuid $$;
+CREATE OR REPLACE FUNCTION auth.role() RETURNS TEXT LANGUAGE sql AS $$ SELECT 'service_role'::text $$;
+"#;
+
+/// Test databases are named `banter_test_<pid>_<n>`.
+const DATABASE_PREFIX: &str = "banter_test_";
+
+static DATABASES: AtomicUsize = AtomicUsize::new(0);
+static DROPPED_OLD: OnceCell<()> = OnceCell::const_new();
+
+/// A new, migrated database.
+pub async fn database() -> PgPool {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set for database tests");
+    let options: PgConnectOptions = url.parse().expect("TEST_DATABASE_URL must be a PostgreSQL URL");
+    let mut admin = PgConnection::connect_with(&options).await.expect("Failed to connect to TEST_DATABASE_URL");
+
+    // Left behind by earlier runs
+    DROPPED_OLD
+        .get_or_init(|| async {
+            let pattern = format!("{DATABASE_PREFIX}%");
+            let old = sqlx::query_scalar::<_, String>("SELECT datname FROM pg_database WHERE datname LIKE $1")
+                .bind(&pattern)
+                .fetch_all(&mut admin)
+                .await
+                .unwrap();
+            for name in old {
+                sqlx::raw_sql(&format!("DROP DATABASE IF EXISTS \"{name}\" WITH (FORCE)"))
+                    .execute(&mut admin)
+                    .await
+                    .unwrap();
+            }
+        })
+        .await;
+
+    let name = format!(
+        "{DATABASE_PREFIX}{}_{}",
+        std::process::id(),
+        DATABASES.fetch_add(1, Ordering::Relaxed)
+    );
+    sqlx::raw_sql(&format!("CREATE DATABASE \"{name}\"")).execute(&mut admin).await.unwrap();
+
+    let pool = PgPoolOptions::new()
+        .max_connections(10)
+        .connect_with(options.database(&name))
+        .await
+        .unwrap();
+
+    sqlx::raw_sql(AUTH_SCHEMA).execute(&pool).await.unwrap();
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+    let mut migrations: Vec<_> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    migrations.sort();
+    for path in migrations {
+        let sql = std::fs::read_to_string(&path).unwrap();
+        sqlx::raw_sql(&sql)
+            .execute(&pool)
+            .await
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    }
+    pool
+}
+
+/// App state on `pool`, as one node with the local event bus.
+pub fn app_state(pool: PgPool) -> AppState {
+    app_state_with_bus(pool, EventBusConfig::Local)
+}
+
+pub fn app_state_with_bus(pool: PgPool, event_bus: EventBusConfig) -> AppState {
+    let config = AppConfig {
+        supabase_url: String::new(),
+        supabase_anon_key: String::new(),
+        supabase_service_role_key: String::new(),
+        supabase_jwt_secret: "test-jwt-secret".into(),
+        database_url: String::new(),
+        // Nothing listens here, so LiveKit API calls fail fast
+        livekit_url: "ws://127.0.0.1:9".into(),
+        livekit_api_key: LIVEKIT_API_KEY.into(),
+        livekit_api_secret: LIVEKIT_API_SECRET.into(),
+        backend_port: 0,
+        ws_heartbeat_interval_ms: 30_000,
+        ws_slow_consumer_policy: SlowConsumerPolicy::DropNonCritical,
+        presence_idle_timeout_secs: 300,
+        event_bus,
+        storage: StorageConfig::Local {
+            dir: std::env::temp_dir().join("banter-test-uploads").display().to_string(),
+            public_url: String::new(),
+            url_secret: "test-url-secret".into(),
+        },
+    };
+
+    AppState {
+        storage: crate::storage::from_config(&config.storage),
+        ws_state: WsState::new(crate::ws::bus::from_config(event_bus, &pool)),
+        pool,
+        config,
+    }
+}
+
+/// Sign up a user; their profile is created with them.
+pub async fn user(pool: &PgPool, name: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO auth.users (id, raw_user_meta_data) VALUES ($1, jsonb_build_object('full_name', $2::text))")
+        .bind(id)
+        .bind(name)
+        .execute(pool)
+        .await
+        .unwrap();
+    id
+}
+
+/// A server as `create_server` sets it up.
+pub struct TestServer {
+    pub id: Uuid,
+    pub text_channel: Uuid,
+    pub voice_channel: Uuid,
+}
+
+/// Create a server owned by `owner`, with its @everyone role and default
+/// channels.
+pub async fn server(pool: &PgPool, owner: Uuid) -> TestServer {
+    let id = sqlx::query_scalar::<_, Uuid>("INSERT INTO servers (name, owner_id) VALUES ('Test', $1) RETURNING id")
+        .bind(owner)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    join(pool, id, owner).await;
+    sqlx::query(
+        "INSERT INTO roles (server_id, name, position, permissions, is_default) VALUES ($1, '@everyone', 0, $2, true)"
+    )
+    .bind(id)
+    .bind(crate::permissions::Permissions::DEFAULT)
+    .execute(pool)
+    .await
+    .unwrap();
+
+    let channel = |name: &'static str, kind: &'static str| {
+        sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO channels (server_id, name, kind) VALUES ($1, $2, $3::channel_type) RETURNING id"
+        )
+        .bind(id)
+        .bind(name)
+        .bind(kind)
+        .fetch_one(pool)
+    };
+    TestServer {
+        id,
+        text_channel: channel("general", "text").await.unwrap(),
+        voice_channel: channel("Lounge", "voice").await.unwrap(),
+    }
+}
+
+/// Add `user_id` to the server's members.
+pub async fn join(pool: &PgPool, server_id: Uuid, user_id: Uuid) {
+    sqlx::query("INSERT INTO server_members (server_id, user_id) VALUES ($1, $2)")
+        .bind(server_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .unwrap();
+}
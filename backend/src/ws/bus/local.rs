@@ -0,0 +1,12 @@
+//! Single-node bus: there's nobody else to tell.
+
+use super::{BusMessage, EventBus};
+use crate::ws::WsState;
+
+pub struct LocalBus;
+
+impl EventBus for LocalBus {
+    fn publish(&self, _message: BusMessage) {}
+
+    fn start(&self, _ws_state: WsState) {}
+}
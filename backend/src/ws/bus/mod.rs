@@ -0,0 +1,110 @@
+//! Event bus between backend nodes.
+//!
+//! `WsState::broadcast_to_channel` and `WsState::send_to_user` deliver to
+//! this node's sessions and also publish on the bus, so users connected to
+//! other nodes get the event too. Which bus is used is picked from
+//! `EventBusConfig` at startup; with a single node it's `LocalBus`, which
+//! publishes nowhere.
+//!
+//! Nodes also tell each other which users they have connected, so presence
+//! and `WsState::user_is_connected` are cluster-wide: a `Presence` message
+//! whenever the combined status of a user's sessions on a node changes
+//! (including their first session starting and their last one ending), and
+//! a periodic `Node` heartbeat listing all of them. A node that misses
+//! `NODE_TIMEOUT` worth of heartbeats is forgotten, and its users go offline
+//! and leave voice.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::config::EventBusConfig;
+use crate::handlers::voice;
+use crate::models::UserStatus;
+use crate::ws::events::WsEvent;
+use crate::ws::{presence, WsState};
+
+mod local;
+mod postgres;
+
+pub use local::LocalBus;
+pub use postgres::PgEventBus;
+
+/// How often each node announces itself and its connected users.
+pub const NODE_HEARTBEAT: Duration = Duration::from_secs(10);
+
+/// Nodes not heard from for this long are considered gone.
+pub const NODE_TIMEOUT: Duration = Duration::from_secs(35);
+
+pub trait EventBus: Send + Sync {
+    /// Send `message` to every other node. Never blocks; delivery is best
+    /// effort.
+    fn publish(&self, message: BusMessage);
+
+    /// Start handing other nodes' messages to `ws_state`.
+    fn start(&self, ws_state: WsState);
+}
+
+/// A message from one node to the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusMessage {
+    /// The sending node; nodes ignore their own messages
+    pub origin: Uuid,
+    pub payload: BusPayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BusPayload {
+    /// `broadcast_to_channel` on the sending node
+    Channel { channel_id: Uuid, event: WsEvent },
+    /// `send_to_user` on the sending node
+    User { user_id: Uuid, event: WsEvent },
+    /// The combined status of a user's sessions on the sending node changed;
+    /// `None` once their last one there ended
+    Presence { user_id: Uuid, status: Option<UserStatus> },
+    /// Heartbeat, with every user connected to the sending node
+    Node { users: HashMap<Uuid, UserStatus> },
+    /// `permissions_changed` on the sending node
+    Permissions { server_id: Uuid },
+}
+
+/// Build the event bus described by `config`.
+pub fn from_config(config: EventBusConfig, pool: &PgPool) -> Arc<dyn EventBus> {
+    match config {
+        EventBusConfig::Local => Arc::new(LocalBus),
+        EventBusConfig::Postgres => Arc::new(PgEventBus::new(pool.clone())),
+    }
+}
+
+/// Announce this node every `NODE_HEARTBEAT`, and forget nodes that went
+/// quiet, broadcasting the presence of users who were on them and removing
+/// their voice states.
+pub async fn run_node_heartbeat(state: AppState) {
+    let started = Instant::now();
+    let mut interval = tokio::time::interval(NODE_HEARTBEAT);
+    loop {
+        interval.tick().await;
+        state.ws_state.publish_node();
+
+        let changed = state.ws_state.prune_nodes(NODE_TIMEOUT);
+        // Every surviving node sees the same change; one of them acts on it
+        if !state.ws_state.is_lowest_node() {
+            continue;
+        }
+        for user_id in changed {
+            presence::broadcast(&state, user_id).await;
+        }
+        // Until then, live nodes may not have been heard from yet
+        if started.elapsed() >= NODE_TIMEOUT {
+            if let Err(e) = voice::remove_orphaned(&state, &state.ws_state.live_nodes()).await {
+                tracing::error!("Failed to remove orphaned voice states: {e}");
+            }
+        }
+    }
+}
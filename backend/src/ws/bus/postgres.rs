@@ -0,0 +1,303 @@
+//! Postgres `LISTEN` / `NOTIFY` bus.
+//!
+//! Messages are published one at a time by a background task, so they reach
+//! other nodes in the order they were published. Payloads too big for
+//! `NOTIFY` go into `gateway_events` and are sent as `#<id>`.
+
+use std::time::{Duration, Instant};
+
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+use super::{BusMessage, EventBus};
+use crate::ws::WsState;
+
+/// The `LISTEN` / `NOTIFY` channel.
+const CHANNEL: &str = "banter_gateway";
+
+/// Largest payload sent inline; Postgres rejects anything from 8000 bytes.
+const MAX_NOTIFY_PAYLOAD: usize = 7900;
+
+/// Messages waiting to be published before new ones are dropped.
+const PUBLISH_QUEUE_SIZE: usize = 4096;
+
+/// How long a stored payload is kept for listeners to fetch.
+const STORED_PAYLOAD_TTL: Duration = Duration::from_secs(60);
+
+/// Wait before listening again after the connection fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub struct PgEventBus {
+    pool: PgPool,
+    queue: mpsc::Sender<String>,
+}
+
+impl PgEventBus {
+    pub fn new(pool: PgPool) -> Self {
+        let (queue, rx) = mpsc::channel(PUBLISH_QUEUE_SIZE);
+        tokio::spawn(publish_queued(pool.clone(), rx));
+        Self { pool, queue }
+    }
+}
+
+impl EventBus for PgEventBus {
+    fn publish(&self, message: BusMessage) {
+        let Ok(payload) = serde_json::to_string(&message) else {
+            return;
+        };
+        if self.queue.try_send(payload).is_err() {
+            tracing::warn!("Event bus publish queue full, dropped a message");
+        }
+    }
+
+    fn start(&self, ws_state: WsState) {
+        tokio::spawn(listen(self.pool.clone(), ws_state));
+    }
+}
+
+/// Publish queued payloads in order, sweeping old stored ones as it goes.
+async fn publish_queued(pool: PgPool, mut rx: mpsc::Receiver<String>) {
+    let mut last_sweep = Instant::now();
+    while let Some(payload) = rx.recv().await {
+        if let Err(e) = notify(&pool, payload).await {
+            tracing::error!("Event bus publish failed: {e}");
+        }
+
+        if last_sweep.elapsed() > STORED_PAYLOAD_TTL {
+            last_sweep = Instant::now();
+            let swept = sqlx::query(
+                "DELETE FROM gateway_events WHERE created_at < now() - make_interval(secs => $1)"
+            )
+            .bind(STORED_PAYLOAD_TTL.as_secs_f64())
+            .execute(&pool)
+            .await;
+            if let Err(e) = swept {
+                tracing::error!("Failed to sweep gateway events: {e}");
+            }
+        }
+    }
+}
+
+async fn notify(pool: &PgPool, payload: String) -> Result<(), sqlx::Error> {
+    let payload = if payload.len() <= MAX_NOTIFY_PAYLOAD {
+        payload
+    } else {
+        let id = sqlx::query_scalar::<_, i64>("INSERT INTO gateway_events (payload) VALUES ($1) RETURNING id")
+            .bind(payload)
+            .fetch_one(pool)
+            .await?;
+        format!("#{id}")
+    };
+
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(CHANNEL)
+        .bind(payload)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Hand every notification to `ws_state`, reconnecting when the listening
+/// connection drops.
+async fn listen(pool: PgPool, ws_state: WsState) {
+    loop {
+        match PgListener::connect_with(&pool).await {
+            Ok(mut listener) => match listener.listen(CHANNEL).await {
+                Ok(()) => {
+                    tracing::info!("Event bus listening on {CHANNEL}");
+                    loop {
+                        match listener.recv().await {
+                            Ok(notification) => receive(&pool, &ws_state, notification.payload()).await,
+                            Err(e) => {
+                                tracing::error!("Event bus connection lost: {e}");
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => tracing::error!("Event bus failed to listen: {e}"),
+            },
+            Err(e) => tracing::error!("Event bus failed to connect: {e}"),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn receive(pool: &PgPool, ws_state: &WsState, payload: &str) {
+    let stored;
+    let payload = match payload.strip_prefix('#').map(str::parse::<i64>) {
+        Some(Ok(id)) => {
+            let row = sqlx::query_scalar::<_, String>("SELECT payload FROM gateway_events WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await;
+            match row {
+                Ok(Some(payload)) => {
+                    stored = payload;
+                    stored.as_str()
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    tracing::error!("Failed to load gateway event {id}: {e}");
+                    return;
+                }
+            }
+        }
+        _ => payload,
+    };
+
+    match serde_json::from_str::<BusMessage>(payload) {
+        Ok(message) => ws_state.receive(message),
+        Err(e) => tracing::warn!("Ignoring malformed event bus message: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::sync::Arc;
+
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::sync::broadcast;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::config::SlowConsumerPolicy;
+    use crate::models::UserStatus;
+    use crate::test_util;
+    use crate::ws::events::WsEvent;
+    use crate::ws::session::Session;
+
+    /// Two nodes on one database, each with its own pool and bus, that have
+    /// heard from each other.
+    async fn nodes() -> (PgPool, WsState, WsState) {
+        let pool = test_util::database().await;
+        let node = |pool: PgPool| {
+            let ws_state = WsState::new(Arc::new(PgEventBus::new(pool)));
+            ws_state.start_bus();
+            ws_state
+        };
+        let other_pool = PgPoolOptions::new().connect_with((*pool.connect_options()).clone()).await.unwrap();
+        let (a, b) = (node(pool.clone()), node(other_pool));
+
+        // Heartbeat until both are listening
+        eventually(|| async {
+            a.publish_node();
+            b.publish_node();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            a.live_nodes().contains(&b.node_id()) && b.live_nodes().contains(&a.node_id())
+        })
+        .await;
+        (pool, a, b)
+    }
+
+    /// Wait up to five seconds for `check` to pass.
+    async fn eventually<F: Future<Output = bool>>(check: impl Fn() -> F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !check().await {
+            assert!(Instant::now() < deadline, "timed out");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn next(rx: &mut broadcast::Receiver<WsEvent>) -> WsEvent {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.expect("timed out").unwrap()
+    }
+
+    fn message_update(content: String) -> WsEvent {
+        WsEvent::MessageUpdate {
+            id: Uuid::new_v4(),
+            channel_id: Uuid::new_v4(),
+            content,
+            edited_at: "2026-01-01T00:00:00Z".into(),
+        }
+    }
+
+    async fn stored_events(pool: &PgPool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM gateway_events").fetch_one(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn channel_events_reach_the_other_node() {
+        let (pool, a, b) = nodes().await;
+        let channel_id = Uuid::new_v4();
+        let mut on_a = a.subscribe_channel(channel_id);
+        let mut on_b = b.subscribe_channel(channel_id);
+
+        a.broadcast_to_channel(&channel_id, message_update("hello".into()));
+        for rx in [&mut on_a, &mut on_b] {
+            match next(rx).await {
+                WsEvent::MessageUpdate { content, .. } => assert_eq!(content, "hello"),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+        // Sent inline, and not echoed back to A
+        assert_eq!(stored_events(&pool).await, 0);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(on_a.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn oversized_events_go_through_the_table() {
+        let (pool, a, b) = nodes().await;
+        let channel_id = Uuid::new_v4();
+        let mut on_b = b.subscribe_channel(channel_id);
+
+        let content = "x".repeat(MAX_NOTIFY_PAYLOAD * 2);
+        a.broadcast_to_channel(&channel_id, message_update(content.clone()));
+        match next(&mut on_b).await {
+            WsEvent::MessageUpdate { content: received, .. } => assert_eq!(received, content),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(stored_events(&pool).await, 1);
+
+        // Small events after it still go inline, in order
+        a.broadcast_to_channel(&channel_id, message_update("after".into()));
+        assert!(matches!(next(&mut on_b).await, WsEvent::MessageUpdate { content, .. } if content == "after"));
+        assert_eq!(stored_events(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn presence_combines_across_nodes() {
+        let (_pool, a, b) = nodes().await;
+        let user_id = Uuid::new_v4();
+        let (phone, laptop) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!b.user_is_connected(&user_id));
+        a.add_device(user_id, phone, UserStatus::Dnd);
+        eventually(|| async { b.presence_status(&user_id) == UserStatus::Dnd }).await;
+        assert!(b.user_is_connected(&user_id));
+
+        // The most available status on any node wins
+        b.add_device(user_id, laptop, UserStatus::Online);
+        eventually(|| async { a.presence_status(&user_id) == UserStatus::Online }).await;
+        b.set_device_status(user_id, laptop, UserStatus::Invisible);
+        eventually(|| async { a.presence_status(&user_id) == UserStatus::Dnd }).await;
+        assert_eq!(b.presence_status(&user_id), UserStatus::Dnd);
+
+        a.remove_device(user_id, phone);
+        eventually(|| async { b.presence_status(&user_id) == UserStatus::Offline }).await;
+        b.remove_device(user_id, laptop);
+        eventually(|| async { !a.user_is_connected(&user_id) }).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn user_events_reach_their_sessions_on_other_nodes() {
+        let (_pool, a, b) = nodes().await;
+        let user_id = Uuid::new_v4();
+
+        let session = Arc::new(Session::new(user_id, SlowConsumerPolicy::DropNonCritical));
+        let mut on_b = b.register_user(&session);
+        b.add_device(user_id, Uuid::new_v4(), UserStatus::Online);
+        eventually(|| async { a.user_is_connected(&user_id) }).await;
+
+        a.send_to_user(&user_id, &message_update("for you".into()));
+        let event = tokio::time::timeout(Duration::from_secs(5), on_b.recv()).await.expect("timed out").unwrap();
+        assert!(matches!(event, WsEvent::MessageUpdate { content, .. } if content == "for you"));
+    }
+}
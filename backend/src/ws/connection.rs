@@ -1,49 +1,90 @@
-/// Full per-connection WebSocket handler.
-///
-/// Lifecycle:
-///   1. Wait for `identify` event with JWT → verify → register user
-///   2. Send `Ready` event
-///   3. Enter main loop: read client events + forward outbound events
-///   4. On disconnect: unregister user + broadcast presence offline
-
-use std::collections::HashSet;
-
-use axum::extract::ws::{Message, WebSocket};
+//! Full per-connection WebSocket handler.
+//!
+//! Lifecycle:
+//!   1. Send `Hello`, wait for `identify` (or `resume`) with JWT → verify
+//!   2. Resume the session, or start a new one and send `Ready` (then a
+//!      `ServerCreate` per large server)
+//!   3. Enter main loop: read client events; the session sends events out
+//!   4. On disconnect: detach from the session, which is torn down (user
+//!      unregistered, voice state removed, presence offline) right away on a
+//!      clean close, or if not resumed within `RESUME_WINDOW`
+//!
+//! Clients must send `heartbeat` every `heartbeat_interval` ms; a connection
+//! that goes silent for half as long again is closed with
+//! `CloseCode::HeartbeatTimeout`, which also reaps half-open TCP connections.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use uuid::Uuid;
 
 use crate::AppState;
-use crate::auth::verify_token;
-use crate::models::ProfileSummary;
-use crate::ws::events::{ClientEvent, WsEvent};
+use crate::auth::{access, verify_token};
+use crate::error::{AppError, AppResult};
+use crate::handlers::voice::{self, VoiceFlags};
+use crate::handlers::{channels, dms, read_states, servers};
+use crate::models::{MessageKind, ProfileSummary, UserStatus};
+use crate::permissions::Permissions;
+use crate::ws::events::{ClientEvent, CloseCode, ErrorCode, WsEvent};
+use crate::ws::{presence, ready};
+use crate::ws::session::{Outbound, Session, Subscription, OUTBOUND_QUEUE_SIZE, RESUME_WINDOW};
+
+/// How long a close frame may take to go out before the socket is dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Handle a single WebSocket connection from upgrade to close.
 pub async fn handle_connection(socket: WebSocket, state: AppState) {
     let (mut ws_sink, mut ws_stream) = socket.split();
 
-    // ── Step 1: Wait for identify ───────────────────────────────────
-    let user_id = loop {
-        match ws_stream.next().await {
+    let heartbeat_interval = state.config.ws_heartbeat_interval_ms;
+    let heartbeat_timeout = Duration::from_millis(heartbeat_interval * 3 / 2);
+    let mut deadline = Instant::now() + heartbeat_timeout;
+
+    let hello = serde_json::to_string(&WsEvent::Hello { heartbeat_interval }).unwrap();
+    if ws_sink.send(Message::Text(hello)).await.is_err() {
+        return;
+    }
+
+    // ── Step 1: Wait for identify / resume ──────────────────────────
+    let (user_id, resume) = loop {
+        let Ok(msg) = tokio::time::timeout_at(deadline, ws_stream.next()).await else {
+            close_socket(&mut ws_sink, CloseCode::HeartbeatTimeout).await;
+            return;
+        };
+        match msg {
             Some(Ok(Message::Text(text))) => {
-                match serde_json::from_str::<ClientEvent>(&text) {
-                    Ok(ClientEvent::Identify { token }) => {
-                        match verify_token(&state.config.supabase_jwt_secret, &token) {
-                            Ok(uid) => break uid,
-                            Err(e) => {
-                                let err = serde_json::to_string(&WsEvent::Error { message: e }).unwrap();
-                                let _ = ws_sink.send(Message::Text(err)).await;
-                                return; // close connection
-                            }
-                        }
+                let (token, resume) = match serde_json::from_str::<ClientEvent>(&text) {
+                    Ok(ClientEvent::Identify { token }) => (token, None),
+                    Ok(ClientEvent::Resume { token, session_id, seq }) => (token, Some((session_id, seq))),
+                    Ok(ClientEvent::Heartbeat) => {
+                        deadline = Instant::now() + heartbeat_timeout;
+                        let ack = serde_json::to_string(&WsEvent::HeartbeatAck).unwrap();
+                        let _ = ws_sink.send(Message::Text(ack)).await;
+                        continue;
                     }
                     _ => {
-                        let err = serde_json::to_string(&WsEvent::Error {
-                            message: "First message must be identify".into(),
-                        }).unwrap();
+                        let err = serde_json::to_string(&WsEvent::error(
+                            ErrorCode::Unauthorized,
+                            "First message must be identify or resume",
+                        )).unwrap();
                         let _ = ws_sink.send(Message::Text(err)).await;
                         return;
                     }
+                };
+                match verify_token(&state.config.supabase_jwt_secret, &token) {
+                    Ok(uid) => break (uid, resume),
+                    Err(e) => {
+                        let err = serde_json::to_string(&WsEvent::error(ErrorCode::Unauthorized, e)).unwrap();
+                        let _ = ws_sink.send(Message::Text(err)).await;
+                        return; // close connection
+                    }
                 }
             }
             Some(Ok(Message::Close(_))) | None => return,
@@ -51,255 +92,531 @@ pub async fn handle_connection(socket: WebSocket, state: AppState) {
         }
     };
 
-    tracing::info!("WS identified: user={user_id}");
+    // Spawn a task writing this socket's outbound frames. It ends when the
+    // session drops the queue, i.e. when another socket resumes the session,
+    // or after sending a close frame.
+    let (outbound_tx, mut outbound_rx) = mpsc::channel::<String>(OUTBOUND_QUEUE_SIZE);
+    let (close_tx, mut close_rx) = mpsc::channel::<CloseCode>(1);
+    let mut forward_task = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                biased;
+                Some(code) = close_rx.recv() => {
+                    close_socket(&mut ws_sink, code).await;
+                    break;
+                }
+                json = outbound_rx.recv() => match json {
+                    Some(json) => {
+                        if ws_sink.send(Message::Text(json)).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+    });
+    let outbound = || Outbound { frames: outbound_tx.clone(), close: close_tx.clone() };
 
-    // ── Step 2: Register user connection ────────────────────────────
-    let mut user_rx = state.ws_state.register_user(user_id);
+    // ── Step 2: Resume the session, or start a new one ──────────────
+    let resumed = resume.and_then(|(session_id, seq)| {
+        let session = state.ws_state.session(&session_id).filter(|s| s.user_id == user_id)?;
+        let attach_id = session.resume(outbound(), seq)?;
+        Some((session, attach_id))
+    });
 
-    // Send Ready event
-    let ready = serde_json::to_string(&WsEvent::Ready { user_id }).unwrap();
-    if ws_sink.send(Message::Text(ready)).await.is_err() {
-        return;
-    }
+    let (session, attach_id) = match resumed {
+        Some((session, attach_id)) => {
+            tracing::info!("WS resumed: user={user_id} session={}", session.id);
+            (session, attach_id)
+        }
+        None => {
+            let session = start_session(&state, user_id);
+            tracing::info!("WS identified: user={user_id} session={}", session.id);
 
-    // Broadcast presence: online
-    broadcast_presence(&state, user_id, "online").await;
+            // Before `Ready`, so it lists the user's own presence too
+            let status = presence::saved_status(&state.pool, user_id).await;
+            let presence_changed = state.ws_state.add_device(user_id, session.id, status);
 
-    // Track which channels/DMs this connection is subscribed to
-    let mut subscribed_channels: HashSet<Uuid> = HashSet::new();
-    let mut subscribed_dms: HashSet<Uuid> = HashSet::new();
+            let (ready, lazy_servers) = match ready::build(&state, user_id, session.id).await {
+                Ok(ready) => ready,
+                Err(e) => {
+                    tracing::error!("Failed to build Ready for user {user_id}: {e}");
+                    if let Ok(json) = serde_json::to_string(&WsEvent::from(&e)) {
+                        let _ = outbound_tx.try_send(json);
+                    }
+                    end_session(&state, &session).await;
+                    return;
+                }
+            };
+            if let Ok(json) = serde_json::to_string(&ready) {
+                let _ = outbound_tx.try_send(json);
+            }
+            let attach_id = session.attach(outbound());
+            tokio::spawn(ready::stream_servers(state.clone(), session.clone(), lazy_servers));
 
-    // Spawn a task to forward outbound events from the user's mpsc channel to the websocket
-    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<String>();
-    let forward_task = tokio::spawn(async move {
-        while let Some(json) = outbound_rx.recv().await {
-            if ws_sink.send(Message::Text(json)).await.is_err() {
-                break;
+            if presence_changed {
+                presence::broadcast(&state, user_id).await;
             }
+            session.add_task(tokio::spawn(presence::watch_idle(state.clone(), user_id, session.id)));
+            (session, attach_id)
         }
-    });
+    };
+    // Only the session holds the queue from here on
+    drop(outbound_tx);
+
+    // ── Step 3: Main loop: client events ────────────────────────────
+    // Set when the client closed on purpose, so there's nothing to resume
+    let mut clean_close = false;
+    loop {
+        tokio::select! {
+            msg = ws_stream.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let event = match serde_json::from_str::<ClientEvent>(&text) {
+                        Ok(e) => e,
+                        Err(e) => {
+                            session.send_unsequenced(&WsEvent::error(ErrorCode::InvalidEvent, e.to_string()));
+                            continue;
+                        }
+                    };
 
-    // Spawn a task to forward broadcast channel events → user's outbound
-    let outbound_tx_for_user = outbound_tx.clone();
-    let user_event_task = tokio::spawn(async move {
-        while let Some(event) = user_rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&event) {
-                if outbound_tx_for_user.send(json).is_err() {
+                    if let ClientEvent::Heartbeat = event {
+                        deadline = Instant::now() + heartbeat_timeout;
+                    } else if state.ws_state.device_active(user_id, session.id) {
+                        // Back from idle
+                        presence::broadcast(&state, user_id).await;
+                    }
+                    handle_client_event(&state, &session, event).await;
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    clean_close = frame.is_some_and(|f| matches!(f.code, close_code::NORMAL | close_code::AWAY));
                     break;
                 }
+                Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+
+            // Missed heartbeats: most likely a half-open connection
+            _ = tokio::time::sleep_until(deadline) => {
+                tracing::info!("WS heartbeat timed out: user={user_id} session={}", session.id);
+                let _ = close_tx.try_send(CloseCode::HeartbeatTimeout);
+                let _ = tokio::time::timeout(CLOSE_TIMEOUT, &mut forward_task).await;
+                break;
             }
-        }
-    });
-
-    // ── Step 3: Main read loop ──────────────────────────────────────
-    while let Some(Ok(msg)) = ws_stream.next().await {
-        match msg {
-            Message::Text(text) => {
-                let event = match serde_json::from_str::<ClientEvent>(&text) {
-                    Ok(e) => e,
-                    Err(_) => continue,
-                };
 
-                handle_client_event(
-                    &state,
-                    user_id,
-                    event,
-                    &outbound_tx,
-                    &mut subscribed_channels,
-                    &mut subscribed_dms,
-                ).await;
-            }
-            Message::Close(_) => break,
-            _ => {}
+            // The socket can't be written to, or was replaced by a resume
+            _ = &mut forward_task => break,
         }
     }
 
     // ── Step 4: Cleanup on disconnect ───────────────────────────────
-    tracing::info!("WS disconnected: user={user_id}");
-
-    // Abort forwarding tasks
+    tracing::info!("WS disconnected: user={user_id} session={}", session.id);
     forward_task.abort();
-    user_event_task.abort();
 
-    // Unsubscribe from all channels (drop broadcast receivers)
-    // (receivers are dropped automatically when the tasks are aborted)
+    if !session.detach(attach_id) {
+        // Resumed on another socket
+        return;
+    }
+    if clean_close {
+        end_session(&state, &session).await;
+        return;
+    }
 
-    // Unregister user connection
-    // We need the original sender to identify which connection to remove.
-    // Since we moved user_rx, we'll just remove all closed senders for this user.
+    // Keep the session (subscriptions, buffered events) around for a resume
+    tokio::spawn(async move {
+        tokio::time::sleep(RESUME_WINDOW).await;
+        if session.is_idle_since(attach_id) {
+            end_session(&state, &session).await;
+        }
+    });
+}
+
+/// Send a close frame with `code`, giving up after `CLOSE_TIMEOUT`.
+async fn close_socket(ws_sink: &mut SplitSink<WebSocket, Message>, code: CloseCode) {
+    let frame = CloseFrame { code: code as u16, reason: code.reason().into() };
+    let _ = tokio::time::timeout(CLOSE_TIMEOUT, ws_sink.send(Message::Close(Some(frame)))).await;
+}
+
+/// Create and register a session for `user_id`, with a task feeding it the
+/// events sent to the user.
+fn start_session(state: &AppState, user_id: Uuid) -> Arc<Session> {
+    let session = Arc::new(Session::new(user_id, state.config.ws_slow_consumer_policy));
+    let mut user_rx = state.ws_state.register_user(&session);
+
+    let task = {
+        let state = state.clone();
+        let session = session.clone();
+        tokio::spawn(async move {
+            while let Some(event) = user_rx.recv().await {
+                // Kicked or banned: stop receiving the server's channel events
+                if let WsEvent::ServerRemove { server_id, .. } = &event {
+                    unsubscribe_server(&state, &session, *server_id).await;
+                }
+                session.dispatch(&event);
+            }
+        })
+    };
+    session.add_task(task);
+
+    state.ws_state.insert_session(session.clone());
+    session
+}
+
+/// Tear down a session that wasn't resumed in time.
+async fn end_session(state: &AppState, session: &Session) {
+    let user_id = session.user_id;
+    tracing::info!("WS session expired: user={user_id} session={}", session.id);
+
+    state.ws_state.remove_session(&session.id);
+    // Aborting the tasks drops their broadcast receivers and the user's
+    // mpsc receiver, so the closed sender can be cleaned up
+    for subscription in session.close().await {
+        state.ws_state.release_channel(&subscription.id());
+    }
     state.ws_state.cleanup_closed_senders(user_id);
 
-    // Broadcast presence offline (only if no more connections for this user)
+    if let Err(e) = voice::disconnect_session(state, session.id).await {
+        tracing::error!("Failed to remove voice state of session {}: {e}", session.id);
+    }
+
+    // Offline, or whatever the user's other sessions show
+    if state.ws_state.remove_device(user_id, session.id) {
+        presence::broadcast(state, user_id).await;
+    }
     if !state.ws_state.user_is_connected(&user_id) {
-        broadcast_presence(&state, user_id, "offline").await;
+        remove_temporary_memberships(state, user_id).await;
     }
 }
 
 /// Process a single client event.
-async fn handle_client_event(
-    state: &AppState,
-    user_id: Uuid,
-    event: ClientEvent,
-    outbound_tx: &mpsc::UnboundedSender<String>,
-    subscribed_channels: &mut HashSet<Uuid>,
-    subscribed_dms: &mut HashSet<Uuid>,
-) {
+async fn handle_client_event(state: &AppState, session: &Arc<Session>, event: ClientEvent) {
+    let user_id = session.user_id;
+
+    // Reject events targeting channels / DMs the user has no access to
+    if let Err(e) = authorize_event(state, user_id, &event).await {
+        tracing::debug!("Rejected event from user {user_id}: {e}");
+        session.send_unsequenced(&WsEvent::from(&e));
+        return;
+    }
+
     match event {
-        ClientEvent::Identify { .. } => {
+        ClientEvent::Identify { .. } | ClientEvent::Resume { .. } => {
             // Already identified, ignore duplicate
         }
 
+        ClientEvent::Heartbeat => {
+            session.send_unsequenced(&WsEvent::HeartbeatAck);
+        }
+
         ClientEvent::SubscribeChannel { channel_id } => {
-            subscribed_channels.insert(channel_id);
-            // Get or create a broadcast channel and spawn a receiver task
-            let tx = state.ws_state.get_or_create_channel(channel_id);
-            let mut rx = tx.subscribe();
-            let outbound = outbound_tx.clone();
-            tokio::spawn(async move {
-                while let Ok(event) = rx.recv().await {
-                    if let Ok(json) = serde_json::to_string(&event) {
-                        if outbound.send(json).is_err() {
-                            break;
-                        }
-                    }
-                }
-            });
-            tracing::debug!("User {user_id} subscribed to channel {channel_id}");
+            let subscription = Subscription::Channel(channel_id);
+            if session.subscribe(subscription, || forward_broadcasts(state, session, subscription)) {
+                tracing::debug!("User {user_id} subscribed to channel {channel_id}");
+            }
         }
 
         ClientEvent::UnsubscribeChannel { channel_id } => {
-            subscribed_channels.remove(&channel_id);
-            // Broadcast receivers will be dropped when the spawn task ends
-            tracing::debug!("User {user_id} unsubscribed from channel {channel_id}");
+            if unsubscribe(state, session, Subscription::Channel(channel_id)).await {
+                tracing::debug!("User {user_id} unsubscribed from channel {channel_id}");
+            }
         }
 
         ClientEvent::SubscribeDm { dm_channel_id } => {
-            subscribed_dms.insert(dm_channel_id);
-            let tx = state.ws_state.get_or_create_channel(dm_channel_id);
-            let mut rx = tx.subscribe();
-            let outbound = outbound_tx.clone();
-            tokio::spawn(async move {
-                while let Ok(event) = rx.recv().await {
-                    if let Ok(json) = serde_json::to_string(&event) {
-                        if outbound.send(json).is_err() {
-                            break;
-                        }
-                    }
-                }
-            });
-            tracing::debug!("User {user_id} subscribed to DM {dm_channel_id}");
+            let subscription = Subscription::Dm(dm_channel_id);
+            if session.subscribe(subscription, || forward_broadcasts(state, session, subscription)) {
+                tracing::debug!("User {user_id} subscribed to DM {dm_channel_id}");
+            }
         }
 
         ClientEvent::UnsubscribeDm { dm_channel_id } => {
-            subscribed_dms.remove(&dm_channel_id);
-            tracing::debug!("User {user_id} unsubscribed from DM {dm_channel_id}");
+            if unsubscribe(state, session, Subscription::Dm(dm_channel_id)).await {
+                tracing::debug!("User {user_id} unsubscribed from DM {dm_channel_id}");
+            }
+        }
+
+        ClientEvent::MessageCreate { channel_id, content, reply_to, attachments } => {
+            // The create helper persists the message and broadcasts `MessageCreate`
+            if let Err(e) = channels::create_message(state, user_id, channel_id, content, reply_to, attachments).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::DmCreate { dm_channel_id, content, attachments } => {
+            // Broadcasts `DmCreate` and pushes it to participants not yet subscribed
+            if let Err(e) = dms::create_dm_message(state, user_id, dm_channel_id, content, attachments).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::MessageUpdate { channel_id, message_id, content } => {
+            // The edit helper broadcasts `MessageUpdate` to the channel itself
+            if let Err(e) = channels::edit_message(state, user_id, channel_id, message_id, content).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::DmUpdate { dm_channel_id, message_id, content } => {
+            if let Err(e) = dms::edit_dm_message(state, user_id, dm_channel_id, message_id, content).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::MessageDelete { channel_id, message_id } => {
+            if let Err(e) = channels::tombstone_message(state, user_id, channel_id, message_id, None).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::DmDelete { dm_channel_id, message_id } => {
+            if let Err(e) = dms::tombstone_dm_message(state, user_id, dm_channel_id, message_id).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::TypingStart { channel_id, dm_channel_id } => {
+            let Ok((kind, target)) = typing_target(channel_id, dm_channel_id) else {
+                return;
+            };
+            // Repeats while already typing only extend the indicator
+            if state.ws_state.typing_start(user_id, kind, target) {
+                let user = get_profile_summary(state, user_id).await;
+                let event = WsEvent::TypingStart { channel_id, dm_channel_id, user };
+                state.ws_state.broadcast_to_channel(&target, event);
+            }
         }
 
-        ClientEvent::MessageCreate { channel_id, content } => {
-            // 1. Persist message to DB
-            let row = sqlx::query_as::<_, (Uuid, chrono::DateTime<chrono::Utc>)>(
-                "INSERT INTO messages (channel_id, author_id, content) VALUES ($1, $2, $3) RETURNING id, created_at"
+        ClientEvent::TypingStop { channel_id, dm_channel_id } => {
+            if let Ok((_, target)) = typing_target(channel_id, dm_channel_id) {
+                state.ws_state.typing_stop(user_id, target);
+            }
+        }
+
+        ClientEvent::PresenceUpdate { status } => {
+            // New sessions start with the status picked last
+            let saved = sqlx::query("UPDATE profiles SET status = $1, updated_at = now() WHERE id = $2")
+                .bind(status)
+                .bind(user_id)
+                .execute(&state.pool)
+                .await;
+            if let Err(e) = saved {
+                session.send_unsequenced(&WsEvent::from(&AppError::from(e)));
+                return;
+            }
+
+            if state.ws_state.set_device_status(user_id, session.id, status) {
+                presence::broadcast(state, user_id).await;
+            }
+        }
+
+        ClientEvent::VoiceStateUpdate { channel_id, muted, deafened, video_on, screen_sharing } => {
+            let flags = VoiceFlags { muted, deafened, video_on, screen_sharing };
+            // Broadcasts `VoiceStateUpdate` for the channel joined and / or left
+            if let Err(e) = voice::update_voice_state(state, user_id, session.id, channel_id, flags).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+
+        ClientEvent::Ack { channel_id, dm_channel_id, message_id } => {
+            let (kind, target) = match (channel_id, dm_channel_id) {
+                (Some(channel_id), _) => (MessageKind::Channel, channel_id),
+                (None, Some(dm_channel_id)) => (MessageKind::Dm, dm_channel_id),
+                (None, None) => return,
+            };
+            // `ack` syncs the new read state to all of the user's devices
+            if let Err(e) = read_states::ack(state, user_id, kind, target, message_id).await {
+                session.send_unsequenced(&WsEvent::from(&e));
+            }
+        }
+    }
+}
+
+/// Check that the user may perform `event`, applying the same membership and
+/// permission rules as the REST endpoints for the targeted channel or DM.
+async fn authorize_event(state: &AppState, user_id: Uuid, event: &ClientEvent) -> AppResult<()> {
+    match event {
+        ClientEvent::SubscribeChannel { channel_id } | ClientEvent::MessageDelete { channel_id, .. } => {
+            access::require_channel_permission(&state.pool, *channel_id, user_id, Permissions::VIEW_CHANNEL).await?;
+        }
+
+        ClientEvent::MessageCreate { channel_id, .. } | ClientEvent::MessageUpdate { channel_id, .. } => {
+            access::require_channel_permission(
+                &state.pool,
+                *channel_id,
+                user_id,
+                Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
             )
-            .bind(channel_id)
-            .bind(user_id)
-            .bind(&content)
-            .fetch_one(&state.pool)
-            .await;
-
-            match row {
-                Ok((msg_id, created_at)) => {
-                    // 2. Fetch author profile summary
-                    let author = get_profile_summary(&state, user_id).await;
-
-                    // 3. Broadcast to channel
-                    let event = WsEvent::MessageCreate {
-                        id: msg_id,
-                        channel_id,
-                        author,
-                        content,
-                        created_at: created_at.to_rfc3339(),
-                    };
-                    state.ws_state.broadcast_to_channel(&channel_id, event);
-                }
-                Err(e) => {
-                    tracing::error!("Failed to insert message: {e}");
-                    let err = serde_json::to_string(&WsEvent::Error {
-                        message: "Failed to send message".into(),
-                    }).unwrap();
-                    let _ = outbound_tx.send(err);
-                }
+            .await?;
+        }
+
+        ClientEvent::TypingStart { channel_id, dm_channel_id } => match typing_target(*channel_id, *dm_channel_id)? {
+            (MessageKind::Channel, channel_id) => {
+                access::require_channel_permission(
+                    &state.pool,
+                    channel_id,
+                    user_id,
+                    Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+                )
+                .await?;
+            }
+            (MessageKind::Dm, dm_channel_id) => {
+                access::require_dm_member(&state.pool, dm_channel_id, user_id).await?;
             }
+        },
+
+        // Only ends the caller's own indicator
+        ClientEvent::TypingStop { channel_id, dm_channel_id } => {
+            typing_target(*channel_id, *dm_channel_id)?;
         }
 
-        ClientEvent::DmCreate { dm_channel_id, content } => {
-            // 1. Persist DM message to DB
-            let row = sqlx::query_as::<_, (Uuid, chrono::DateTime<chrono::Utc>)>(
-                "INSERT INTO dm_messages (dm_channel_id, author_id, content) VALUES ($1, $2, $3) RETURNING id, created_at"
+        ClientEvent::SubscribeDm { dm_channel_id }
+        | ClientEvent::DmCreate { dm_channel_id, .. }
+        | ClientEvent::DmUpdate { dm_channel_id, .. }
+        | ClientEvent::DmDelete { dm_channel_id, .. } => {
+            access::require_dm_member(&state.pool, *dm_channel_id, user_id).await?;
+        }
+
+        ClientEvent::Ack { channel_id, dm_channel_id, .. } => match (channel_id, dm_channel_id) {
+            (Some(channel_id), None) => {
+                access::require_channel_permission(&state.pool, *channel_id, user_id, Permissions::VIEW_CHANNEL).await?;
+            }
+            (None, Some(dm_channel_id)) => {
+                access::require_dm_member(&state.pool, *dm_channel_id, user_id).await?;
+            }
+            _ => {
+                return Err(AppError::BadRequest("Ack needs exactly one of channel_id and dm_channel_id".into()));
+            }
+        },
+
+        ClientEvent::VoiceStateUpdate { channel_id: Some(channel_id), .. } => {
+            access::require_channel_permission(
+                &state.pool,
+                *channel_id,
+                user_id,
+                Permissions::VIEW_CHANNEL | Permissions::CONNECT,
             )
-            .bind(dm_channel_id)
-            .bind(user_id)
-            .bind(&content)
-            .fetch_one(&state.pool)
-            .await;
-
-            match row {
-                Ok((msg_id, created_at)) => {
-                    let author = get_profile_summary(&state, user_id).await;
-
-                    // Broadcast to the DM channel (both participants receive it)
-                    let event = WsEvent::DmCreate {
-                        id: msg_id,
-                        dm_channel_id,
-                        author,
-                        content,
-                        created_at: created_at.to_rfc3339(),
-                    };
-                    state.ws_state.broadcast_to_channel(&dm_channel_id, event.clone());
-
-                    // Also send directly to the other participant if they're connected
-                    // (in case they haven't subscribed to this DM channel yet)
-                    if let Ok(members) = sqlx::query_scalar::<_, Uuid>(
-                        "SELECT user_id FROM dm_members WHERE dm_channel_id = $1 AND user_id != $2"
-                    )
-                    .bind(dm_channel_id)
-                    .bind(user_id)
-                    .fetch_all(&state.pool)
-                    .await {
-                        for member_id in members {
-                            state.ws_state.send_to_user(&member_id, &event);
+            .await?;
+        }
+
+        ClientEvent::PresenceUpdate { status: UserStatus::Offline } => {
+            return Err(AppError::BadRequest("Use invisible to appear offline".into()));
+        }
+
+        // Only affect the caller's own connection / profile
+        ClientEvent::Identify { .. }
+        | ClientEvent::Resume { .. }
+        | ClientEvent::Heartbeat
+        | ClientEvent::UnsubscribeChannel { .. }
+        | ClientEvent::UnsubscribeDm { .. }
+        | ClientEvent::PresenceUpdate { .. }
+        | ClientEvent::VoiceStateUpdate { channel_id: None, .. } => {}
+    }
+
+    Ok(())
+}
+
+/// Where a `typing_start` / `typing_stop` is aimed: exactly one of a channel
+/// and a DM.
+fn typing_target(channel_id: Option<Uuid>, dm_channel_id: Option<Uuid>) -> AppResult<(MessageKind, Uuid)> {
+    match (channel_id, dm_channel_id) {
+        (Some(channel_id), None) => Ok((MessageKind::Channel, channel_id)),
+        (None, Some(dm_channel_id)) => Ok((MessageKind::Dm, dm_channel_id)),
+        _ => Err(AppError::BadRequest("Typing needs exactly one of channel_id and dm_channel_id".into())),
+    }
+}
+
+/// Spawn a task forwarding a channel's (or DM's) broadcasts to the session.
+///
+/// Access to a channel is checked again before forwarding whenever roles or
+/// overwrites in its server have changed since the last check; once the user
+/// can no longer view it, they get `SubscriptionRevoked` and the task ends.
+fn forward_broadcasts(state: &AppState, session: &Arc<Session>, subscription: Subscription) -> JoinHandle<()> {
+    let (channel_id, dm_channel_id) = match subscription {
+        Subscription::Channel(id) => (Some(id), None),
+        Subscription::Dm(id) => (None, Some(id)),
+    };
+    let mut rx = state.ws_state.subscribe_channel(subscription.id());
+    let state = state.clone();
+    let session = session.clone();
+    tokio::spawn(async move {
+        let mut checked = None;
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Some(channel_id) = channel_id {
+                        if !may_still_view(&state, session.user_id, channel_id, &mut checked).await {
+                            tracing::debug!("User {} can no longer view channel {channel_id}", session.user_id);
+                            session.dispatch(&WsEvent::SubscriptionRevoked { channel_id });
+                            break;
                         }
                     }
+                    session.dispatch(&event);
                 }
-                Err(e) => {
-                    tracing::error!("Failed to insert DM: {e}");
-                    let err = serde_json::to_string(&WsEvent::Error {
-                        message: "Failed to send DM".into(),
-                    }).unwrap();
-                    let _ = outbound_tx.send(err);
+                // Fell behind the broadcast buffer: skip ahead and tell the client
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("WS session {} lagged {missed} events on {subscription:?}", session.id);
+                    session.dispatch(&WsEvent::SubscriptionLagged { channel_id, dm_channel_id, missed });
                 }
+                Err(RecvError::Closed) => break,
             }
         }
+        drop(rx);
+        state.ws_state.release_channel(&subscription.id());
+    })
+}
 
-        ClientEvent::TypingStart { channel_id } => {
-            let user = get_profile_summary(&state, user_id).await;
-            let event = WsEvent::TypingStart { channel_id, user };
-            state.ws_state.broadcast_to_channel(&channel_id, event);
-        }
+/// Whether a subscriber may still view `channel_id`. `checked` holds the
+/// channel's server and its permissions epoch as of the last check that
+/// passed; the check only runs again once that epoch has moved on.
+async fn may_still_view(state: &AppState, user_id: Uuid, channel_id: Uuid, checked: &mut Option<(Uuid, u64)>) -> bool {
+    // Read before checking once the server is known, so a change made
+    // meanwhile triggers another check
+    let epoch = match *checked {
+        Some((server_id, epoch)) if state.ws_state.permissions_epoch(&server_id) == epoch => return true,
+        Some((server_id, _)) => Some(state.ws_state.permissions_epoch(&server_id)),
+        None => None,
+    };
 
-        ClientEvent::PresenceUpdate { status } => {
-            // Update presence in DB
-            let _ = sqlx::query("UPDATE profiles SET status = $1::user_status WHERE id = $2")
-                .bind(&status)
-                .bind(user_id)
-                .execute(&state.pool)
-                .await;
+    match access::require_channel_permission(&state.pool, channel_id, user_id, Permissions::VIEW_CHANNEL).await {
+        Ok(channel) => {
+            let epoch = epoch.unwrap_or_else(|| state.ws_state.permissions_epoch(&channel.server_id));
+            *checked = Some((channel.server_id, epoch));
+            true
+        }
+        Err(AppError::Forbidden(_) | AppError::NotFound(_)) => false,
+        Err(e) => {
+            tracing::error!("Failed to check access to channel {channel_id}: {e}");
+            false
+        }
+    }
+}
 
-            // Broadcast to all servers the user belongs to
-            broadcast_presence(state, user_id, &status).await;
+/// Drop the session's subscriptions to every channel of `server_id`.
+async fn unsubscribe_server(state: &AppState, session: &Session, server_id: Uuid) {
+    let channel_ids = match sqlx::query_scalar::<_, Uuid>("SELECT id FROM channels WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_all(&state.pool)
+        .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("Failed to load channels of server {server_id}: {e}");
+            return;
         }
+    };
+
+    for channel_id in channel_ids {
+        unsubscribe(state, session, Subscription::Channel(channel_id)).await;
     }
 }
 
+/// Stop one of the session's subscriptions, dropping the channel's
+/// broadcast sender if that was its last subscriber.
+async fn unsubscribe(state: &AppState, session: &Session, subscription: Subscription) -> bool {
+    let unsubscribed = session.unsubscribe(subscription).await;
+    state.ws_state.release_channel(&subscription.id());
+    unsubscribed
+}
+
 /// Fetch a user's profile summary for embedding in events.
 async fn get_profile_summary(state: &AppState, user_id: Uuid) -> ProfileSummary {
     sqlx::query_as::<_, ProfileSummary>(
@@ -318,27 +635,190 @@ async fn get_profile_summary(state: &AppState, user_id: Uuid) -> ProfileSummary
     })
 }
 
-/// Broadcast a presence update to users who share a server with this user.
-async fn broadcast_presence(state: &AppState, user_id: Uuid, status: &str) {
-    let event = WsEvent::PresenceUpdate {
-        user_id,
-        status: status.to_string(),
-    };
-
-    // Get all users who share a server with this user
-    if let Ok(peer_ids) = sqlx::query_scalar::<_, Uuid>(
-        r#"
-        SELECT DISTINCT sm2.user_id
-        FROM server_members sm1
-        JOIN server_members sm2 ON sm1.server_id = sm2.server_id
-        WHERE sm1.user_id = $1 AND sm2.user_id != $1
-        "#
+/// Drop memberships gained through temporary invites once the user's last
+/// connection closes (members who were given a role in the meantime stay).
+/// They leave the same way as through `leave_server`.
+async fn remove_temporary_memberships(state: &AppState, user_id: Uuid) {
+    let server_ids = sqlx::query_scalar::<_, Uuid>(
+        "SELECT server_id FROM server_members WHERE user_id = $1 AND temporary"
     )
     .bind(user_id)
     .fetch_all(&state.pool)
-    .await {
-        for peer_id in peer_ids {
-            state.ws_state.send_to_user(&peer_id, &event);
+    .await;
+
+    let server_ids = match server_ids {
+        Ok(server_ids) => server_ids,
+        Err(e) => {
+            tracing::error!("Failed to load temporary memberships of user {user_id}: {e}");
+            return;
+        }
+    };
+    for server_id in server_ids {
+        match servers::leave(state, server_id, user_id, true).await {
+            Ok(true) => tracing::debug!("Removed temporary member {user_id} from server {server_id}"),
+            Ok(false) => {}
+            Err(e) => tracing::error!("Failed to remove temporary member {user_id} from server {server_id}: {e}"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AuditAction;
+    use crate::test_util;
+
+    fn message_update(channel_id: Uuid, content: &str) -> WsEvent {
+        WsEvent::MessageUpdate {
+            id: Uuid::new_v4(),
+            channel_id,
+            content: content.into(),
+            edited_at: "2026-01-01T00:00:00Z".into(),
         }
     }
+
+    /// The type of the next frame sent to the socket, and its content if any.
+    async fn next_frame(frames: &mut mpsc::Receiver<String>) -> (String, Option<String>) {
+        let frame = tokio::time::timeout(Duration::from_secs(5), frames.recv()).await.expect("timed out").unwrap();
+        let frame: serde_json::Value = serde_json::from_str(&frame).unwrap();
+        (
+            frame["type"].as_str().unwrap().to_owned(),
+            frame["content"].as_str().map(str::to_owned),
+        )
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn subscriptions_end_when_access_is_lost() {
+        let pool = test_util::database().await;
+        let owner = test_util::user(&pool, "Owner").await;
+        let member = test_util::user(&pool, "Member").await;
+        let server = test_util::server(&pool, owner).await;
+        test_util::join(&pool, server.id, member).await;
+        let state = test_util::app_state(pool.clone());
+        let channel_id = server.text_channel;
+
+        let session = Arc::new(Session::new(member, state.config.ws_slow_consumer_policy));
+        let (frames_tx, mut frames) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let (close, _close_rx) = mpsc::channel(1);
+        session.attach(Outbound { frames: frames_tx, close });
+
+        let subscribe = || ClientEvent::SubscribeChannel { channel_id };
+        handle_client_event(&state, &session, subscribe()).await;
+        state.ws_state.broadcast_to_channel(&channel_id, message_update(channel_id, "before"));
+        assert_eq!(next_frame(&mut frames).await, ("message_update".into(), Some("before".into())));
+
+        // Shut the member out of the channel mid-session
+        sqlx::query(
+            "INSERT INTO channel_overwrites (channel_id, target_id, kind, deny) VALUES ($1, $2, 'member', $3)"
+        )
+        .bind(channel_id)
+        .bind(member)
+        .bind(Permissions::VIEW_CHANNEL)
+        .execute(&pool)
+        .await
+        .unwrap();
+        state.ws_state.permissions_changed(server.id);
+
+        state.ws_state.broadcast_to_channel(&channel_id, message_update(channel_id, "after"));
+        assert_eq!(next_frame(&mut frames).await, ("subscription_revoked".into(), None));
+        state.ws_state.broadcast_to_channel(&channel_id, message_update(channel_id, "later"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(frames.try_recv().is_err());
+
+        // Subscribing again is refused until access is restored
+        handle_client_event(&state, &session, subscribe()).await;
+        assert_eq!(next_frame(&mut frames).await.0, "error");
+
+        sqlx::query("DELETE FROM channel_overwrites WHERE channel_id = $1")
+            .bind(channel_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        state.ws_state.permissions_changed(server.id);
+        handle_client_event(&state, &session, subscribe()).await;
+        state.ws_state.broadcast_to_channel(&channel_id, message_update(channel_id, "again"));
+        assert_eq!(next_frame(&mut frames).await, ("message_update".into(), Some("again".into())));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn temporary_members_leave_like_everyone_else() {
+        let pool = test_util::database().await;
+        let owner = test_util::user(&pool, "Owner").await;
+        let guest = test_util::user(&pool, "Guest").await;
+        let server = test_util::server(&pool, owner).await;
+        test_util::join(&pool, server.id, guest).await;
+        sqlx::query("UPDATE server_members SET temporary = true WHERE user_id = $1")
+            .bind(guest)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let state = test_util::app_state(pool.clone());
+
+        let owner_session = Arc::new(Session::new(owner, state.config.ws_slow_consumer_policy));
+        let mut owner_events = state.ws_state.register_user(&owner_session);
+
+        remove_temporary_memberships(&state, guest).await;
+
+        let member = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)"
+        )
+        .bind(server.id)
+        .bind(guest)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(!member);
+        let (actor, action) = sqlx::query_as::<_, (Option<Uuid>, AuditAction)>(
+            "SELECT actor_id, action FROM audit_log_entries WHERE server_id = $1 AND target_id = $2"
+        )
+        .bind(server.id)
+        .bind(guest)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((actor, action), (Some(guest), AuditAction::MemberLeave));
+        assert!(matches!(
+            owner_events.try_recv(),
+            Ok(WsEvent::MemberLeave { server_id, user_id }) if server_id == server.id && user_id == guest
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn timed_out_members_cannot_edit() {
+        let pool = test_util::database().await;
+        let owner = test_util::user(&pool, "Owner").await;
+        let member = test_util::user(&pool, "Member").await;
+        let server = test_util::server(&pool, owner).await;
+        test_util::join(&pool, server.id, member).await;
+        let state = test_util::app_state(pool.clone());
+        let channel_id = server.text_channel;
+        let message = channels::create_message(&state, member, channel_id, "original".into(), None, Vec::new())
+            .await
+            .unwrap();
+
+        sqlx::query("UPDATE server_members SET timed_out_until = now() + interval '1 hour' WHERE user_id = $1")
+            .bind(member)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let session = Arc::new(Session::new(member, state.config.ws_slow_consumer_policy));
+        let (frames_tx, mut frames) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let (close, _close_rx) = mpsc::channel(1);
+        session.attach(Outbound { frames: frames_tx, close });
+        let edit = ClientEvent::MessageUpdate { channel_id, message_id: message.id, content: "edited".into() };
+        handle_client_event(&state, &session, edit).await;
+        assert_eq!(next_frame(&mut frames).await.0, "error");
+
+        let content = sqlx::query_scalar::<_, String>("SELECT content FROM messages WHERE id = $1")
+            .bind(message.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(content, "original");
+    }
 }
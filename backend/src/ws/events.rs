@@ -1,35 +1,127 @@
-/// WebSocket event types (client↔server protocol).
-///
-/// Uses serde's externally tagged enum for JSON serialization,
-/// producing `{ "type": "message_create", ... }` shape.
+//! WebSocket event types (client↔server protocol).
+//!
+//! Uses serde's externally tagged enum for JSON serialization,
+//! producing `{ "type": "message_create", ... }` shape. Events dispatched
+//! to a session also carry its next sequence number, `{ "seq": 42, ... }`
+//! (see `ws::session`).
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::models::ProfileSummary;
+use crate::error::AppError;
+use crate::models::{
+    Attachment, DmChannelSummary, GatewayServer, MessageReference, MessageWithAuthor, Presence, Profile,
+    ProfileSummary, ReadState, RemovalKind, Thread, UserStatus, VoiceState,
+};
 
 /// Events sent from client → server
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ClientEvent {
     Identify { token: String },
+    /// Pick a dropped session back up instead of identifying: replays
+    /// everything after `seq` (the last sequence number received)
+    Resume { token: String, session_id: Uuid, seq: u64 },
+    /// Keep the connection alive; expected every `heartbeat_interval` ms
+    Heartbeat,
     SubscribeChannel { channel_id: Uuid },
     UnsubscribeChannel { channel_id: Uuid },
     SubscribeDm { dm_channel_id: Uuid },
     UnsubscribeDm { dm_channel_id: Uuid },
-    MessageCreate { channel_id: Uuid, content: String },
-    DmCreate { dm_channel_id: Uuid, content: String },
-    TypingStart { channel_id: Uuid },
-    PresenceUpdate { status: String },
+    MessageCreate {
+        channel_id: Uuid,
+        #[serde(default)]
+        content: String,
+        reply_to: Option<Uuid>,
+        /// Ids of the author's uploads (see `POST /channels/:id/attachments`)
+        #[serde(default)]
+        attachments: Vec<Uuid>,
+    },
+    DmCreate {
+        dm_channel_id: Uuid,
+        #[serde(default)]
+        content: String,
+        #[serde(default)]
+        attachments: Vec<Uuid>,
+    },
+    MessageUpdate { channel_id: Uuid, message_id: Uuid, content: String },
+    DmUpdate { dm_channel_id: Uuid, message_id: Uuid, content: String },
+    MessageDelete { channel_id: Uuid, message_id: Uuid },
+    DmDelete { dm_channel_id: Uuid, message_id: Uuid },
+    /// Start typing in a channel (`channel_id`) or DM (`dm_channel_id`);
+    /// resend every few seconds while the user keeps typing
+    TypingStart {
+        channel_id: Option<Uuid>,
+        dm_channel_id: Option<Uuid>,
+    },
+    /// Stop typing, e.g. after clearing the input
+    TypingStop {
+        channel_id: Option<Uuid>,
+        dm_channel_id: Option<Uuid>,
+    },
+    /// Set this session's status; also saved as the status new sessions
+    /// start with. `offline` isn't accepted, use `invisible`
+    PresenceUpdate { status: UserStatus },
+    /// Join or move to a voice channel, or leave voice with `channel_id:
+    /// null`, with this session's mute / deafen / video / screen share state
+    VoiceStateUpdate {
+        channel_id: Option<Uuid>,
+        #[serde(default)]
+        muted: bool,
+        /// Implies `muted`
+        #[serde(default)]
+        deafened: bool,
+        #[serde(default)]
+        video_on: bool,
+        #[serde(default)]
+        screen_sharing: bool,
+    },
+    /// Mark a channel (`channel_id`) or DM (`dm_channel_id`) read up to `message_id`
+    Ack {
+        channel_id: Option<Uuid>,
+        dm_channel_id: Option<Uuid>,
+        message_id: Uuid,
+    },
 }
 
 /// Events sent from server → client (also stored in broadcast channels)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WsEvent {
-    /// Server confirms successful identification
+    /// First frame on every connection: how often to send `heartbeat`
+    Hello {
+        heartbeat_interval: u64,
+    },
+
+    /// Reply to a client `heartbeat`
+    HeartbeatAck,
+
+    /// Server confirms successful identification and starts a new session
+    /// (also sent when a `resume` can't be honoured), with the initial state
+    /// (see `ws::ready`)
     Ready {
         user_id: Uuid,
+        session_id: Uuid,
+        user: Profile,
+        servers: Vec<GatewayServer>,
+        /// Large servers, each sent as `ServerCreate` after `Ready`
+        lazy_servers: Vec<Uuid>,
+        dms: Vec<DmChannelSummary>,
+        /// Read states of the user's DMs (channel ones are in each server)
+        read_states: Vec<ReadState>,
+        /// Connected DM partners (server members are in each server)
+        presences: Vec<Presence>,
+        voice_state: Option<VoiceState>,
+    },
+
+    /// One of the `lazy_servers` from `Ready`
+    ServerCreate {
+        server: GatewayServer,
+    },
+
+    /// A `resume` succeeded; follows the replayed events
+    Resumed {
+        session_id: Uuid,
     },
 
     /// New message in a text channel
@@ -39,6 +131,11 @@ pub enum WsEvent {
         author: ProfileSummary,
         content: String,
         created_at: String,
+        reply_to: Option<MessageReference>,
+        attachments: Vec<Attachment>,
+        mentions: Vec<ProfileSummary>,
+        mention_roles: Vec<Uuid>,
+        mention_everyone: bool,
     },
 
     /// New direct message
@@ -48,25 +145,111 @@ pub enum WsEvent {
         author: ProfileSummary,
         content: String,
         created_at: String,
+        attachments: Vec<Attachment>,
     },
 
-    /// Someone started typing
-    TypingStart {
+    /// A channel message was edited by its author
+    MessageUpdate {
+        id: Uuid,
         channel_id: Uuid,
+        content: String,
+        edited_at: String,
+    },
+
+    /// A direct message was edited by its author
+    DmUpdate {
+        id: Uuid,
+        dm_channel_id: Uuid,
+        content: String,
+        edited_at: String,
+    },
+
+    /// A channel message was deleted (by its author or a moderator)
+    MessageDelete {
+        id: Uuid,
+        channel_id: Uuid,
+    },
+
+    /// Several channel messages were deleted at once by a moderator
+    MessageDeleteBulk {
+        ids: Vec<Uuid>,
+        channel_id: Uuid,
+    },
+
+    /// A direct message was deleted by its author
+    DmDelete {
+        id: Uuid,
+        dm_channel_id: Uuid,
+    },
+
+    /// A reaction was added to a channel message (`channel_id`) or a DM
+    /// (`dm_channel_id`)
+    ReactionAdd {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<Uuid>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dm_channel_id: Option<Uuid>,
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+    },
+
+    /// A reaction was removed by its author or a moderator
+    ReactionRemove {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<Uuid>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dm_channel_id: Option<Uuid>,
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+    },
+
+    /// A channel message mentioned you (directly, through a role, or with
+    /// `@everyone` / `@here`); sent whether or not you're subscribed
+    MentionCreate {
+        server_id: Uuid,
+        message: MessageWithAuthor,
+    },
+
+    /// Your read position in a channel or DM changed (on any of your devices)
+    ReadStateUpdate {
+        read_state: ReadState,
+    },
+
+    /// Someone started typing in a channel (`channel_id`) or DM
+    /// (`dm_channel_id`); lasts until `TypingStop`
+    TypingStart {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<Uuid>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dm_channel_id: Option<Uuid>,
         user: ProfileSummary,
     },
 
-    /// User presence changed
-    PresenceUpdate {
+    /// Someone stopped typing: they said so, sent a message or went quiet
+    TypingStop {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<Uuid>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dm_channel_id: Option<Uuid>,
         user_id: Uuid,
-        status: String,
     },
 
-    /// Voice channel state changed
+    /// User presence changed (see `ws::presence`)
+    PresenceUpdate {
+        #[serde(flatten)]
+        presence: Presence,
+    },
+
+    /// Someone joined a voice channel, changed their state in it, or left
+    /// it (`voice_state: None`); moving channels is a leave and a join. Sent
+    /// to server members who can see the channel
     VoiceStateUpdate {
         channel_id: Uuid,
+        server_id: Uuid,
         user: ProfileSummary,
-        action: String, // "join" | "leave" | "mute" | "unmute" | "video_on" | "video_off"
+        voice_state: Option<VoiceState>,
     },
 
     /// Server membership events
@@ -79,8 +262,117 @@ pub enum WsEvent {
         user_id: Uuid,
     },
 
-    /// Error message
+    /// A thread was started in a channel (sent to the parent channel)
+    ThreadCreate {
+        thread: Thread,
+    },
+
+    /// A thread was renamed, archived or unarchived (sent to the parent
+    /// channel and the thread)
+    ThreadUpdate {
+        thread: Thread,
+    },
+
+    /// You left, or were kicked or banned; the gateway drops your
+    /// subscriptions to the server's channels
+    ServerRemove {
+        server_id: Uuid,
+        kind: RemovalKind,
+        reason: Option<String>,
+    },
+
+    /// Your timeout in a server was set or lifted (`timed_out_until: None`)
+    MemberTimeout {
+        server_id: Uuid,
+        user_id: Uuid,
+        timed_out_until: Option<String>,
+    },
+
+    /// Events of a subscribed channel (`channel_id`) or DM (`dm_channel_id`)
+    /// were skipped because they arrived faster than they could be
+    /// forwarded; refetch its recent messages
+    SubscriptionLagged {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<Uuid>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dm_channel_id: Option<Uuid>,
+        missed: u64,
+    },
+
+    /// You can no longer view a subscribed channel (a role or overwrite
+    /// changed); its events stop until you subscribe again
+    SubscriptionRevoked { channel_id: Uuid },
+
+    /// Error message (e.g. a rejected client event)
     Error {
+        code: ErrorCode,
         message: String,
     },
 }
+
+/// Machine-readable reason attached to `WsEvent::Error`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The frame could not be parsed as a `ClientEvent`
+    InvalidEvent,
+    /// Identify failed or has not happened yet
+    Unauthorized,
+    /// The user lacks access to the target channel / DM / server
+    Forbidden,
+    NotFound,
+    BadRequest,
+    Internal,
+}
+
+/// Close codes the gateway ends a connection with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u16)]
+pub enum CloseCode {
+    /// No `identify` / `heartbeat` arrived in time; the session can still
+    /// be resumed
+    HeartbeatTimeout = 4000,
+    /// The client fell too far behind on its events; the session can still
+    /// be resumed
+    SlowConsumer = 4001,
+}
+
+impl CloseCode {
+    /// Human-readable reason sent with the code.
+    pub fn reason(self) -> &'static str {
+        match self {
+            CloseCode::HeartbeatTimeout => "Heartbeat timed out",
+            CloseCode::SlowConsumer => "Slow consumer",
+        }
+    }
+}
+
+impl WsEvent {
+    /// Shorthand for building an `Error` event.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        WsEvent::Error { code, message: message.into() }
+    }
+
+    /// Events a slow client can miss without its state going wrong.
+    pub fn is_droppable(&self) -> bool {
+        matches!(self, WsEvent::TypingStart { .. })
+    }
+}
+
+impl From<&AppError> for WsEvent {
+    /// Convert a handler error into a client-facing rejection, hiding
+    /// database details the same way the REST responses do.
+    fn from(err: &AppError) -> Self {
+        match err {
+            AppError::NotFound(msg) => WsEvent::error(ErrorCode::NotFound, msg.clone()),
+            AppError::Unauthorized(msg) => WsEvent::error(ErrorCode::Unauthorized, msg.clone()),
+            AppError::BadRequest(msg) => WsEvent::error(ErrorCode::BadRequest, msg.clone()),
+            AppError::Forbidden(msg) => WsEvent::error(ErrorCode::Forbidden, msg.clone()),
+            AppError::Internal(msg) => WsEvent::error(ErrorCode::Internal, msg.clone()),
+            AppError::Sqlx(e) => {
+                tracing::error!("Database error: {e:?}");
+                WsEvent::error(ErrorCode::Internal, "Internal database error")
+            }
+        }
+    }
+}
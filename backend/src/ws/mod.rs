@@ -1,19 +1,43 @@
+pub mod bus;
 pub mod events;
 pub mod connection;
+pub mod presence;
+pub mod ready;
 pub mod router;
+pub mod session;
+pub mod typing;
 
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
 use tokio::sync::{broadcast, mpsc};
 use uuid::Uuid;
 
+use self::bus::{BusMessage, BusPayload, EventBus};
 use self::events::WsEvent;
+use self::presence::Device;
+use self::session::Session;
+use self::typing::Typing;
+use crate::models::UserStatus;
+
+/// Events queued per user session before the slow consumer policy applies.
+const USER_QUEUE_SIZE: usize = 256;
 
 /// Shared WebSocket state for real-time event routing.
 ///
-/// - `channel_senders`: per-channel broadcast senders for fan-out
-/// - `user_connections`: per-user list of mpsc senders (supports multi-device)
+/// - `channel_senders`: per-channel broadcast senders for fan-out, only while
+///   someone is subscribed
+/// - `user_connections`: per-user list of bounded mpsc senders, one per session
+///   (supports multi-device), along with the session
+/// - `sessions`: gateway sessions by id, kept through the resume window
+/// - `devices`: per-user status of each of their sessions (see `ws::presence`)
+/// - `typing`: typing indicators by user and channel / DM (see `ws::typing`)
+/// - `remote_nodes`: the other backend nodes on the event bus and the users
+///   connected to them (see `ws::bus`)
+/// - `permission_epochs`: per-server count of role / overwrite changes, so
+///   channel subscriptions know when to re-check access
 #[derive(Clone)]
 pub struct WsState {
     inner: Arc<WsStateInner>,
@@ -21,21 +45,72 @@ pub struct WsState {
 
 struct WsStateInner {
     pub channel_senders: DashMap<Uuid, broadcast::Sender<WsEvent>>,
-    pub user_connections: DashMap<Uuid, Vec<mpsc::UnboundedSender<WsEvent>>>,
+    pub user_connections: DashMap<Uuid, Vec<UserConnection>>,
+    pub sessions: DashMap<Uuid, Arc<Session>>,
+    pub devices: DashMap<Uuid, HashMap<Uuid, Device>>,
+    pub typing: DashMap<(Uuid, Uuid), Typing>,
+    pub node_id: Uuid,
+    pub bus: Arc<dyn EventBus>,
+    pub remote_nodes: DashMap<Uuid, RemoteNode>,
+    pub permission_epochs: DashMap<Uuid, u64>,
+}
+
+/// A session's queue of the events sent to its user.
+struct UserConnection {
+    tx: mpsc::Sender<WsEvent>,
+    session: Arc<Session>,
+}
+
+/// Another node, as last heard from over the event bus.
+struct RemoteNode {
+    /// Combined status of each user's sessions there
+    users: HashMap<Uuid, UserStatus>,
+    last_seen: Instant,
 }
 
 impl WsState {
-    pub fn new() -> Self {
+    pub fn new(bus: Arc<dyn EventBus>) -> Self {
         Self {
             inner: Arc::new(WsStateInner {
                 channel_senders: DashMap::new(),
                 user_connections: DashMap::new(),
+                sessions: DashMap::new(),
+                devices: DashMap::new(),
+                typing: DashMap::new(),
+                node_id: Uuid::new_v4(),
+                bus,
+                remote_nodes: DashMap::new(),
+                permission_epochs: DashMap::new(),
             }),
         }
     }
 
-    /// Get or create a broadcast sender for a channel.
-    pub fn get_or_create_channel(&self, channel_id: Uuid) -> broadcast::Sender<WsEvent> {
+    /// Start receiving other nodes' events from the bus.
+    pub fn start_bus(&self) {
+        self.inner.bus.start(self.clone());
+    }
+
+    /// This node's id on the event bus.
+    pub fn node_id(&self) -> Uuid {
+        self.inner.node_id
+    }
+
+    /// This node and every other one heard from recently.
+    pub fn live_nodes(&self) -> Vec<Uuid> {
+        std::iter::once(self.inner.node_id)
+            .chain(self.inner.remote_nodes.iter().map(|node| *node.key()))
+            .collect()
+    }
+
+    fn publish(&self, payload: BusPayload) {
+        self.inner.bus.publish(BusMessage { origin: self.inner.node_id, payload });
+    }
+
+    /// Subscribe to a channel's broadcasts, creating its sender if needed.
+    ///
+    /// Subscribing happens under the map entry's lock, so it can't race
+    /// `release_channel` into holding a sender that's no longer in the map.
+    pub fn subscribe_channel(&self, channel_id: Uuid) -> broadcast::Receiver<WsEvent> {
         self.inner
             .channel_senders
             .entry(channel_id)
@@ -43,64 +118,254 @@ impl WsState {
                 let (tx, _) = broadcast::channel(256);
                 tx
             })
-            .clone()
+            .subscribe()
     }
 
-    /// Register a user connection (returns an mpsc receiver for outbound events).
-    pub fn register_user(&self, user_id: Uuid) -> mpsc::UnboundedReceiver<WsEvent> {
-        let (tx, rx) = mpsc::unbounded_channel();
+    /// Drop a channel's broadcast sender once nobody is subscribed to it.
+    pub fn release_channel(&self, channel_id: &Uuid) {
+        self.inner
+            .channel_senders
+            .remove_if(channel_id, |_, tx| tx.receiver_count() == 0);
+    }
+
+    /// Register a session's user connection (returns an mpsc receiver for
+    /// outbound events).
+    pub fn register_user(&self, session: &Arc<Session>) -> mpsc::Receiver<WsEvent> {
+        let (tx, rx) = mpsc::channel(USER_QUEUE_SIZE);
         self.inner
             .user_connections
-            .entry(user_id)
+            .entry(session.user_id)
             .or_default()
-            .push(tx);
+            .push(UserConnection { tx, session: session.clone() });
         rx
     }
 
     /// Remove a user's connection sender.
-    pub fn unregister_user(&self, user_id: Uuid, tx: &mpsc::UnboundedSender<WsEvent>) {
-        if let Some(mut senders) = self.inner.user_connections.get_mut(&user_id) {
-            senders.retain(|s| !s.same_channel(tx));
-            if senders.is_empty() {
-                drop(senders);
-                self.inner.user_connections.remove(&user_id);
-            }
-        }
+    pub fn unregister_user(&self, user_id: Uuid, tx: &mpsc::Sender<WsEvent>) {
+        self.remove_senders(user_id, |s| s.same_channel(tx));
     }
 
-    /// Send an event to a specific user (all their connected devices).
+    /// Send an event to a specific user (all their connected devices, on
+    /// every node).
     pub fn send_to_user(&self, user_id: &Uuid, event: &WsEvent) {
-        if let Some(senders) = self.inner.user_connections.get(user_id) {
-            for tx in senders.iter() {
-                let _ = tx.send(event.clone());
+        self.deliver_to_user(user_id, event);
+        // Only nodes the user is connected to care
+        if self.connected_remotely(user_id) {
+            self.publish(BusPayload::User { user_id: *user_id, event: event.clone() });
+        }
+    }
+
+    fn deliver_to_user(&self, user_id: &Uuid, event: &WsEvent) {
+        if let Some(connections) = self.inner.user_connections.get(user_id) {
+            for connection in connections.iter() {
+                if let Err(mpsc::error::TrySendError::Full(_)) = connection.tx.try_send(event.clone()) {
+                    connection.session.dropped(event);
+                }
             }
         }
     }
 
-    /// Broadcast an event to all subscribers of a channel.
+    /// Broadcast an event to all subscribers of a channel, on every node.
     pub fn broadcast_to_channel(&self, channel_id: &Uuid, event: WsEvent) {
+        self.deliver_to_channel(channel_id, event.clone());
+        self.publish(BusPayload::Channel { channel_id: *channel_id, event });
+    }
+
+    fn deliver_to_channel(&self, channel_id: &Uuid, event: WsEvent) {
         if let Some(tx) = self.inner.channel_senders.get(channel_id) {
             let _ = tx.send(event);
         }
     }
 
+    /// Note that roles or channel overwrites changed in a server, on every
+    /// node, so subscriptions to its channels re-check the subscriber's
+    /// access before forwarding anything else.
+    pub fn permissions_changed(&self, server_id: Uuid) {
+        self.bump_permissions_epoch(server_id);
+        self.publish(BusPayload::Permissions { server_id });
+    }
+
+    fn bump_permissions_epoch(&self, server_id: Uuid) {
+        *self.inner.permission_epochs.entry(server_id).or_default() += 1;
+    }
+
+    /// How many times permissions in a server have changed, as far as this
+    /// node knows.
+    pub fn permissions_epoch(&self, server_id: &Uuid) -> u64 {
+        self.inner.permission_epochs.get(server_id).map(|epoch| *epoch).unwrap_or(0)
+    }
+
     /// Remove closed/dropped senders for a user (used on disconnect cleanup).
     pub fn cleanup_closed_senders(&self, user_id: Uuid) {
-        if let Some(mut senders) = self.inner.user_connections.get_mut(&user_id) {
-            senders.retain(|s| !s.is_closed());
-            if senders.is_empty() {
-                drop(senders);
-                self.inner.user_connections.remove(&user_id);
-            }
-        }
+        self.remove_senders(user_id, |s| s.is_closed());
     }
 
-    /// Check if a user still has any active connections.
+    fn remove_senders(&self, user_id: Uuid, remove: impl Fn(&mpsc::Sender<WsEvent>) -> bool) {
+        self.inner.user_connections.remove_if_mut(&user_id, |_, connections| {
+            connections.retain(|c| !remove(&c.tx));
+            connections.is_empty()
+        });
+    }
+
+    /// Check if a user still has any active connections, on any node.
     pub fn user_is_connected(&self, user_id: &Uuid) -> bool {
         self.inner
             .user_connections
             .get(user_id)
             .map(|s| !s.is_empty())
             .unwrap_or(false)
+            || self.connected_remotely(user_id)
+    }
+
+    fn connected_remotely(&self, user_id: &Uuid) -> bool {
+        self.inner.remote_nodes.iter().any(|node| node.users.contains_key(user_id))
+    }
+
+    /// Handle a message from another node.
+    pub fn receive(&self, message: BusMessage) {
+        if message.origin == self.inner.node_id {
+            return;
+        }
+        match message.payload {
+            BusPayload::Channel { channel_id, event } => self.deliver_to_channel(&channel_id, event),
+            BusPayload::User { user_id, event } => self.deliver_to_user(&user_id, &event),
+            BusPayload::Presence { user_id, status } => {
+                let mut node = self.remote_node(message.origin);
+                match status {
+                    Some(status) => node.users.insert(user_id, status),
+                    None => node.users.remove(&user_id),
+                };
+            }
+            BusPayload::Node { users } => self.remote_node(message.origin).users = users,
+            BusPayload::Permissions { server_id } => self.bump_permissions_epoch(server_id),
+        }
+    }
+
+    /// The node with `node_id`, marked as just heard from.
+    fn remote_node(&self, node_id: Uuid) -> dashmap::mapref::one::RefMut<'_, Uuid, RemoteNode> {
+        let mut node = self.inner.remote_nodes.entry(node_id).or_insert_with(|| RemoteNode {
+            users: HashMap::new(),
+            last_seen: Instant::now(),
+        });
+        node.last_seen = Instant::now();
+        node
+    }
+
+    /// Tell other nodes this one is alive, and who's connected to it.
+    pub fn publish_node(&self) {
+        self.publish(BusPayload::Node { users: self.local_statuses() });
+    }
+
+    /// Forget nodes not heard from within `timeout`. Returns the users who
+    /// were connected to them and whose presence changed.
+    pub fn prune_nodes(&self, timeout: Duration) -> Vec<Uuid> {
+        let stale: Vec<Uuid> = self
+            .inner
+            .remote_nodes
+            .iter()
+            .filter(|node| node.last_seen.elapsed() >= timeout)
+            .map(|node| *node.key())
+            .collect();
+
+        let mut affected: Vec<Uuid> = stale
+            .iter()
+            .filter_map(|node_id| self.inner.remote_nodes.get(node_id))
+            .flat_map(|node| node.users.keys().copied().collect::<Vec<_>>())
+            .collect();
+        affected.sort_unstable();
+        affected.dedup();
+        let before: Vec<UserStatus> = affected.iter().map(|id| self.presence_status(id)).collect();
+
+        for node_id in stale {
+            tracing::warn!("Gateway node {node_id} timed out");
+            self.inner.remote_nodes.remove(&node_id);
+        }
+        affected
+            .into_iter()
+            .zip(before)
+            .filter(|(user_id, before)| self.presence_status(user_id) != *before)
+            .map(|(user_id, _)| user_id)
+            .collect()
+    }
+
+    /// True if this node has the lowest id of those alive, so that cluster
+    /// wide chores happen once.
+    pub fn is_lowest_node(&self) -> bool {
+        self.inner.remote_nodes.iter().all(|node| *node.key() > self.inner.node_id)
+    }
+
+    /// Keep a session so it can be resumed.
+    pub fn insert_session(&self, session: Arc<Session>) {
+        self.inner.sessions.insert(session.id, session);
+    }
+
+    /// Look up a live session by id.
+    pub fn session(&self, session_id: &Uuid) -> Option<Arc<Session>> {
+        self.inner.sessions.get(session_id).map(|s| s.clone())
+    }
+
+    /// Forget a session; it can no longer be resumed.
+    pub fn remove_session(&self, session_id: &Uuid) {
+        self.inner.sessions.remove(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::error::TryRecvError;
+
+    use super::*;
+    use crate::config::SlowConsumerPolicy;
+    use crate::models::ProfileSummary;
+    use crate::ws::bus::LocalBus;
+    use crate::ws::events::CloseCode;
+    use crate::ws::session::{Outbound, OUTBOUND_QUEUE_SIZE};
+
+    /// A session with an attached socket whose user event queue is full,
+    /// with the receiving ends of the socket's queues.
+    fn full_session(
+        ws_state: &WsState,
+        policy: SlowConsumerPolicy,
+    ) -> (Arc<Session>, mpsc::Receiver<WsEvent>, mpsc::Receiver<CloseCode>) {
+        let session = Arc::new(Session::new(Uuid::new_v4(), policy));
+        let (frames, _frames_rx) = mpsc::channel(1);
+        let (close, close_rx) = mpsc::channel(1);
+        session.attach(Outbound { frames, close });
+
+        let user_rx = ws_state.register_user(&session);
+        for _ in 0..USER_QUEUE_SIZE {
+            ws_state.send_to_user(&session.user_id, &WsEvent::HeartbeatAck);
+        }
+        (session, user_rx, close_rx)
+    }
+
+    fn typing(user_id: Uuid) -> WsEvent {
+        WsEvent::TypingStart {
+            channel_id: Some(Uuid::new_v4()),
+            dm_channel_id: None,
+            user: ProfileSummary { id: user_id, username: None, display_name: "Typist".into(), avatar_url: None },
+        }
+    }
+
+    #[test]
+    fn full_user_queues_apply_the_slow_consumer_policy() {
+        let ws_state = WsState::new(Arc::new(LocalBus));
+
+        // Typing is dropped and the socket stays open
+        let (session, _user_rx, mut close_rx) = full_session(&ws_state, SlowConsumerPolicy::DropNonCritical);
+        ws_state.send_to_user(&session.user_id, &typing(session.user_id));
+        assert_eq!(close_rx.try_recv(), Err(TryRecvError::Empty));
+
+        // Anything else closes it, and the session can't be resumed
+        ws_state.send_to_user(&session.user_id, &WsEvent::HeartbeatAck);
+        assert_eq!(close_rx.try_recv(), Ok(CloseCode::SlowConsumer));
+        let (frames, _) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let (close, _) = mpsc::channel(1);
+        assert_eq!(session.resume(Outbound { frames, close }, 0), None);
+
+        // With the disconnect policy, even typing closes it
+        let (session, _user_rx, mut close_rx) = full_session(&ws_state, SlowConsumerPolicy::Disconnect);
+        ws_state.send_to_user(&session.user_id, &typing(session.user_id));
+        assert_eq!(close_rx.try_recv(), Ok(CloseCode::SlowConsumer));
     }
 }
@@ -0,0 +1,351 @@
+//! Presence: the status each user shows to others.
+//!
+//! Every session (device) has its own status, starting from the one the user
+//! last picked (`profiles.status`) until it sends `presence_update`. A device
+//! that sends nothing but heartbeats for `presence_idle_timeout_secs` shows
+//! as idle until it's used again. A user's presence is the most available
+//! status across their devices on every node: online beats idle beats dnd.
+//! Invisible devices count for nothing, so a user whose devices are all
+//! invisible appears offline.
+//!
+//! Each node shares its users' combined status with the others over the
+//! event bus (see `ws::bus`).
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::bus::BusPayload;
+use super::events::WsEvent;
+use super::WsState;
+use crate::AppState;
+use crate::error::AppResult;
+use crate::models::{CustomStatus, Presence, UserStatus};
+
+/// One of a user's sessions on this node.
+pub(super) struct Device {
+    status: UserStatus,
+    idle: bool,
+    last_active: Instant,
+}
+
+impl Device {
+    fn status(&self) -> UserStatus {
+        match self.status {
+            UserStatus::Online if self.idle => UserStatus::Idle,
+            status => status,
+        }
+    }
+}
+
+/// How available a status is; the highest of a user's devices wins.
+fn rank(status: UserStatus) -> u8 {
+    match status {
+        UserStatus::Online => 3,
+        UserStatus::Idle => 2,
+        UserStatus::Dnd => 1,
+        UserStatus::Invisible | UserStatus::Offline => 0,
+    }
+}
+
+/// The status shown for a user whose devices have `statuses`.
+fn combine(statuses: impl IntoIterator<Item = UserStatus>) -> Option<UserStatus> {
+    statuses.into_iter().max_by_key(|status| rank(*status))
+}
+
+impl WsState {
+    /// Add a session with `status`. Returns true if the user's presence
+    /// changed.
+    pub fn add_device(&self, user_id: Uuid, session_id: Uuid, status: UserStatus) -> bool {
+        self.update_devices(user_id, |devices| {
+            devices.insert(session_id, Device { status, idle: false, last_active: Instant::now() });
+        })
+    }
+
+    /// Remove an ended session. Returns true if the user's presence changed.
+    pub fn remove_device(&self, user_id: Uuid, session_id: Uuid) -> bool {
+        self.update_devices(user_id, |devices| {
+            devices.remove(&session_id);
+        })
+    }
+
+    /// Set the status a session picked. Returns true if the user's presence
+    /// changed.
+    pub fn set_device_status(&self, user_id: Uuid, session_id: Uuid, status: UserStatus) -> bool {
+        self.update_devices(user_id, |devices| {
+            if let Some(device) = devices.get_mut(&session_id) {
+                device.status = status;
+            }
+        })
+    }
+
+    /// Note activity on a session. Returns true if it was idle and the
+    /// user's presence changed.
+    pub fn device_active(&self, user_id: Uuid, session_id: Uuid) -> bool {
+        let was_idle = {
+            let Some(mut devices) = self.inner.devices.get_mut(&user_id) else {
+                return false;
+            };
+            let Some(device) = devices.get_mut(&session_id) else {
+                return false;
+            };
+            device.last_active = Instant::now();
+            device.idle
+        };
+        was_idle
+            && self.update_devices(user_id, |devices| {
+                if let Some(device) = devices.get_mut(&session_id) {
+                    device.idle = false;
+                }
+            })
+    }
+
+    /// When the session goes idle if nothing happens before then, or `None`
+    /// once it has ended.
+    fn idle_at(&self, user_id: Uuid, session_id: Uuid, timeout: Duration) -> Option<Instant> {
+        let devices = self.inner.devices.get(&user_id)?;
+        let device = devices.get(&session_id)?;
+        Some(device.last_active + timeout)
+    }
+
+    /// Mark the session idle if it's been inactive for `timeout`. Returns
+    /// true if the user's presence changed.
+    fn mark_idle(&self, user_id: Uuid, session_id: Uuid, timeout: Duration) -> bool {
+        self.update_devices(user_id, |devices| {
+            if let Some(device) = devices.get_mut(&session_id) {
+                device.idle = device.last_active.elapsed() >= timeout;
+            }
+        })
+    }
+
+    /// Apply `update` to the user's sessions on this node, telling other
+    /// nodes if their combined status changed. Returns true if the user's
+    /// presence changed.
+    fn update_devices(&self, user_id: Uuid, update: impl FnOnce(&mut HashMap<Uuid, Device>)) -> bool {
+        let before = self.presence_status(&user_id);
+        let (old, new) = {
+            let mut devices = self.inner.devices.entry(user_id).or_default();
+            let old = combine(devices.values().map(Device::status));
+            update(&mut devices);
+            (old, combine(devices.values().map(Device::status)))
+        };
+        self.inner.devices.remove_if(&user_id, |_, devices| devices.is_empty());
+
+        if old != new {
+            self.publish(BusPayload::Presence { user_id, status: new });
+        }
+        self.presence_status(&user_id) != before
+    }
+
+    /// The combined status of the user's sessions on this node, if any.
+    fn local_status(&self, user_id: &Uuid) -> Option<UserStatus> {
+        let devices = self.inner.devices.get(user_id)?;
+        combine(devices.values().map(Device::status))
+    }
+
+    /// Every user with a session on this node, with their combined status.
+    pub(super) fn local_statuses(&self) -> HashMap<Uuid, UserStatus> {
+        self.inner
+            .devices
+            .iter()
+            .filter_map(|entry| Some((*entry.key(), combine(entry.values().map(Device::status))?)))
+            .collect()
+    }
+
+    /// The status others see for the user, across every node.
+    pub fn presence_status(&self, user_id: &Uuid) -> UserStatus {
+        let remote: Vec<UserStatus> = self
+            .inner
+            .remote_nodes
+            .iter()
+            .filter_map(|node| node.users.get(user_id).copied())
+            .collect();
+        match combine(self.local_status(user_id).into_iter().chain(remote)) {
+            None | Some(UserStatus::Invisible) => UserStatus::Offline,
+            Some(status) => status,
+        }
+    }
+}
+
+/// The status a new session starts with: the one the user last picked.
+pub async fn saved_status(pool: &PgPool, user_id: Uuid) -> UserStatus {
+    let status = sqlx::query_scalar::<_, UserStatus>("SELECT status FROM profiles WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await;
+    match status {
+        // Never picked one
+        Ok(Some(UserStatus::Offline)) | Ok(None) => UserStatus::Online,
+        Ok(Some(status)) => status,
+        Err(e) => {
+            tracing::error!("Failed to load status of user {user_id}: {e}");
+            UserStatus::Online
+        }
+    }
+}
+
+/// Presences of whichever of `user_ids` appear online; everyone else is
+/// offline.
+pub async fn presences(state: &AppState, user_ids: &[Uuid]) -> AppResult<Vec<Presence>> {
+    let statuses: HashMap<Uuid, UserStatus> = user_ids
+        .iter()
+        .map(|id| (*id, state.ws_state.presence_status(id)))
+        .filter(|(_, status)| *status != UserStatus::Offline)
+        .collect();
+    if statuses.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<Uuid> = statuses.keys().copied().collect();
+    let mut custom_statuses = custom_statuses(&state.pool, &ids).await?;
+
+    Ok(statuses
+        .into_iter()
+        .map(|(user_id, status)| Presence { user_id, status, custom_status: custom_statuses.remove(&user_id) })
+        .collect())
+}
+
+/// The user's presence, as others see it.
+pub async fn presence(state: &AppState, user_id: Uuid) -> AppResult<Presence> {
+    Ok(presences(state, &[user_id])
+        .await?
+        .pop()
+        .unwrap_or(Presence { user_id, status: UserStatus::Offline, custom_status: None }))
+}
+
+/// Unexpired custom statuses of `user_ids`, for those who have one.
+async fn custom_statuses(pool: &PgPool, user_ids: &[Uuid]) -> AppResult<HashMap<Uuid, CustomStatus>> {
+    #[derive(sqlx::FromRow)]
+    struct Row {
+        id: Uuid,
+        #[sqlx(flatten)]
+        custom_status: CustomStatus,
+    }
+
+    let rows = sqlx::query_as::<_, Row>(
+        r#"
+        SELECT id, custom_status_text, custom_status_emoji, custom_status_expires_at
+        FROM profiles
+        WHERE id = ANY($1)
+          AND (custom_status_text IS NOT NULL OR custom_status_emoji IS NOT NULL)
+          AND (custom_status_expires_at IS NULL OR custom_status_expires_at > now())
+        "#,
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.id, row.custom_status)).collect())
+}
+
+/// Send the user's current presence to everyone who shares a server or DM
+/// with them.
+pub async fn broadcast(state: &AppState, user_id: Uuid) {
+    let presence = match presence(state, user_id).await {
+        Ok(presence) => presence,
+        Err(e) => {
+            tracing::error!("Failed to load presence of user {user_id}: {e}");
+            return;
+        }
+    };
+    let event = WsEvent::PresenceUpdate { presence };
+
+    let peer_ids = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT sm2.user_id
+        FROM server_members sm1
+        JOIN server_members sm2 ON sm1.server_id = sm2.server_id
+        WHERE sm1.user_id = $1 AND sm2.user_id != $1
+        UNION
+        SELECT dm2.user_id
+        FROM dm_members dm1
+        JOIN dm_members dm2 ON dm1.dm_channel_id = dm2.dm_channel_id
+        WHERE dm1.user_id = $1 AND dm2.user_id != $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await;
+
+    match peer_ids {
+        Ok(peer_ids) => {
+            for peer_id in peer_ids {
+                state.ws_state.send_to_user(&peer_id, &event);
+            }
+        }
+        Err(e) => tracing::error!("Failed to load presence peers of user {user_id}: {e}"),
+    }
+}
+
+/// Mark the session idle whenever it goes `presence_idle_timeout_secs`
+/// without activity. Runs for the life of the session.
+pub async fn watch_idle(state: AppState, user_id: Uuid, session_id: Uuid) {
+    let timeout = Duration::from_secs(state.config.presence_idle_timeout_secs);
+    while let Some(idle_at) = state.ws_state.idle_at(user_id, session_id, timeout) {
+        // Already idle: activity from here on moves `idle_at` no earlier
+        // than a full timeout away
+        let now = Instant::now();
+        let wake = if idle_at > now { idle_at } else { now + timeout };
+        tokio::time::sleep_until(wake.into()).await;
+
+        if state.ws_state.mark_idle(user_id, session_id, timeout) {
+            broadcast(&state, user_id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ws::bus::LocalBus;
+
+    use UserStatus::{Dnd, Idle, Invisible, Offline, Online};
+
+    #[test]
+    fn most_available_status_wins() {
+        assert_eq!(combine([Dnd, Idle, Online]), Some(Online));
+        assert_eq!(combine([Online, Dnd]), Some(Online));
+        assert_eq!(combine([Dnd, Idle]), Some(Idle));
+        assert_eq!(combine([Invisible, Dnd]), Some(Dnd));
+        assert_eq!(combine([]), None);
+    }
+
+    #[test]
+    fn presence_combines_the_users_devices() {
+        let ws_state = WsState::new(Arc::new(LocalBus));
+        let user_id = Uuid::new_v4();
+        let (phone, laptop, tablet) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(ws_state.presence_status(&user_id), Offline);
+
+        assert!(ws_state.add_device(user_id, phone, Dnd));
+        assert!(ws_state.add_device(user_id, laptop, Online));
+        assert_eq!(ws_state.presence_status(&user_id), Online);
+
+        // An idle device still beats dnd
+        assert!(ws_state.mark_idle(user_id, laptop, Duration::ZERO));
+        assert_eq!(ws_state.presence_status(&user_id), Idle);
+        assert!(ws_state.add_device(user_id, tablet, Online));
+        assert_eq!(ws_state.presence_status(&user_id), Online);
+        assert!(ws_state.remove_device(user_id, tablet));
+        assert!(ws_state.remove_device(user_id, laptop));
+        assert_eq!(ws_state.presence_status(&user_id), Dnd);
+    }
+
+    #[test]
+    fn users_with_only_invisible_devices_appear_offline() {
+        let ws_state = WsState::new(Arc::new(LocalBus));
+        let user_id = Uuid::new_v4();
+        let (phone, laptop) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(!ws_state.add_device(user_id, phone, Invisible));
+        assert!(!ws_state.add_device(user_id, laptop, Invisible));
+        assert_eq!(ws_state.presence_status(&user_id), Offline);
+
+        assert!(ws_state.set_device_status(user_id, laptop, Dnd));
+        assert_eq!(ws_state.presence_status(&user_id), Dnd);
+    }
+}
@@ -0,0 +1,134 @@
+//! The gateway's `Ready` payload and the `ServerCreate` events that follow it.
+//!
+//! `Ready` carries what a client needs right after connecting: the user's
+//! profile, their servers with channels and read states, DMs, presences and
+//! their current voice state. Servers with more than `LARGE_SERVER_MEMBERS`
+//! members are only listed by id (`lazy_servers`); each one follows as its
+//! own `ServerCreate`, so `Ready` stays small for users in big servers.
+
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::error::{AppError, AppResult};
+use crate::handlers::{channels, dms, read_states};
+use crate::models::{GatewayServer, Profile, Server, VoiceState};
+use crate::permissions;
+use crate::ws::events::WsEvent;
+use crate::ws::presence::presences;
+use crate::ws::session::Session;
+
+/// Servers with more members than this are streamed after `Ready`.
+pub const LARGE_SERVER_MEMBERS: i64 = 100;
+
+const SERVER_SELECT: &str = r#"
+    SELECT s.*, (SELECT count(*) FROM server_members m WHERE m.server_id = s.id) as member_count
+    FROM servers s
+    INNER JOIN server_members sm ON sm.server_id = s.id AND sm.user_id = $1
+"#;
+
+#[derive(sqlx::FromRow)]
+struct ServerRow {
+    #[sqlx(flatten)]
+    server: Server,
+    member_count: i64,
+}
+
+/// Build `Ready` for a new session, along with the large servers to stream
+/// afterwards with `stream_servers`.
+pub async fn build(state: &AppState, user_id: Uuid, session_id: Uuid) -> AppResult<(WsEvent, Vec<Uuid>)> {
+    let user = sqlx::query_as::<_, Profile>("SELECT * FROM profiles WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Profile not found".into()))?;
+
+    let rows = sqlx::query_as::<_, ServerRow>(&format!("{SERVER_SELECT} ORDER BY s.created_at"))
+        .bind(user_id)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let mut servers = Vec::new();
+    let mut lazy_servers = Vec::new();
+    for row in rows {
+        if row.member_count > LARGE_SERVER_MEMBERS {
+            lazy_servers.push(row.server.id);
+        } else {
+            servers.push(gateway_server(state, user_id, row).await?);
+        }
+    }
+
+    let dms = dms::dm_summaries(&state.pool, user_id).await?;
+    let read_states = read_states::dm_read_states(&state.pool, user_id, None)
+        .await?
+        .into_values()
+        .collect();
+    let partner_ids: Vec<Uuid> = dms.iter().map(|dm| dm.other_user.id).collect();
+    let presences = presences(state, &partner_ids).await?;
+
+    let voice_state = sqlx::query_as::<_, VoiceState>(
+        "SELECT * FROM voice_states WHERE user_id = $1 ORDER BY joined_at DESC LIMIT 1"
+    )
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?;
+
+    let ready = WsEvent::Ready {
+        user_id,
+        session_id,
+        user,
+        servers,
+        lazy_servers: lazy_servers.clone(),
+        dms,
+        read_states,
+        presences,
+        voice_state,
+    };
+    Ok((ready, lazy_servers))
+}
+
+/// Dispatch a `ServerCreate` for each of `server_ids` to the session, once
+/// `Ready` has gone out.
+pub async fn stream_servers(state: AppState, session: Arc<Session>, server_ids: Vec<Uuid>) {
+    for server_id in server_ids {
+        let row = sqlx::query_as::<_, ServerRow>(&format!("{SERVER_SELECT} WHERE s.id = $2"))
+            .bind(session.user_id)
+            .bind(server_id)
+            .fetch_optional(&state.pool)
+            .await;
+
+        let server = match row {
+            // Left the server since `Ready`
+            Ok(None) => continue,
+            Ok(Some(row)) => gateway_server(&state, session.user_id, row).await,
+            Err(e) => Err(e.into()),
+        };
+        match server {
+            Ok(server) => session.dispatch(&WsEvent::ServerCreate { server }),
+            Err(e) => tracing::error!("Failed to load server {server_id} for session {}: {e}", session.id),
+        }
+    }
+}
+
+/// The user's view of one of their servers.
+async fn gateway_server(state: &AppState, user_id: Uuid, row: ServerRow) -> AppResult<GatewayServer> {
+    let server_id = row.server.id;
+    let perms = permissions::server_permissions(&state.pool, server_id, user_id).await?;
+    let channels = channels::visible_channels(&state.pool, server_id, user_id, &perms).await?;
+    let read_states = read_states::server_read_states(&state.pool, server_id, user_id, &perms).await?;
+
+    let member_ids = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM server_members WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_all(&state.pool)
+        .await?;
+    let presences = presences(state, &member_ids).await?;
+
+    Ok(GatewayServer {
+        server: row.server,
+        member_count: row.member_count,
+        channels,
+        read_states,
+        presences,
+    })
+}
@@ -1,7 +1,7 @@
-/// WebSocket upgrade route handler.
-///
-/// Accepts WS upgrade requests at `/api/v1/ws` and hands them off
-/// to the per-connection handler.
+//! WebSocket upgrade route handler.
+//!
+//! Accepts WS upgrade requests at `/api/v1/ws` and hands them off
+//! to the per-connection handler.
 
 use axum::extract::{State, WebSocketUpgrade};
 use axum::response::Response;
@@ -0,0 +1,281 @@
+//! Gateway sessions: sequence numbers, the replay buffer and resuming.
+//!
+//! A session starts at `identify` and outlives its socket. Every event sent
+//! to it gets the next sequence number and is kept in a bounded buffer, and
+//! its channel / DM subscriptions keep running while the client is away. A
+//! client that reconnects within `RESUME_WINDOW` sends `resume` with the last
+//! `seq` it saw and gets everything after it replayed; if that's no longer
+//! in the buffer it gets a fresh session and `Ready` instead.
+//!
+//! Each socket's outbound queue is bounded. When a client can't keep up, the
+//! configured `SlowConsumerPolicy` decides between dropping non-critical
+//! events and closing the socket with `CloseCode::SlowConsumer`; either way
+//! the session keeps buffering, so the client can resume. The same policy
+//! applies when the queue of events sent to the user (not via a
+//! subscription) fills up, but a critical event lost there can't be
+//! replayed, so that session can't be resumed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use super::events::{CloseCode, WsEvent};
+use crate::config::SlowConsumerPolicy;
+
+/// Sequenced events kept per session for replay.
+pub const REPLAY_BUFFER_SIZE: usize = 512;
+
+/// Frames queued per socket before the slow consumer policy applies. Room
+/// for a full replay, so resuming never trips it.
+pub const OUTBOUND_QUEUE_SIZE: usize = 2 * REPLAY_BUFFER_SIZE;
+
+/// How long a session survives without a socket before it's torn down.
+pub const RESUME_WINDOW: Duration = Duration::from_secs(120);
+
+/// An outbound frame: the event plus its sequence number.
+#[derive(Serialize)]
+struct Frame<'a> {
+    seq: u64,
+    #[serde(flatten)]
+    event: &'a WsEvent,
+}
+
+/// What a subscription forwards: a server channel or a DM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subscription {
+    Channel(Uuid),
+    Dm(Uuid),
+}
+
+impl Subscription {
+    /// The channel or DM id, which is also its broadcast sender's key.
+    pub fn id(self) -> Uuid {
+        match self {
+            Subscription::Channel(id) | Subscription::Dm(id) => id,
+        }
+    }
+}
+
+/// The queues of the socket a session is attached to.
+pub struct Outbound {
+    /// Serialized frames, written out in order
+    pub frames: mpsc::Sender<String>,
+    /// Asks the writer to close the socket with a code
+    pub close: mpsc::Sender<CloseCode>,
+}
+
+/// One identified client, across reconnects.
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    policy: SlowConsumerPolicy,
+    inner: Mutex<SessionInner>,
+}
+
+struct SessionInner {
+    /// Sequence number of the last event dispatched
+    seq: u64,
+    buffer: VecDeque<(u64, String)>,
+    /// Highest sequence number pushed out of `buffer`
+    evicted_seq: u64,
+    /// The attached socket's queues, if any
+    outbound: Option<Outbound>,
+    /// Bumped on every attach, so a replaced socket can tell it's stale
+    attach_id: u64,
+    subscriptions: HashMap<Subscription, JoinHandle<()>>,
+    /// Tasks feeding the session that aren't subscriptions
+    tasks: Vec<JoinHandle<()>>,
+    /// Set once an event was dropped before it could be dispatched, so
+    /// replaying the buffer would miss it
+    lost_events: bool,
+}
+
+impl Session {
+    pub fn new(user_id: Uuid, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            policy,
+            inner: Mutex::new(SessionInner {
+                seq: 0,
+                buffer: VecDeque::new(),
+                evicted_seq: 0,
+                outbound: None,
+                attach_id: 0,
+                subscriptions: HashMap::new(),
+                tasks: Vec::new(),
+                lost_events: false,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SessionInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number and buffer an event, and send it if a socket is attached.
+    pub fn dispatch(&self, event: &WsEvent) {
+        let mut inner = self.lock();
+        inner.seq += 1;
+        let seq = inner.seq;
+
+        let Ok(json) = serde_json::to_string(&Frame { seq, event }) else {
+            return;
+        };
+        self.send(&mut inner, json.clone(), event.is_droppable());
+
+        inner.buffer.push_back((seq, json));
+        while inner.buffer.len() > REPLAY_BUFFER_SIZE {
+            if let Some((evicted, _)) = inner.buffer.pop_front() {
+                inner.evicted_seq = evicted;
+            }
+        }
+    }
+
+    /// Send an event to the attached socket only, without a sequence number
+    /// (replies such as errors, which aren't replayed).
+    pub fn send_unsequenced(&self, event: &WsEvent) {
+        if let Ok(json) = serde_json::to_string(event) {
+            self.send(&mut self.lock(), json, event.is_droppable());
+        }
+    }
+
+    /// Queue a frame on the attached socket, applying the slow consumer
+    /// policy if its queue is full.
+    fn send(&self, inner: &mut SessionInner, json: String, droppable: bool) {
+        let Some(outbound) = &inner.outbound else {
+            return;
+        };
+        match outbound.frames.try_send(json) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                if droppable && self.policy == SlowConsumerPolicy::DropNonCritical {
+                    return;
+                }
+                self.close_slow_consumer(inner);
+            }
+        }
+    }
+
+    /// An event for the session was dropped before it could be dispatched,
+    /// because the user's event queue was full. Unless the policy lets it
+    /// be dropped, the socket is closed with `CloseCode::SlowConsumer` and
+    /// the session can no longer be resumed, as its replay would miss it.
+    pub fn dropped(&self, event: &WsEvent) {
+        if event.is_droppable() && self.policy == SlowConsumerPolicy::DropNonCritical {
+            return;
+        }
+        let mut inner = self.lock();
+        inner.lost_events = true;
+        self.close_slow_consumer(&mut inner);
+    }
+
+    fn close_slow_consumer(&self, inner: &mut SessionInner) {
+        let Some(outbound) = inner.outbound.take() else {
+            return;
+        };
+        tracing::warn!("WS slow consumer: user={} session={}", self.user_id, self.id);
+        let _ = outbound.close.try_send(CloseCode::SlowConsumer);
+    }
+
+    /// Attach a new socket's queues, returning its attach id.
+    pub fn attach(&self, outbound: Outbound) -> u64 {
+        let mut inner = self.lock();
+        inner.attach_id += 1;
+        inner.outbound = Some(outbound);
+        inner.attach_id
+    }
+
+    /// Attach a reconnecting socket: queue every event after `seq`, then
+    /// `Resumed`. Returns `None`, attaching nothing, if events after `seq`
+    /// have already left the buffer, `seq` was never sent or events were
+    /// lost.
+    ///
+    /// A socket still attached is replaced; its outbound queue closes.
+    pub fn resume(&self, outbound: Outbound, seq: u64) -> Option<u64> {
+        let mut inner = self.lock();
+        if inner.lost_events || seq < inner.evicted_seq || seq > inner.seq {
+            return None;
+        }
+
+        let resumed = serde_json::to_string(&WsEvent::Resumed { session_id: self.id }).ok()?;
+        let replay = inner.buffer.iter().filter(|(s, _)| *s > seq).map(|(_, json)| json.clone());
+        for json in replay.chain(std::iter::once(resumed)) {
+            outbound.frames.try_send(json).ok()?;
+        }
+
+        inner.attach_id += 1;
+        inner.outbound = Some(outbound);
+        Some(inner.attach_id)
+    }
+
+    /// Detach the socket with `attach_id`. Returns false if it had already
+    /// been replaced by a resumed one.
+    pub fn detach(&self, attach_id: u64) -> bool {
+        let mut inner = self.lock();
+        if inner.attach_id != attach_id {
+            return false;
+        }
+        inner.outbound = None;
+        true
+    }
+
+    /// True if nothing has attached since the socket with `attach_id` left.
+    pub fn is_idle_since(&self, attach_id: u64) -> bool {
+        let inner = self.lock();
+        inner.attach_id == attach_id && inner.outbound.is_none()
+    }
+
+    /// Start forwarding `subscription` with the task `spawn` starts, unless
+    /// it's already being forwarded. Returns false for a duplicate.
+    pub fn subscribe(&self, subscription: Subscription, spawn: impl FnOnce() -> JoinHandle<()>) -> bool {
+        let mut inner = self.lock();
+        if inner.subscriptions.get(&subscription).is_some_and(|task| !task.is_finished()) {
+            return false;
+        }
+        inner.subscriptions.insert(subscription, spawn());
+        true
+    }
+
+    /// Stop forwarding `subscription`. Returns once its task, and with it
+    /// the broadcast receiver, has been dropped; false if there was none.
+    pub async fn unsubscribe(&self, subscription: Subscription) -> bool {
+        let task = self.lock().subscriptions.remove(&subscription);
+        let Some(task) = task else {
+            return false;
+        };
+        task.abort();
+        let _ = task.await;
+        true
+    }
+
+    /// Keep `task` running for as long as the session lives.
+    pub fn add_task(&self, task: JoinHandle<()>) {
+        self.lock().tasks.push(task);
+    }
+
+    /// Stop every subscription and task; the session is done. Returns once
+    /// they've been dropped, along with their receivers, with the
+    /// subscriptions that were stopped.
+    pub async fn close(&self) -> Vec<Subscription> {
+        let (subscriptions, tasks): (Vec<Subscription>, Vec<JoinHandle<()>>) = {
+            let mut inner = self.lock();
+            inner.outbound = None;
+            let (subscriptions, tasks): (Vec<_>, Vec<_>) = inner.subscriptions.drain().unzip();
+            (subscriptions, tasks.into_iter().chain(inner.tasks.drain(..)).collect())
+        };
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+        subscriptions
+    }
+}
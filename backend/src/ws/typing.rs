@@ -0,0 +1,91 @@
+//! Typing indicators.
+//!
+//! `WsState` tracks who is typing where. A `typing_start` from a user who is
+//! already typing in the same channel or DM only extends their indicator;
+//! `TypingStart` is re-broadcast at most every `TYPING_THROTTLE`. Indicators
+//! end with `TypingStop` when the user sends `typing_stop` or a message, or
+//! after `TYPING_TIMEOUT` without another `typing_start`.
+
+use std::time::{Duration, Instant};
+
+use dashmap::mapref::entry::Entry;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use super::events::WsEvent;
+use super::WsState;
+use crate::models::MessageKind;
+
+/// How long an indicator lasts without another `typing_start`.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Minimum time between two `TypingStart` broadcasts for the same user and
+/// channel.
+pub const TYPING_THROTTLE: Duration = Duration::from_secs(5);
+
+/// A user typing in one channel or DM.
+pub(super) struct Typing {
+    kind: MessageKind,
+    last_broadcast: Instant,
+    expires_at: Instant,
+    /// Ends the indicator at `expires_at`
+    expiry: JoinHandle<()>,
+}
+
+impl WsState {
+    /// Record that the user is typing in a channel or DM. Returns true if
+    /// `TypingStart` should be broadcast, false if it was throttled.
+    pub fn typing_start(&self, user_id: Uuid, kind: MessageKind, target: Uuid) -> bool {
+        let now = Instant::now();
+        match self.inner.typing.entry((user_id, target)) {
+            Entry::Occupied(mut entry) => {
+                let typing = entry.get_mut();
+                typing.expires_at = now + TYPING_TIMEOUT;
+                if now.duration_since(typing.last_broadcast) < TYPING_THROTTLE {
+                    return false;
+                }
+                typing.last_broadcast = now;
+                true
+            }
+            Entry::Vacant(entry) => {
+                let expiry = tokio::spawn(self.clone().expire_typing(user_id, target));
+                entry.insert(Typing { kind, last_broadcast: now, expires_at: now + TYPING_TIMEOUT, expiry });
+                true
+            }
+        }
+    }
+
+    /// End the user's indicator in a channel or DM, broadcasting
+    /// `TypingStop`. Does nothing if they weren't typing there.
+    pub fn typing_stop(&self, user_id: Uuid, target: Uuid) {
+        if let Some((_, typing)) = self.inner.typing.remove(&(user_id, target)) {
+            typing.expiry.abort();
+            self.broadcast_typing_stop(user_id, typing.kind, target);
+        }
+    }
+
+    /// Wait out the indicator, however many times it gets extended.
+    async fn expire_typing(self, user_id: Uuid, target: Uuid) {
+        let key = (user_id, target);
+        loop {
+            let Some(expires_at) = self.inner.typing.get(&key).map(|t| t.expires_at) else {
+                return;
+            };
+            tokio::time::sleep_until(expires_at.into()).await;
+
+            let expired = self.inner.typing.remove_if(&key, |_, t| t.expires_at <= Instant::now());
+            if let Some((_, typing)) = expired {
+                self.broadcast_typing_stop(user_id, typing.kind, target);
+                return;
+            }
+        }
+    }
+
+    fn broadcast_typing_stop(&self, user_id: Uuid, kind: MessageKind, target: Uuid) {
+        let (channel_id, dm_channel_id) = match kind {
+            MessageKind::Channel => (Some(target), None),
+            MessageKind::Dm => (None, Some(target)),
+        };
+        self.broadcast_to_channel(&target, WsEvent::TypingStop { channel_id, dm_channel_id, user_id });
+    }
+}